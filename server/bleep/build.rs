@@ -26,10 +26,12 @@ fn set_index_version() {
     let model_files = &[
         "sqlx-data.json",
         "src/indexes/file.rs",
+        "src/indexes/commit.rs",
         "src/semantic.rs",
         "src/semantic/schema.rs",
         "src/semantic/chunk.rs",
         "src/indexes/schema.rs",
+        "src/indexes/tokenizer.rs",
         "src/intelligence/scope_resolution.rs",
         "../languages.yml",
     ];
@@ -0,0 +1,7 @@
+CREATE TABLE blame_cache (
+    repo_ref TEXT NOT NULL,
+    commit_id TEXT NOT NULL,
+    path TEXT NOT NULL,
+    blame TEXT NOT NULL,
+    PRIMARY KEY (repo_ref, commit_id, path)
+);
@@ -0,0 +1,25 @@
+CREATE TABLE saved_searches (
+    id INTEGER PRIMARY KEY,
+    project_id INTEGER NOT NULL REFERENCES projects (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    query TEXT NOT NULL,
+    webhook_url TEXT,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    last_run_at DATETIME,
+
+    -- JSON serialized matches of the last run, which the next run is compared against
+    last_matches TEXT
+);
+
+CREATE TABLE saved_search_changes (
+    id INTEGER PRIMARY KEY,
+    saved_search_id INTEGER NOT NULL REFERENCES saved_searches (id) ON DELETE CASCADE,
+
+    -- The repository whose sync triggered this run
+    repo_ref TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+
+    -- JSON serialized fields
+    added TEXT NOT NULL,
+    removed TEXT NOT NULL
+);
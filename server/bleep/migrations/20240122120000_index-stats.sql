@@ -0,0 +1,26 @@
+CREATE TABLE index_stats (
+    id INTEGER PRIMARY KEY,
+    repo_ref TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT (datetime('now')),
+    duration_ms INTEGER NOT NULL,
+
+    -- Totals of the files and directories reindexed by the sync
+    size INTEGER NOT NULL,
+    chunks INTEGER NOT NULL,
+    reindex_count INTEGER NOT NULL,
+    skipped_count INTEGER NOT NULL,
+
+    is_first_index BOOLEAN NOT NULL,
+    was_index_reset BOOLEAN NOT NULL,
+    incremental BOOLEAN NOT NULL
+);
+
+-- Files whose contents aren't indexed, which are removed along with their `file_cache` entry
+CREATE TABLE skipped_files (
+    repo_ref TEXT NOT NULL,
+    cache_hash TEXT NOT NULL,
+    relative_path TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    PRIMARY KEY (repo_ref, cache_hash)
+);
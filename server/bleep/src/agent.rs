@@ -74,6 +74,8 @@ pub struct AgentSemanticSearchParams<'a> {
     pub paths: Vec<RepoPath>,
     pub repos: Vec<RepoRef>,
     pub semantic_params: SemanticSearchParams,
+    /// Also search the content index, fusing its results with the semantic ones
+    pub hybrid: bool,
 }
 
 /// We use a `Drop` implementation to track agent query cancellation.
@@ -333,6 +335,7 @@ impl Agent {
             paths,
             repos,
             semantic_params,
+            hybrid,
         }: AgentSemanticSearchParams<'_>,
     ) -> Result<Vec<semantic::Payload>> {
         let paths_set = paths
@@ -381,8 +384,18 @@ impl Agent {
             ..self.last_exchange().query.clone()
         };
 
-        debug!(?query, %self.conversation.thread_id, "executing semantic query");
-        self.app.semantic.search(&query, semantic_params).await
+        debug!(?query, hybrid, %self.conversation.thread_id, "executing semantic query");
+        if hybrid {
+            semantic::hybrid::search(
+                &self.app.indexes,
+                &self.app.semantic,
+                &query,
+                semantic_params,
+            )
+            .await
+        } else {
+            self.app.semantic.search(&query, semantic_params).await
+        }
     }
 
     async fn get_file_content(
@@ -35,6 +35,7 @@ impl Agent {
                     threshold: 0.3,
                     exact_match: false,
                 },
+                hybrid: true,
             })
             .await?;
 
@@ -57,6 +58,7 @@ impl Agent {
                             threshold: 0.3,
                             exact_match: false,
                         },
+                        hybrid: false,
                     })
                     .await?;
 
@@ -45,6 +45,7 @@ impl Agent {
                         threshold: 0.0,
                         exact_match: false,
                     },
+                    hybrid: false,
                 })
                 .await?
                 .into_iter()
@@ -40,6 +40,7 @@ impl Agent {
                     threshold: 0.0,
                     exact_match: true,
                 },
+                hybrid: false,
             })
             .await?;
 
@@ -174,19 +174,7 @@ impl SyncHandle {
                         .source
                         .repo_path_for_name(&name.replace('/', "_"));
 
-                    Repository {
-                        disk_path,
-                        remote,
-                        shallow,
-                        sync_status: SyncStatus::Queued,
-                        pub_sync_status: SyncStatus::Queued,
-                        last_index_unix_secs: 0,
-                        last_commit_unix_secs: 0,
-                        most_common_lang: None,
-                        branch_filter: None,
-                        file_filter: Default::default(),
-                        locked: false,
-                    }
+                    Repository::remote_from(disk_path, remote, shallow)
                 }
             });
 
@@ -290,6 +278,12 @@ impl SyncHandle {
                         error!(?err, "failed to generate tutorial questions");
                     }
                 }
+
+                tokio::task::spawn(crate::saved_search::rerun_for_repo(
+                    self.app.clone(),
+                    self.reporef.clone(),
+                ));
+
                 self.set_status(|_| SyncStatus::Done)
             }
             Err(SyncError::Cancelled) => self.set_status(|_| SyncStatus::Cancelled),
@@ -496,6 +490,10 @@ impl SyncHandle {
             .await
             .map_err(SyncError::Sql)?;
 
+        indexes::analytics::delete(&self.app.sql, &self.reporef)
+            .await
+            .map_err(SyncError::Sql)?;
+
         if !self.reporef.is_local() {
             repo.remove_all()
                 .await
@@ -3,10 +3,15 @@ use bleep::{Application, Configuration, Environment};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = Configuration::cli_overriding_config_file()?;
+    let mut config = Configuration::cli_overriding_config_file()?;
 
     _ = color_eyre::install();
 
+    // CLI commands print their results to stdout, so we don't install logging for them.
+    if let Some(command) = config.command.take() {
+        return bleep::cli::run(config, command).await;
+    }
+
     Application::install_logging(&config);
     let app = Application::initialize(Environment::server(), config).await?;
 
@@ -0,0 +1,521 @@
+//! Portable index bundles, which package everything indexed for a single repository.
+//!
+//! A bundle holds a repository's file and commit documents, its `FileCache` rows, and its Qdrant
+//! points along with their embeddings. This way, a repository can be indexed once, e.g. in CI, and
+//! imported on other machines without walking or embedding any of its files again.
+//!
+//! Bundles are tied to the schema version of the bleep that built them. A bundle starts with
+//! `MAGIC`, followed by the bincode-encoded format version, a `Header`, and a stream of `Entry`
+//! values, terminated by `Entry::End`.
+
+use std::{
+    fmt, fs,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use scc::hash_map::Entry as PoolEntry;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::{ChunkRow, FileCache},
+    indexes::repo_documents,
+    repo::{
+        BranchFilterConfig, FileFilterConfig, IndexedTip, RepoRef, RepoRemote, Repository,
+        SyncStatus,
+    },
+    semantic::{Embedding, Payload},
+    state::SCHEMA_VERSION,
+    Application,
+};
+
+const MAGIC: &[u8; 8] = b"BLOOPIDX";
+
+/// Version of the layout of a bundle. Changes to what's indexed are covered by the schema version.
+const FORMAT_VERSION: u32 = 1;
+
+/// Number of points sent to Qdrant at once on import.
+const POINT_BATCH_SIZE: usize = 256;
+
+/// Repository metadata, which is restored into the repository pool on import.
+///
+/// The remote and its credentials are not included, and the repository is cloned over HTTPS on
+/// its next sync, unless it was already known to the importing installation.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    schema_version: String,
+    reporef: RepoRef,
+    indexed_tip: Option<IndexedTip>,
+    last_commit_unix_secs: i64,
+    last_index_unix_secs: u64,
+    most_common_lang: Option<String>,
+    branch_filter: Option<BranchFilterConfig>,
+    file_filter: FileFilterConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Entry {
+    /// A document of the file index as tantivy JSON, and the time of the last commit that touched
+    /// it, which isn't stored
+    File {
+        json: String,
+        last_commit: u64,
+    },
+
+    /// A document of the commit index as tantivy JSON
+    Commit {
+        json: String,
+    },
+
+    /// A row of the `file_cache` table
+    FileCache(String),
+
+    /// A row of the `chunk_cache` table
+    ChunkCache(ChunkRow),
+
+    /// A Qdrant point. The id and embedding of a `Payload` aren't serialized with it.
+    Point {
+        id: String,
+        embedding: Embedding,
+        payload: Payload,
+    },
+
+    End,
+}
+
+/// What was exported or imported.
+pub struct Summary {
+    pub reporef: RepoRef,
+    pub files: usize,
+    pub commits: usize,
+    pub points: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} files, {} commits and {} embedded chunks",
+            self.reporef, self.files, self.commits, self.points
+        )
+    }
+}
+
+/// Write everything indexed for a repository to a bundle at `path`.
+pub async fn export(app: &Application, reporef: &RepoRef, path: &Path) -> Result<Summary> {
+    if !reporef.is_remote() {
+        bail!("local repositories are indexed from their working tree, and can't be exported");
+    }
+
+    let repo = app
+        .repo_pool
+        .read_async(reporef, |_, repo| repo.clone())
+        .await
+        .with_context(|| format!("repository {reporef} was never indexed"))?;
+
+    if repo.sync_status != SyncStatus::Done {
+        bail!(
+            "repository {reporef} is not fully indexed: {:?}",
+            repo.sync_status
+        );
+    }
+
+    let file =
+        fs::File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+
+    write_header(
+        &mut out,
+        &Header {
+            schema_version: SCHEMA_VERSION.to_owned(),
+            reporef: reporef.clone(),
+            indexed_tip: repo.indexed_tip,
+            last_commit_unix_secs: repo.last_commit_unix_secs,
+            last_index_unix_secs: repo.last_index_unix_secs,
+            most_common_lang: repo.most_common_lang,
+            branch_filter: repo.branch_filter,
+            file_filter: repo.file_filter,
+        },
+    )?;
+
+    let mut summary = Summary {
+        reporef: reporef.clone(),
+        files: 0,
+        commits: 0,
+        points: 0,
+    };
+
+    let mut write = |entry: &Entry| bincode::serialize_into(&mut out, entry);
+
+    {
+        let file = &app.indexes.file;
+        let searcher = file.reader.searcher();
+        let schema = file.index.schema();
+
+        // The last commit time is only available as a fast field.
+        let last_commit_field = schema.get_field_name(file.source.last_commit_unix_seconds);
+        let last_commits = searcher
+            .segment_readers()
+            .iter()
+            .map(|segment| segment.fast_fields().u64(last_commit_field))
+            .collect::<tantivy::Result<Vec<_>>>()?;
+
+        for addr in repo_documents(&searcher, file.source.repo_ref, reporef)? {
+            let doc = searcher.doc(addr)?;
+            write(&Entry::File {
+                json: schema.to_json(&doc),
+                last_commit: last_commits[addr.segment_ord as usize]
+                    .values
+                    .get_val(addr.doc_id),
+            })?;
+            summary.files += 1;
+        }
+    }
+
+    {
+        let commit = &app.indexes.commit;
+        let searcher = commit.reader.searcher();
+        let schema = commit.index.schema();
+
+        for addr in repo_documents(&searcher, commit.source.repo_ref, reporef)? {
+            let doc = searcher.doc(addr)?;
+            write(&Entry::Commit {
+                json: schema.to_json(&doc),
+            })?;
+            summary.commits += 1;
+        }
+    }
+
+    let (files, chunks) = FileCache::new(app.sql.clone(), app.semantic.clone())
+        .rows(reporef)
+        .await?;
+    for hash in files {
+        write(&Entry::FileCache(hash))?;
+    }
+    for row in chunks {
+        write(&Entry::ChunkCache(row))?;
+    }
+
+    app.semantic
+        .for_each_point(&reporef.to_string(), true, |mut payload| {
+            let (Some(id), Some(embedding)) = (payload.id.take(), payload.embedding.take()) else {
+                bail!("point is missing its id or embedding");
+            };
+
+            write(&Entry::Point {
+                id,
+                embedding,
+                payload,
+            })?;
+            summary.points += 1;
+            Ok(())
+        })
+        .await?;
+
+    write(&Entry::End)?;
+    out.flush()?;
+
+    Ok(summary)
+}
+
+/// Install a bundle, replacing everything indexed for its repository.
+///
+/// The documents and points of the bundle are added as they are, so nothing is indexed or embedded
+/// again. Index writers are opened for this, so it fails while the server is running.
+///
+/// The whole bundle is read and parsed before anything is changed, so that a corrupt or truncated
+/// bundle leaves the index untouched. Qdrant points and cache rows are then replaced before the
+/// documents are committed. If that fails, the documents already indexed for the repository are
+/// kept, but its points may be incomplete until the import is run again.
+pub async fn import(app: &Application, path: &Path) -> Result<Summary> {
+    let file =
+        fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut input = BufReader::new(file);
+
+    let header =
+        read_header(&mut input).with_context(|| format!("failed to read {}", path.display()))?;
+    let entries = read_entries(&mut input)?;
+
+    let reporef = header.reporef;
+    let existing = app
+        .repo_pool
+        .read_async(&reporef, |_, repo| {
+            (repo.disk_path.clone(), repo.remote.clone(), repo.shallow)
+        })
+        .await;
+
+    let mut repo = match existing {
+        Some((disk_path, remote, shallow)) => Repository::remote_from(disk_path, remote, shallow),
+        None => {
+            let disk_path = app
+                .config
+                .source
+                .repo_path_for_name(&reporef.to_string().replace('/', "_"));
+            Repository::remote_from(disk_path, RepoRemote::from(&reporef), false)
+        }
+    };
+
+    repo.sync_status = SyncStatus::Done;
+    repo.pub_sync_status = SyncStatus::Done;
+    repo.indexed_tip = header.indexed_tip;
+    repo.last_commit_unix_secs = header.last_commit_unix_secs;
+    repo.last_index_unix_secs = header.last_index_unix_secs;
+    repo.most_common_lang = header.most_common_lang;
+    repo.branch_filter = header.branch_filter;
+    repo.file_filter = header.file_filter;
+
+    let file_schema = app.indexes.file.index.schema();
+    let commit_schema = app.indexes.commit.index.schema();
+    let disk_path = repo.disk_path.to_string_lossy().into_owned();
+
+    let mut file_docs = Vec::new();
+    let mut commit_docs = Vec::new();
+    let mut files = Vec::new();
+    let mut chunks = Vec::new();
+    let mut points = Vec::new();
+
+    for entry in entries {
+        match entry {
+            Entry::File { json, last_commit } => {
+                let mut doc = file_schema.parse_document(&json)?;
+                app.indexes
+                    .file
+                    .source
+                    .restore_document(&mut doc, &disk_path, last_commit);
+                file_docs.push(doc);
+            }
+            Entry::Commit { json } => {
+                let mut doc = commit_schema.parse_document(&json)?;
+                app.indexes
+                    .commit
+                    .source
+                    .restore_document(&mut doc, &disk_path);
+                commit_docs.push(doc);
+            }
+            Entry::FileCache(hash) => files.push(hash),
+            Entry::ChunkCache(row) => chunks.push(row),
+            Entry::Point {
+                id,
+                embedding,
+                mut payload,
+            } => {
+                if payload.repo_ref != reporef {
+                    bail!("the bundle holds points of another repository");
+                }
+
+                payload.id = Some(id);
+                payload.embedding = Some(embedding);
+                points.push(payload);
+            }
+            Entry::End => unreachable!("entries are read up to the end"),
+        }
+    }
+
+    let summary = Summary {
+        reporef: reporef.clone(),
+        files: file_docs.len(),
+        commits: commit_docs.len(),
+        points: points.len(),
+    };
+
+    // Uncommitted changes are discarded when the writers are dropped.
+    let open_error = "failed to open the indexes for writing, is the server running?";
+    let mut repo_writer = app.indexes.repo.write_handle().context(open_error)?;
+    let mut file_writer = app.indexes.file.write_handle().context(open_error)?;
+    let mut commit_writer = app.indexes.commit.write_handle().context(open_error)?;
+
+    for writer in [&repo_writer, &file_writer, &commit_writer] {
+        writer.delete(&repo);
+    }
+
+    app.semantic
+        .delete_repo_points(&reporef.to_string())
+        .await
+        .context("failed to delete the repository's points")?;
+
+    while !points.is_empty() {
+        let batch = points.split_off(points.len().saturating_sub(POINT_BATCH_SIZE));
+        app.semantic.insert_points(batch).await?;
+    }
+
+    FileCache::new(app.sql.clone(), app.semantic.clone())
+        .restore(&reporef, &files, &chunks)
+        .await?;
+
+    for doc in file_docs {
+        file_writer.add_document(doc)?;
+    }
+    for doc in commit_docs {
+        commit_writer.add_document(doc)?;
+    }
+    repo_writer.add_document(app.indexes.repo.source.build_document(&reporef, &repo))?;
+
+    repo_writer.commit()?;
+    file_writer.commit()?;
+    commit_writer.commit()?;
+
+    match app.repo_pool.entry_async(reporef).await {
+        PoolEntry::Occupied(mut existing) => *existing.get_mut() = repo,
+        PoolEntry::Vacant(vacant) => _ = vacant.insert_entry(repo),
+    }
+    app.config.source.save_pool(app.repo_pool.clone())?;
+
+    Ok(summary)
+}
+
+fn write_header(out: &mut impl Write, header: &Header) -> Result<()> {
+    out.write_all(MAGIC)?;
+    bincode::serialize_into(&mut *out, &FORMAT_VERSION)?;
+    bincode::serialize_into(out, header)?;
+    Ok(())
+}
+
+/// Read the header of a bundle, checking that it can be imported.
+fn read_header(input: &mut impl Read) -> Result<Header> {
+    let mut magic = [0; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("not an index bundle");
+    }
+
+    let format_version: u32 = bincode::deserialize_from(&mut *input)?;
+    if format_version != FORMAT_VERSION {
+        bail!("unsupported bundle format version {format_version}");
+    }
+
+    let header: Header = bincode::deserialize_from(input)?;
+    if header.schema_version != SCHEMA_VERSION {
+        bail!(
+            "the bundle was built by a different version of bleep, \
+             export it again with this version"
+        );
+    }
+
+    Ok(header)
+}
+
+/// Read every entry of a bundle, up to `Entry::End`, which isn't returned.
+fn read_entries(input: &mut impl Read) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    loop {
+        match bincode::deserialize_from(&mut *input).context("bundle is truncated")? {
+            Entry::End => return Ok(entries),
+            entry => entries.push(entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::Backend;
+
+    fn header(schema_version: &str) -> Header {
+        Header {
+            schema_version: schema_version.to_owned(),
+            reporef: RepoRef::new(Backend::Github, "BloopAI/bloop").unwrap(),
+            indexed_tip: Some(IndexedTip {
+                branch: "origin/main".into(),
+                is_head: true,
+                commit: "0123456789abcdef".into(),
+            }),
+            last_commit_unix_secs: 1_700_000_000,
+            last_index_unix_secs: 1_700_000_100,
+            most_common_lang: Some("rust".into()),
+            branch_filter: None,
+            file_filter: FileFilterConfig::default(),
+        }
+    }
+
+    fn entries() -> Vec<Entry> {
+        let payload = Payload {
+            lang: "rust".into(),
+            repo_name: "BloopAI/bloop".into(),
+            repo_ref: header(SCHEMA_VERSION).reporef,
+            relative_path: "src/main.rs".into(),
+            content_hash: "hash".into(),
+            text: "fn main() {}".into(),
+            start_line: 0,
+            end_line: 0,
+            start_byte: 0,
+            end_byte: 12,
+            branches: vec!["main".into()],
+            id: None,
+            embedding: None,
+            score: None,
+        };
+
+        vec![
+            Entry::File {
+                json: r#"{"relative_path":["src/main.rs"]}"#.into(),
+                last_commit: 1_700_000_000,
+            },
+            Entry::Commit {
+                json: r#"{"commit_id":["0123456789abcdef"]}"#.into(),
+            },
+            Entry::FileCache("hash".into()),
+            Entry::ChunkCache(ChunkRow {
+                chunk_hash: "chunk".into(),
+                file_hash: "hash".into(),
+                branches: "main".into(),
+            }),
+            Entry::Point {
+                id: "00000000-0000-0000-0000-000000000000".into(),
+                embedding: vec![0.5; 4],
+                payload,
+            },
+        ]
+    }
+
+    fn bundle(header: &Header, entries: &[Entry], end: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_header(&mut out, header).unwrap();
+        for entry in entries {
+            bincode::serialize_into(&mut out, entry).unwrap();
+        }
+        if end {
+            bincode::serialize_into(&mut out, &Entry::End).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = entries();
+        let bytes = bundle(&header(SCHEMA_VERSION), &entries, true);
+
+        let mut input = bytes.as_slice();
+        let header = read_header(&mut input).unwrap();
+        let read = read_entries(&mut input).unwrap();
+
+        assert_eq!(header.reporef.to_string(), "github.com/BloopAI/bloop");
+        assert_eq!(header.indexed_tip.unwrap().commit, "0123456789abcdef");
+        assert_eq!(
+            bincode::serialize(&read).unwrap(),
+            bincode::serialize(&entries).unwrap()
+        );
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn rejects_other_bundles() {
+        let bytes = bundle(&header(SCHEMA_VERSION), &entries(), true);
+
+        let mut other = bytes.clone();
+        other[0] = b'X';
+        assert!(read_header(&mut other.as_slice()).is_err());
+
+        let mut version = bytes.clone();
+        version[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&2u32.to_le_bytes());
+        assert!(read_header(&mut version.as_slice()).is_err());
+
+        let old = bundle(&header("old"), &entries(), true);
+        assert!(read_header(&mut old.as_slice()).is_err());
+
+        let truncated = bundle(&header(SCHEMA_VERSION), &entries(), false);
+        let mut input = truncated.as_slice();
+        read_header(&mut input).unwrap();
+        assert!(read_entries(&mut input).is_err());
+    }
+}
@@ -13,6 +13,7 @@ use tracing::{error, info, trace, warn};
 use uuid::Uuid;
 
 use crate::{
+    commits::BlameLine,
     repo::RepoRef,
     semantic::{
         embedder::{EmbedChunk, EmbedQueue},
@@ -127,6 +128,29 @@ impl<'a> FileCacheSnapshot<'a> {
             }
         }
     }
+
+    /// Mark every entry as fresh, so that only the entries marked stale afterwards are deleted
+    /// on synchronization.
+    ///
+    /// This is used when only the files that changed are walked.
+    pub(crate) fn keep_all(&self) {
+        self.snapshot.retain(|_, v| {
+            v.fresh = true;
+            true
+        });
+    }
+
+    /// Mark the entries matching `is_stale` as stale, so they're deleted on synchronization.
+    pub(crate) fn mark_stale(&self, is_stale: impl Fn(&CacheKeys) -> bool) {
+        self.snapshot.retain(|keys, v| {
+            if is_stale(keys) {
+                v.fresh = false;
+                trace!(?keys, "marked stale");
+            }
+
+            true
+        });
+    }
 }
 
 impl<'a> Deref for FileCacheSnapshot<'a> {
@@ -150,6 +174,14 @@ pub struct FileCache {
     embed_queue: EmbedQueue,
 }
 
+/// A row of the `chunk_cache` table, linking the Qdrant point of a chunk to its file.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct ChunkRow {
+    pub(crate) chunk_hash: String,
+    pub(crate) file_hash: String,
+    pub(crate) branches: String,
+}
+
 #[derive(Default)]
 pub struct InsertStats {
     pub new: usize,
@@ -178,6 +210,7 @@ impl<'a> FileCache {
         repo_pool.for_each(|reporef, repo| {
             refs.push(reporef.to_owned());
             repo.last_index_unix_secs = 0;
+            repo.indexed_tip = None;
         });
 
         for reporef in refs {
@@ -304,6 +337,114 @@ impl<'a> FileCache {
         let mut tx = self.db.begin().await?;
         self.delete_files(reporef, &mut tx).await?;
         self.delete_chunks(reporef, &mut tx).await?;
+        self.delete_blames(reporef, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// All `file_cache` and `chunk_cache` rows of the repository in scope.
+    pub(crate) async fn rows(
+        &self,
+        reporef: &RepoRef,
+    ) -> anyhow::Result<(Vec<String>, Vec<ChunkRow>)> {
+        let repo_str = reporef.to_string();
+        let files = sqlx::query! {
+            "SELECT cache_hash FROM file_cache \
+             WHERE repo_ref = ?",
+            repo_str,
+        }
+        .fetch_all(self.db.as_ref())
+        .await?
+        .into_iter()
+        .map(|row| row.cache_hash)
+        .collect();
+
+        let chunks = sqlx::query_as! {
+            ChunkRow,
+            "SELECT chunk_hash, file_hash, branches FROM chunk_cache \
+             WHERE repo_ref = ?",
+            repo_str,
+        }
+        .fetch_all(self.db.as_ref())
+        .await?;
+
+        Ok((files, chunks))
+    }
+
+    /// Replace all caches for the repository in scope with the given rows.
+    ///
+    /// The Qdrant points the chunks refer to must already exist.
+    pub(crate) async fn restore(
+        &self,
+        reporef: &RepoRef,
+        files: &[String],
+        chunks: &[ChunkRow],
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        self.delete_files(reporef, &mut tx).await?;
+        self.delete_chunks(reporef, &mut tx).await?;
+
+        let repo_str = reporef.to_string();
+        for hash in files {
+            sqlx::query!(
+                "INSERT INTO file_cache \
+                (repo_ref, cache_hash) \
+                VALUES (?, ?)",
+                repo_str,
+                hash,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        for chunk in chunks {
+            sqlx::query! {
+                "INSERT INTO chunk_cache (chunk_hash, file_hash, branches, repo_ref) \
+                 VALUES (?, ?, ?, ?)",
+                 chunk.chunk_hash, chunk.file_hash, chunk.branches, repo_str
+            }
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Delete individual `file_cache` and `chunk_cache` rows of the repository in scope.
+    pub(crate) async fn delete_rows(
+        &self,
+        reporef: &RepoRef,
+        cache_hashes: &[String],
+        chunk_hashes: &[String],
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        let repo_str = reporef.to_string();
+
+        for hash in cache_hashes {
+            sqlx::query! {
+                "DELETE FROM file_cache \
+                 WHERE repo_ref = ? AND cache_hash = ?",
+                repo_str,
+                hash,
+            }
+            .execute(&mut tx)
+            .await?;
+        }
+
+        for hash in chunk_hashes {
+            sqlx::query! {
+                "DELETE FROM chunk_cache \
+                 WHERE repo_ref = ? AND chunk_hash = ?",
+                repo_str,
+                hash,
+            }
+            .execute(&mut tx)
+            .await?;
+        }
+
         tx.commit().await?;
 
         Ok(())
@@ -461,7 +602,27 @@ impl<'a> FileCache {
         Ok(())
     }
 
-    /// Delete all chunks in the `chunk_cache` table for the repository in scope.
+    /// Delete all blames in the `blame_cache` table for the repository in scope.
+    ///
+    /// Blames are cached by commit, so they stay valid however branches move, and are only
+    /// deleted along with the repository.
+    async fn delete_blames(
+        &self,
+        reporef: &RepoRef,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> anyhow::Result<()> {
+        let repo_str = reporef.to_string();
+        sqlx::query! {
+            "DELETE FROM blame_cache \
+                 WHERE repo_ref = ?",
+            repo_str
+        }
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(())
+    }
+
     async fn delete_chunks(
         &self,
         reporef: &RepoRef,
@@ -491,6 +652,68 @@ impl<'a> FileCache {
     }
 }
 
+/// Cache of per-line blame for files, keyed by the commit a branch
+/// pointed to when the blame was calculated.
+///
+/// Entries stay valid however branches move, so they are only dropped
+/// when the repository itself is deleted.
+pub struct BlameCache {
+    db: SqlDb,
+}
+
+impl BlameCache {
+    pub(crate) fn new(db: SqlDb) -> Self {
+        Self { db }
+    }
+
+    pub(crate) async fn get(
+        &self,
+        reporef: &RepoRef,
+        commit_id: &str,
+        path: &str,
+    ) -> anyhow::Result<Option<Vec<BlameLine>>> {
+        let repo_str = reporef.to_string();
+        let row = sqlx::query! {
+            "SELECT blame FROM blame_cache \
+             WHERE repo_ref = ? AND commit_id = ? AND path = ?",
+            repo_str,
+            commit_id,
+            path,
+        }
+        .fetch_optional(self.db.as_ref())
+        .await?;
+
+        Ok(match row {
+            Some(row) => Some(serde_json::from_str(&row.blame)?),
+            None => None,
+        })
+    }
+
+    pub(crate) async fn insert(
+        &self,
+        reporef: &RepoRef,
+        commit_id: &str,
+        path: &str,
+        blame: &[BlameLine],
+    ) -> anyhow::Result<()> {
+        let repo_str = reporef.to_string();
+        let blame = serde_json::to_string(blame)?;
+        sqlx::query! {
+            "INSERT OR REPLACE INTO blame_cache \
+             (repo_ref, commit_id, path, blame) \
+             VALUES (?, ?, ?, ?)",
+            repo_str,
+            commit_id,
+            path,
+            blame,
+        }
+        .execute(self.db.as_ref())
+        .await?;
+
+        Ok(())
+    }
+}
+
 /// Manage both the SQL cache and the underlying qdrant database to
 /// ensure consistency.
 ///
@@ -0,0 +1,572 @@
+//! A standalone search client, which runs queries against an existing index without starting the
+//! webserver.
+//!
+//! Indexes and the database are opened read-only, so this can be used alongside a running server.
+//! The exceptions are `index import` and `fsck --repair`, which write to them.
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand, ValueEnum};
+use futures::StreamExt;
+use secrecy::ExposeSecret;
+use serde::Serialize;
+
+use crate::{
+    bundle, db, fsck,
+    indexes::{Commit, File, Indexer, Repo},
+    llm::{
+        call::{llm_call, Delta},
+        client::api,
+    },
+    minimum_parallelism,
+    query::{
+        execute::{ApiQuery, QueryResponse, QueryResult},
+        parser,
+        stopwords::remove_stopwords,
+    },
+    repo::RepoRef,
+    snippet::SnippedFile,
+    Application, Configuration, Environment,
+};
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Search the index with a query written in the bloop query language
+    Search(SearchArgs),
+
+    /// Answer a question about the indexed code with the configured LLM
+    Answer(AnswerArgs),
+
+    /// Export the index of a repository to a bundle, or import one
+    #[clap(subcommand)]
+    Index(IndexCommand),
+
+    /// Check that the indexes, caches and embeddings of repositories agree with each other
+    Fsck(FsckArgs),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum IndexCommand {
+    /// Write the documents, caches and embeddings of an indexed repository to a bundle
+    Export(ExportArgs),
+
+    /// Replace the index of a repository with a bundle. The server must not be running
+    Import(ImportArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct SearchArgs {
+    /// A query written in the bloop query language
+    pub query: String,
+
+    /// Restrict the search to the repos of a project
+    #[clap(long)]
+    pub project: Option<i64>,
+
+    /// How results are printed
+    #[clap(long, value_enum, default_value_t)]
+    pub format: OutputFormat,
+
+    /// Page of results to print
+    #[clap(long, default_value_t = 0)]
+    pub page: usize,
+
+    /// Number of results per page
+    #[clap(long, default_value_t = 100)]
+    pub page_size: usize,
+
+    /// The number of lines of context to print before each match
+    #[clap(short = 'B', long, default_value_t = 1)]
+    pub context_before: usize,
+
+    /// The number of lines of context to print after each match
+    #[clap(short = 'A', long, default_value_t = 1)]
+    pub context_after: usize,
+
+    /// Name of a configured ranking profile used to order results
+    #[clap(long)]
+    pub ranking: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct AnswerArgs {
+    /// A question about the indexed code
+    pub question: String,
+
+    /// Restrict the search to the repos of a project
+    #[clap(long)]
+    pub project: Option<i64>,
+
+    /// Query used to find code relevant to the question. Defaults to the question's keywords
+    #[clap(long)]
+    pub query: Option<String>,
+
+    /// Maximum number of files passed to the LLM
+    #[clap(long, default_value_t = 10)]
+    pub files: usize,
+
+    /// Model used to answer the question
+    #[clap(long)]
+    pub model: Option<String>,
+
+    /// Print the answer and its sources as a JSON object
+    #[clap(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ExportArgs {
+    /// The repository to export, e.g. `github.com/BloopAI/bloop`
+    pub repo: String,
+
+    /// Path of the bundle to write
+    #[clap(short, long)]
+    pub output: PathBuf,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ImportArgs {
+    /// Path of a bundle written by `index export`
+    pub bundle: PathBuf,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct FsckArgs {
+    /// Only check this repository, e.g. `github.com/BloopAI/bloop`
+    #[clap(long)]
+    pub repo: Option<String>,
+
+    /// Delete orphaned entries, and forget files with missing entries so that they're reindexed
+    /// when the server next starts. The server must not be running
+    #[clap(long, default_value_t = false)]
+    pub repair: bool,
+
+    /// Print the reports as a JSON array
+    #[clap(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Matching lines grouped by file, with line numbers
+    #[default]
+    Text,
+
+    /// The JSON response of the `/q` endpoint
+    Json,
+
+    /// One `path:line:column:text` line per match, like `rg --vimgrep`
+    #[value(alias = "rg")]
+    Ripgrep,
+}
+
+/// The tantivy indexes under `index_dir`, opened for reading only.
+struct ReadOnlyIndexes {
+    repo: Indexer<Repo>,
+    file: Indexer<File>,
+    commit: Indexer<Commit>,
+}
+
+impl ReadOnlyIndexes {
+    fn open(config: &Configuration) -> Result<Self> {
+        let threads = config.max_threads.max(minimum_parallelism());
+
+        Ok(Self {
+            repo: Indexer::open(Repo::new(), config.index_path("repo").as_ref(), threads)?,
+            file: Indexer::open(File::new(), config.index_path("content").as_ref(), threads)?,
+            commit: Indexer::open(Commit::new(), config.index_path("commit").as_ref(), threads)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct AnswerResponse<'a> {
+    answer: String,
+    sources: &'a [SnippedFile],
+}
+
+/// Run a CLI command against the index in `config.index_dir`.
+pub async fn run(mut config: Configuration, command: Command) -> Result<()> {
+    match command {
+        Command::Index(command) => return index(config, command).await,
+        Command::Fsck(args) => return check(config, args).await,
+        Command::Search(_) | Command::Answer(_) => {}
+    }
+
+    if !config.index_dir.exists() {
+        bail!("no index found in {}", config.index_dir.display());
+    }
+
+    config.source.set_default_dir(&config.index_dir);
+    if config.source.index_version_mismatch() {
+        bail!(
+            "the index in {} was built by a different version of bleep, \
+             start the server to rebuild it",
+            config.index_dir.display()
+        );
+    }
+
+    let indexes = ReadOnlyIndexes::open(&config)?;
+
+    match command {
+        Command::Search(args) => search(&config, &indexes, args).await,
+        Command::Answer(args) => answer(&config, &indexes, args).await,
+        Command::Index(_) | Command::Fsck(_) => {
+            unreachable!("index and fsck commands don't use read-only indexes")
+        }
+    }
+}
+
+/// Open all databases like the server does, for commands which need more than the indexes.
+async fn initialize(mut config: Configuration) -> Result<Application> {
+    config.source.set_default_dir(&config.index_dir);
+    if config.source.index_version_mismatch() {
+        bail!(
+            "the index in {} was built by a different version of bleep, \
+             start the server to rebuild it",
+            config.index_dir.display()
+        );
+    }
+
+    Application::initialize(Environment::server(), config).await
+}
+
+/// Export or import the index of a repository.
+async fn index(config: Configuration, command: IndexCommand) -> Result<()> {
+    let app = initialize(config).await?;
+    let mut out = io::stdout().lock();
+
+    match command {
+        IndexCommand::Export(args) => {
+            let reporef = args.repo.parse::<RepoRef>()?;
+            let summary = bundle::export(&app, &reporef, &args.output).await?;
+            writeln!(out, "exported {summary} to {}", args.output.display())?;
+        }
+        IndexCommand::Import(args) => {
+            let summary = bundle::import(&app, &args.bundle).await?;
+            writeln!(out, "imported {summary}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the consistency of indexed repositories, and optionally repair them.
+async fn check(config: Configuration, args: FsckArgs) -> Result<()> {
+    let app = initialize(config).await?;
+    let reporef = args.repo.map(|r| r.parse::<RepoRef>()).transpose()?;
+
+    let reports = fsck::run(&app, reporef, args.repair).await?;
+
+    let mut out = io::stdout().lock();
+    if args.json {
+        serde_json::to_writer_pretty(&mut out, &reports)?;
+        writeln!(out)?;
+    } else {
+        for report in &reports {
+            writeln!(out, "{report}")?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn search(config: &Configuration, indexes: &ReadOnlyIndexes, args: SearchArgs) -> Result<()> {
+    let api_query = ApiQuery {
+        q: args.query,
+        project_id: args.project.unwrap_or_default(),
+        page: args.page,
+        page_size: args.page_size,
+        calculate_totals: args.format == OutputFormat::Json,
+        context_before: args.context_before,
+        context_after: args.context_after,
+        ranking_profile: config.ranking_profile(args.ranking.as_deref())?,
+        ranking: args.ranking,
+    };
+
+    let response = execute(config, indexes, &api_query, args.project).await?;
+    let mut out = io::stdout().lock();
+
+    match args.format {
+        OutputFormat::Text => print_text(&mut out, &response)?,
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut out, &response)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Ripgrep => print_ripgrep(&mut out, &response)?,
+    }
+
+    Ok(())
+}
+
+async fn answer(config: &Configuration, indexes: &ReadOnlyIndexes, args: AnswerArgs) -> Result<()> {
+    let api_query = ApiQuery {
+        q: args.query.unwrap_or_else(|| keyword_query(&args.question)),
+        project_id: args.project.unwrap_or_default(),
+        page: 0,
+        page_size: args.files,
+        calculate_totals: false,
+        context_before: 5,
+        context_after: 5,
+        ranking: None,
+        ranking_profile: config.ranking_profile(None)?,
+    };
+
+    let files = execute(config, indexes, &api_query, args.project)
+        .await?
+        .data
+        .into_iter()
+        .filter_map(|result| match result {
+            QueryResult::Snippets(file) => Some(file),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    if files.is_empty() {
+        bail!("no code matched `{}`", api_query.q);
+    }
+
+    let api_key = config
+        .openai_api_key
+        .as_ref()
+        .map(|key| key.expose_secret().clone());
+
+    if api_key.is_none() && config.llm.requires_key() {
+        bail!("no API key configured for the LLM provider, set `--openai-api-key`");
+    }
+
+    let request = api::LLMRequest {
+        api_key,
+        messages: api::Messages {
+            messages: vec![
+                api::Message::system(&answer_prompt(&files)),
+                api::Message::user(&args.question),
+            ],
+        },
+        functions: None,
+        max_tokens: None,
+        temperature: Some(0.0),
+        presence_penalty: None,
+        frequency_penalty: None,
+        model: args.model,
+        extra_stop_sequences: vec![],
+    };
+
+    let stream = llm_call(&config.llm, request).await?;
+    tokio::pin!(stream);
+
+    let mut out = io::stdout().lock();
+    let mut answer = String::new();
+
+    while let Some(delta) = stream.next().await {
+        if let Delta::Content(Some(text)) = delta? {
+            if !args.json {
+                write!(out, "{text}")?;
+                out.flush()?;
+            }
+
+            answer += &text;
+        }
+    }
+
+    if args.json {
+        let response = AnswerResponse {
+            answer,
+            sources: &files,
+        };
+
+        serde_json::to_writer(&mut out, &response)?;
+        writeln!(out)?;
+    } else {
+        writeln!(out, "\n\nSources:")?;
+        for file in &files {
+            writeln!(
+                out,
+                "  {}",
+                file_path(&file.repo_ref, &file.repo_name, &file.relative_path)
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn execute(
+    config: &Configuration,
+    indexes: &ReadOnlyIndexes,
+    api_query: &ApiQuery,
+    project: Option<i64>,
+) -> Result<QueryResponse> {
+    let queries = parser::parse(&api_query.q)?;
+    let queries = match project {
+        Some(_) => {
+            let sql = db::open_read_only(config).await?.into();
+            api_query.restrict_queries(queries, &sql).await?
+        }
+        None => queries,
+    };
+
+    api_query
+        .query_indexers(&indexes.repo, &indexes.file, &indexes.commit, queries)
+        .await
+        .context("search failed")
+}
+
+/// Build a query matching any of the keywords in a natural language question.
+fn keyword_query(question: &str) -> String {
+    let keywords = match remove_stopwords(question) {
+        keywords if keywords.is_empty() => question.to_owned(),
+        keywords => keywords,
+    };
+
+    keywords
+        .split_whitespace()
+        .map(|k| k.replace(['"', '\\'], ""))
+        .filter(|k| !k.is_empty())
+        .map(|k| format!("\"{k}\""))
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+fn answer_prompt(files: &[SnippedFile]) -> String {
+    let mut prompt = "You answer questions about a codebase. Use only the code below to answer, \
+        and mention the file paths of the code you refer to. If the code is not enough to \
+        answer the question, say so.\n"
+        .to_owned();
+
+    for file in files {
+        for snippet in &file.snippets {
+            prompt += &format!(
+                "\n{}/{}:{}-{}\n```\n{}\n```\n",
+                file.repo_name,
+                file.relative_path,
+                snippet.line_range.start + 1,
+                snippet.line_range.end + 1,
+                snippet.data.trim_end(),
+            );
+        }
+    }
+
+    prompt
+}
+
+/// The path of a result on disk if the repo is local, or prefixed with the repo name otherwise.
+fn file_path(repo_ref: &str, repo_name: &str, relative_path: &str) -> String {
+    match repo_ref
+        .parse::<RepoRef>()
+        .ok()
+        .and_then(|r| r.local_path())
+    {
+        Some(root) => root.join(relative_path).display().to_string(),
+        None => format!("{repo_name}/{relative_path}"),
+    }
+}
+
+fn print_text(out: &mut impl Write, response: &QueryResponse) -> io::Result<()> {
+    for result in &response.data {
+        match result {
+            QueryResult::Snippets(file) => {
+                let path = file_path(&file.repo_ref, &file.repo_name, &file.relative_path);
+                writeln!(out, "{path}")?;
+
+                for (i, snippet) in file.snippets.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(out, "--")?;
+                    }
+
+                    let matched = snippet.highlight_positions();
+                    for (offset, line) in snippet.data.lines().enumerate() {
+                        let line_number = snippet.line_range.start + offset + 1;
+                        let separator = if matched.iter().any(|(l, _)| *l == line_number) {
+                            ':'
+                        } else {
+                            '-'
+                        };
+
+                        writeln!(out, "{line_number}{separator}{line}")?;
+                    }
+                }
+
+                writeln!(out)?;
+            }
+            QueryResult::FileResult(file) => {
+                let path = file_path(
+                    &file.repo_ref.to_string(),
+                    &file.repo_name,
+                    &file.relative_path.text,
+                );
+                writeln!(out, "{path}")?;
+            }
+            QueryResult::RepositoryResult(repo) => writeln!(out, "{}", repo.name.text)?,
+            QueryResult::File(file) => writeln!(out, "{}/{}", file.repo_name, file.relative_path)?,
+            QueryResult::Directory(dir) => {
+                writeln!(out, "{}/{}", dir.repo_name, dir.relative_path)?
+            }
+            QueryResult::Commit(commit) => {
+                let date = chrono::NaiveDateTime::from_timestamp_opt(commit.timestamp as i64, 0)
+                    .map(|date| date.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+                let summary = commit.message.text.lines().next().unwrap_or_default();
+
+                let short_id = &commit.commit_id[..commit.commit_id.len().min(10)];
+
+                writeln!(
+                    out,
+                    "{} {short_id} {date} {} {summary}",
+                    commit.repo_name, commit.author.text,
+                )?;
+            }
+            QueryResult::Flag(text) | QueryResult::Lang(text) => writeln!(out, "{text}")?,
+        }
+    }
+
+    Ok(())
+}
+
+fn print_ripgrep(out: &mut impl Write, response: &QueryResponse) -> io::Result<()> {
+    for result in &response.data {
+        match result {
+            QueryResult::Snippets(file) => {
+                let path = file_path(&file.repo_ref, &file.repo_name, &file.relative_path);
+
+                for snippet in &file.snippets {
+                    for (line, column) in snippet.highlight_positions() {
+                        let text = snippet.line(line).unwrap_or_default();
+
+                        writeln!(out, "{path}:{line}:{column}:{text}")?;
+                    }
+                }
+            }
+            // Non-content results have no position, so only the path is printed, like `rg -l`.
+            QueryResult::FileResult(file) => {
+                let path = file_path(
+                    &file.repo_ref.to_string(),
+                    &file.repo_name,
+                    &file.relative_path.text,
+                );
+                writeln!(out, "{path}")?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keywords() {
+        assert_eq!(
+            keyword_query("where is the query parser?"),
+            "\"query\" or \"parser\""
+        );
+        assert_eq!(keyword_query("the \"a\""), "\"the\" or \"a\"");
+    }
+}
@@ -1,4 +1,8 @@
-use std::collections::HashSet;
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    ops::Range,
+    path::Path,
+};
 
 use anyhow::{bail, Context, Result};
 use gix::{
@@ -8,12 +12,12 @@ use gix::{
     objs::tree::EntryMode,
     Commit, Id,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, trace};
 
 use crate::{
     llm::{self, client::api::Message},
-    repo::iterator::EXT_BLACKLIST,
+    repo::iterator::{branch_tips, BranchFilter, EXT_BLACKLIST, MAX_FILE_LEN},
     repo::RepoRef,
     state::RepositoryPool,
 };
@@ -177,30 +181,520 @@ fn add_diff(
     new: std::borrow::Cow<'_, str>,
     stats: &mut DiffStat,
 ) {
-    let input = gix::diff::blob::intern::InternedInput::new(old.as_ref(), new.as_ref());
-    stats.diff += &format!(
-        r#"diff --git a/{location} b/{location}
+    let diff = unified_diff(location, &old, &new);
+
+    if let Some(ext) = extension {
+        if !COMMIT_EXCLUDE_EXTENSIONS.contains(ext) {
+            // Confusingly these are inverted
+            stats.num_line_insertions += &diff.removals;
+            stats.num_line_deletions += &diff.insertions;
+        }
+    }
+
+    stats.diff += diff.wrapped.as_str();
+    stats.diff += "\n";
+}
+
+/// Render a git-style unified diff of a single file, along with the number of changed lines.
+fn unified_diff(location: &str, old: &str, new: &str) -> Counter<String> {
+    let input = gix::diff::blob::intern::InternedInput::new(old, new);
+    let mut diff = gix::diff::blob::diff(
+        Algorithm::Histogram,
+        &input,
+        Counter::new(UnifiedDiffBuilder::new(&input)),
+    );
+
+    diff.wrapped.insert_str(
+        0,
+        &format!(
+            r#"diff --git a/{location} b/{location}
 --- a/{location}
 +++ b/{location}
 "#
+        ),
     );
 
-    let diff = gix::diff::blob::diff(
+    diff
+}
+
+/// Upper bound on the size of the diff we keep for a single commit in the history index.
+const MAX_HISTORY_DIFF_LEN: usize = 64 * 1024;
+
+/// A single commit, as recorded in the commit history index.
+pub(crate) struct HistoryEntry {
+    pub id: gix::ObjectId,
+    pub author: String,
+    pub timestamp: u64,
+    pub message: String,
+    pub paths: Vec<String>,
+    pub diff: String,
+}
+
+/// The outcome of `walk_history`.
+pub(crate) struct HistoryWalk {
+    /// Every commit reachable from the walked branches.
+    pub reachable: HashSet<gix::ObjectId>,
+    /// The number of commits passed to the callback.
+    pub visited: usize,
+}
+
+/// Walk the history of every branch that passes `branch_filter`, newest commits first.
+///
+/// Commits for which `is_known` returns true aren't passed to `callback`, but their ancestors
+/// are, so that repeated walks pick up older history which an earlier walk didn't reach. At
+/// most `limit` commits are passed to `callback`, after which only the commit graph is walked.
+///
+/// The walk stops early if the callback returns `false`, in which case `reachable` is
+/// incomplete.
+pub(crate) fn walk_history(
+    reporef: &RepoRef,
+    disk_path: &Path,
+    branch_filter: &BranchFilter,
+    limit: usize,
+    mut is_known: impl FnMut(&gix::oid) -> bool,
+    mut callback: impl FnMut(HistoryEntry) -> bool,
+) -> Result<HistoryWalk> {
+    let git = open_repository(disk_path)?;
+
+    let commit_time = |id: gix::ObjectId| -> Result<i64> {
+        Ok(git.find_object(id)?.try_into_commit()?.time()?.seconds)
+    };
+
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::new();
+    for (_, _, id) in branch_tips(reporef, &git, branch_filter)? {
+        if seen.insert(id) {
+            queue.push((commit_time(id)?, id));
+        }
+    }
+
+    let mut visited = 0;
+    while let Some((_, id)) = queue.pop() {
+        let commit = git.find_object(id)?.try_into_commit()?;
+        let parents = commit
+            .parent_ids()
+            .map(|id| id.detach())
+            .collect::<Vec<_>>();
+
+        for &parent in &parents {
+            // Shallow clones don't have the parents of their oldest commits.
+            if seen.insert(parent) {
+                if let Ok(time) = commit_time(parent) {
+                    queue.push((time, parent));
+                }
+            }
+        }
+
+        if visited >= limit || is_known(&id) {
+            continue;
+        }
+
+        let old_tree = match parents.first().and_then(|&id| git.find_object(id).ok()) {
+            Some(parent) => parent.try_into_commit()?.tree()?,
+            None => git.empty_tree(),
+        };
+
+        let (paths, diff) = history_diff(&old_tree, &commit.tree()?);
+        let author = commit.author()?;
+
+        visited += 1;
+        let entry = HistoryEntry {
+            id,
+            author: format!("{} <{}>", author.name, author.email),
+            timestamp: author.time.seconds.max(0) as u64,
+            message: commit.message_raw()?.to_str_lossy().into_owned(),
+            paths,
+            diff,
+        };
+
+        if !callback(entry) {
+            break;
+        }
+    }
+
+    Ok(HistoryWalk {
+        reachable: seen,
+        visited,
+    })
+}
+
+/// Diff two trees, returning the touched paths and a unified diff of the changed files.
+///
+/// Files with blacklisted extensions, binary files and very large files are listed in the
+/// paths, but left out of the diff.
+fn history_diff(old: &gix::Tree<'_>, new: &gix::Tree<'_>) -> (Vec<String>, String) {
+    use gix::object::tree::diff::change::Event;
+
+    let mut paths = vec![];
+    let mut diff = String::new();
+
+    let blob_text = |id: Option<Id<'_>>| -> Option<String> {
+        let Some(id) = id else {
+            return Some(String::new());
+        };
+
+        let blob = id.object().ok()?;
+        if blob.data.len() as u64 > MAX_FILE_LEN || blob.data.contains(&0) {
+            return None;
+        }
+
+        Some(blob.data.to_str_lossy().into_owned())
+    };
+
+    let Ok(mut changes) = old.changes() else {
+        return (paths, diff);
+    };
+
+    _ = changes.track_path().for_each_to_obtain_tree(new, |change| {
+        let (entry_mode, old_id, new_id) = match change.event {
+            Event::Addition { entry_mode, id } => (entry_mode, None, Some(id)),
+            Event::Deletion { entry_mode, id } => (entry_mode, Some(id), None),
+            Event::Modification {
+                entry_mode,
+                previous_id,
+                id,
+                ..
+            } => (entry_mode, Some(previous_id), Some(id)),
+            Event::Rewrite {
+                entry_mode,
+                source_id,
+                id,
+                ..
+            } => (entry_mode, Some(source_id), Some(id)),
+        };
+
+        if entry_mode.is_tree() {
+            return Ok::<Action, NoneError>(Action::Continue);
+        }
+
+        let location = change.location.to_str_lossy();
+        paths.push(location.to_string());
+
+        let ext = change
+            .location
+            .to_path_lossy()
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_string());
+
+        let excluded = ext
+            .as_deref()
+            .map(|ext| EXT_BLACKLIST.contains(&ext))
+            .unwrap_or_default();
+
+        if excluded || !entry_mode.is_blob() || diff.len() >= MAX_HISTORY_DIFF_LEN {
+            return Ok(Action::Continue);
+        }
+
+        if let (Some(old), Some(new)) = (blob_text(old_id), blob_text(new_id)) {
+            diff += unified_diff(&location, &old, &new).wrapped.as_str();
+            diff += "\n";
+        }
+
+        Ok(Action::Continue)
+    });
+
+    if diff.len() > MAX_HISTORY_DIFF_LEN {
+        let mut end = MAX_HISTORY_DIFF_LEN;
+        while !diff.is_char_boundary(end) {
+            end -= 1;
+        }
+        diff.truncate(end);
+    }
+
+    (paths, diff)
+}
+
+/// Maximum number of commits we walk back through when blaming a file.
+///
+/// Lines older than this are attributed to the last commit visited, like a boundary commit in
+/// `git blame`.
+const MAX_BLAME_COMMITS: usize = 10_000;
+
+/// Find the time of the last commit that touched each file and directory on the branches that
+/// pass `branch_filter`, keyed by their relative path.
+///
+/// History is walked newest first, until every entry of the branches has been found. If `since`
+/// is given, the walk stops there instead, and only the entries that changed after it are found.
+pub(crate) fn last_commit_times(
+    reporef: &RepoRef,
+    disk_path: &Path,
+    branch_filter: &BranchFilter,
+    since: Option<&str>,
+) -> Result<HashMap<String, i64>> {
+    let git = open_repository(disk_path)?;
+    let since = since
+        .map(|id| gix::ObjectId::from_hex(id.as_bytes()))
+        .transpose()?;
+
+    let commit_time = |id: gix::ObjectId| -> Result<i64> {
+        Ok(git.find_object(id)?.try_into_commit()?.time()?.seconds)
+    };
+
+    let mut remaining = since.is_none().then(HashSet::new);
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::new();
+    for (_, _, id) in branch_tips(reporef, &git, branch_filter)? {
+        if let Some(ref mut remaining) = remaining {
+            let tree = git.find_object(id)?.peel_to_tree()?;
+            for entry in tree.traverse().breadthfirst.files()? {
+                remaining.insert(entry.filepath.to_str_lossy().into_owned());
+            }
+        }
+
+        if seen.insert(id) && Some(id) != since {
+            queue.push((commit_time(id)?, id));
+        }
+    }
+
+    let mut times = HashMap::new();
+    while let Some((time, id)) = queue.pop() {
+        if remaining.as_ref().is_some_and(HashSet::is_empty) {
+            break;
+        }
+
+        let commit = git.find_object(id)?.try_into_commit()?;
+        let parents = commit
+            .parent_ids()
+            .map(|id| id.detach())
+            .collect::<Vec<_>>();
+
+        for &parent in &parents {
+            // Shallow clones don't have the parents of their oldest commits.
+            if seen.insert(parent) && Some(parent) != since {
+                if let Ok(time) = commit_time(parent) {
+                    queue.push((time, parent));
+                }
+            }
+        }
+
+        let old_tree = match parents.first().and_then(|&id| git.find_object(id).ok()) {
+            Some(parent) => parent.try_into_commit()?.tree()?,
+            None => git.empty_tree(),
+        };
+
+        // A change to a file touches the directories containing it too.
+        for location in changed_paths(&old_tree, &commit.tree()?) {
+            let mut path = location.as_str();
+            loop {
+                if remaining.as_mut().map_or(true, |r| r.remove(path)) {
+                    times.entry(path.to_owned()).or_insert(time);
+                }
+
+                match path.rfind('/') {
+                    Some(end) => path = &path[..end],
+                    None => break,
+                }
+            }
+        }
+    }
+
+    Ok(times)
+}
+
+/// The paths of the entries that differ between two trees.
+fn changed_paths(old: &gix::Tree<'_>, new: &gix::Tree<'_>) -> Vec<String> {
+    let mut paths = vec![];
+
+    let Ok(mut changes) = old.changes() else {
+        return paths;
+    };
+
+    _ = changes.track_path().for_each_to_obtain_tree(new, |change| {
+        use gix::object::tree::diff::change::Event;
+
+        if let Event::Rewrite {
+            source_location, ..
+        } = change.event
+        {
+            paths.push(source_location.to_str_lossy().into_owned());
+        }
+
+        paths.push(change.location.to_str_lossy().into_owned());
+        Ok::<Action, NoneError>(Action::Continue)
+    });
+
+    paths
+}
+
+/// The commit that last changed a single line of a file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlameLine {
+    pub commit_id: String,
+    pub author: String,
+    /// Author timestamp, in seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// Find the commit a branch points to, if the branch passes `branch_filter`.
+///
+/// Passing no branch, or `HEAD`, resolves the branch that is currently checked out.
+pub(crate) fn branch_commit(
+    reporef: &RepoRef,
+    disk_path: &Path,
+    branch_filter: &BranchFilter,
+    branch: Option<&str>,
+) -> Result<Option<gix::ObjectId>> {
+    let git = open_repository(disk_path)?;
+    let commit = branch_tips(reporef, &git, branch_filter)?
+        .into_iter()
+        .find(|(is_head, name, _)| match branch {
+            None | Some("HEAD") => *is_head,
+            Some(branch) => name == branch,
+        })
+        .map(|(_, _, id)| id);
+
+    Ok(commit)
+}
+
+/// Attribute every line of the file at `path`, as of commit `tip`, to the commit that last
+/// changed it.
+///
+/// History is followed along first parents only, and renames are not tracked, so the lines of a
+/// renamed file are attributed to the commit that renamed it.
+///
+/// Returns `None` if the file doesn't exist in `tip`.
+pub(crate) fn blame(
+    disk_path: &Path,
+    tip: gix::ObjectId,
+    path: &str,
+) -> Result<Option<Vec<BlameLine>>> {
+    let git = open_repository(disk_path)?;
+    let mut buf = vec![];
+    let mut blob_at = |commit: &Commit<'_>| -> Result<Option<gix::ObjectId>> {
+        Ok(commit
+            .tree()?
+            .lookup_entry_by_path(path, &mut buf)?
+            .filter(|entry| entry.mode().is_blob())
+            .map(|entry| entry.object_id()))
+    };
+
+    let text = |id: gix::ObjectId| -> Result<String> {
+        Ok(git.find_object(id)?.data.to_str_lossy().into_owned())
+    };
+
+    let mut commit = git.find_object(tip)?.try_into_commit()?;
+    let Some(mut blob_id) = blob_at(&commit)? else {
+        return Ok(None);
+    };
+
+    let mut content = text(blob_id)?;
+    let line_count = content.lines().count();
+    let mut lines = vec![None; line_count];
+
+    // Lines that have not been attributed yet, as pairs of the line number in the blamed file and
+    // the line number in the revision of the file we're currently looking at.
+    let mut pending = (0..line_count)
+        .map(|line| (line, line as u32))
+        .collect::<Vec<_>>();
+
+    let mut visited = 0;
+    while !pending.is_empty() {
+        visited += 1;
+
+        // Shallow clones don't have the parents of their oldest commits, so these are treated
+        // like root commits.
+        let parent = match commit.parent_ids().next() {
+            Some(id) if visited < MAX_BLAME_COMMITS => match id.object() {
+                Ok(parent) => Some(parent.try_into_commit()?),
+                Err(_) => None,
+            },
+            _ => None,
+        };
+
+        let parent_blob = match &parent {
+            Some(parent) => blob_at(parent)?,
+            None => None,
+        };
+
+        match parent_blob {
+            // The file didn't change in this commit, so every line carries over to the parent.
+            Some(id) if id == blob_id => {}
+            Some(id) => {
+                let parent_content = text(id)?;
+                let (changed, carried) = trace_lines(&parent_content, &content, &pending);
+
+                let line = blame_line(&commit)?;
+                for changed in changed {
+                    lines[changed] = Some(line.clone());
+                }
+
+                pending = carried;
+                blob_id = id;
+                content = parent_content;
+            }
+            // The file was added in this commit, or we have reached the end of the walk.
+            None => {
+                let line = blame_line(&commit)?;
+                for (changed, _) in pending.drain(..) {
+                    lines[changed] = Some(line.clone());
+                }
+            }
+        }
+
+        match parent {
+            Some(parent) => commit = parent,
+            None => break,
+        }
+    }
+
+    Ok(Some(lines.into_iter().flatten().collect()))
+}
+
+fn blame_line(commit: &Commit<'_>) -> Result<BlameLine> {
+    let author = commit.author()?;
+
+    Ok(BlameLine {
+        commit_id: commit.id.to_string(),
+        author: format!("{} <{}>", author.name, author.email),
+        timestamp: author.time.seconds.max(0) as u64,
+    })
+}
+
+/// Split the `pending` lines of `new` into the ones that were changed relative to `old`, and the
+/// ones carried over unchanged, with their line numbers mapped into `old`.
+///
+/// `pending` must be sorted by line number in `new`.
+fn trace_lines(old: &str, new: &str, pending: &[(usize, u32)]) -> (Vec<usize>, Vec<(usize, u32)>) {
+    let input = gix::diff::blob::intern::InternedInput::new(old, new);
+
+    let mut hunks = vec![];
+    gix::diff::blob::diff(
         Algorithm::Histogram,
         &input,
-        Counter::new(UnifiedDiffBuilder::new(&input)),
+        |before: Range<u32>, after: Range<u32>| hunks.push((before, after)),
     );
 
-    if let Some(ext) = extension {
-        if !COMMIT_EXCLUDE_EXTENSIONS.contains(ext) {
-            // Confusingly these are inverted
-            stats.num_line_insertions += &diff.removals;
-            stats.num_line_deletions += &diff.insertions;
+    let mut changed = vec![];
+    let mut carried = vec![];
+
+    // The difference between line numbers in `old` and `new`, after the hunks we've passed.
+    let mut offset = 0i64;
+    let mut hunks = hunks.into_iter().peekable();
+
+    for &(line, current) in pending {
+        while let Some((before, after)) = hunks.peek() {
+            if after.end > current {
+                break;
+            }
+
+            offset = before.end as i64 - after.end as i64;
+            hunks.next();
+        }
+
+        match hunks.peek() {
+            Some((_, after)) if after.start <= current => changed.push(line),
+            _ => carried.push((line, (current as i64 + offset) as u32)),
         }
     }
 
-    stats.diff += diff.wrapped.as_str();
-    stats.diff += "\n";
+    (changed, carried)
+}
+
+fn open_repository(disk_path: &Path) -> Result<gix::Repository> {
+    Ok(gix::open::Options::isolated()
+        .filter_config_section(|_| false)
+        .open(disk_path)?
+        .to_thread_local())
 }
 
 pub async fn expand_commits_to_questions(
@@ -491,3 +985,154 @@ pub async fn generate_tutorial_questions(
     debug!(%reporef, "questions committed");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::iterator::test_utils::TestRepo;
+
+    #[test]
+    fn trace_changed_lines() {
+        let old = "a\nb\nc\n";
+        let new = "a\nx\nb\nc\nd\n";
+        let pending = (0..5).map(|line| (line, line as u32)).collect::<Vec<_>>();
+
+        let (changed, carried) = trace_lines(old, new, &pending);
+        assert_eq!(changed, [1, 4]);
+        assert_eq!(carried, [(0, 0), (2, 1), (3, 2)]);
+
+        // Lines that were attributed earlier are skipped entirely.
+        let (changed, carried) = trace_lines("b\n", "a\nb\n", &[(7, 1)]);
+        assert!(changed.is_empty());
+        assert_eq!(carried, [(7, 0)]);
+    }
+
+    #[test]
+    fn last_commit_of_each_file() {
+        let repo = TestRepo::new();
+        repo.write("a.rs", "fn a() {}\n");
+        repo.write("dir/b.rs", "fn b() {}\n");
+        repo.commit(1_700_000_000);
+
+        repo.write("dir/b.rs", "fn b() -> u8 { 0 }\n");
+        let since = repo.commit(1_700_000_100);
+
+        repo.write("c.rs", "fn c() {}\n");
+        repo.commit(1_700_000_200);
+
+        let times = |since: Option<&str>| {
+            last_commit_times(&repo.reporef, repo.path(), &BranchFilter::Head, since).unwrap()
+        };
+
+        let all = times(None);
+        assert_eq!(all.len(), 4);
+        assert_eq!(all["a.rs"], 1_700_000_000);
+        assert_eq!(all["dir/b.rs"], 1_700_000_100);
+        assert_eq!(all["dir"], 1_700_000_100);
+        assert_eq!(all["c.rs"], 1_700_000_200);
+
+        let changed = times(Some(since.as_str()));
+        assert_eq!(changed, HashMap::from([("c.rs".to_owned(), 1_700_000_200)]));
+    }
+
+    #[test]
+    fn blame_past_shallow_boundary() {
+        let repo = TestRepo::new();
+        let ids = ["a\nb\nc\n", "a\nx\nc\n", "y\nx\nc\n"]
+            .into_iter()
+            .zip(1_700_000_000..)
+            .map(|(contents, time)| {
+                repo.write("a.rs", contents);
+                repo.commit(time)
+            })
+            .collect::<Vec<_>>();
+
+        let clone = repo.shallow_clone(2);
+        let tip = gix::ObjectId::from_hex(ids[2].as_bytes()).unwrap();
+        let commits = blame(clone.path(), tip, "a.rs")
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|line| line.commit_id)
+            .collect::<Vec<_>>();
+
+        // Lines older than the boundary commit are attributed to it.
+        assert_eq!(commits, [ids[2].as_str(), ids[1].as_str(), ids[1].as_str()]);
+    }
+
+    #[test]
+    fn walk_history_past_known_commits() {
+        let repo = TestRepo::new();
+        let ids = (0..3)
+            .map(|i| {
+                repo.write("a.rs", &format!("fn a() -> u8 {{ {i} }}\n"));
+                gix::ObjectId::from_hex(repo.commit(1_700_000_000 + i).as_bytes()).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let walk = |known: &HashSet<gix::ObjectId>| {
+            let mut entries = vec![];
+            let walk = walk_history(
+                &repo.reporef,
+                repo.path(),
+                &BranchFilter::Head,
+                1,
+                |id| known.contains(id),
+                |entry| {
+                    entries.push(entry.id);
+                    true
+                },
+            )
+            .unwrap();
+
+            assert_eq!(walk.visited, entries.len());
+            (walk.reachable, entries)
+        };
+
+        // Only the newest commit fits in the limit, but the whole graph is reachable.
+        let (reachable, entries) = walk(&HashSet::new());
+        assert_eq!(entries, [ids[2]]);
+        assert_eq!(reachable, ids.iter().copied().collect());
+
+        // Known commits don't hide their ancestors.
+        let (_, entries) = walk(&HashSet::from([ids[2]]));
+        assert_eq!(entries, [ids[1]]);
+
+        // Commits dropped from the branch are no longer reachable.
+        repo.git(&["reset", "--quiet", "--hard", "HEAD~1"]);
+        repo.git(&["update-ref", "refs/remotes/origin/main", "HEAD"]);
+
+        let (reachable, _) = walk(&HashSet::from([ids[0], ids[1]]));
+        assert_eq!(reachable, ids[..2].iter().copied().collect());
+    }
+
+    #[test]
+    fn walk_history_of_shallow_clone() {
+        let repo = TestRepo::new();
+        for i in 0..3 {
+            repo.write("a.rs", &format!("fn a() -> u8 {{ {i} }}\n"));
+            repo.commit(1_700_000_000 + i);
+        }
+
+        let clone = repo.shallow_clone(2);
+        let mut entries = vec![];
+        let walk = walk_history(
+            &clone.reporef,
+            clone.path(),
+            &BranchFilter::Head,
+            10,
+            |_| false,
+            |entry| {
+                entries.push(entry);
+                true
+            },
+        )
+        .unwrap();
+
+        // The boundary commit is diffed against the empty tree, as its parent is missing.
+        assert_eq!(walk.visited, 2);
+        assert_eq!(entries[1].paths, ["a.rs"]);
+        assert!(entries[1].diff.contains("+fn a() -> u8 { 1 }"));
+        assert!(!entries[1].diff.contains("-fn a() -> u8 { 0 }"));
+    }
+}
@@ -1,10 +1,13 @@
-use crate::state::StateSource;
+use crate::{
+    cli::Command, llm::provider::LlmProvider, query::ranking::RankingProfile, state::StateSource,
+};
 use anyhow::{Context, Result};
 use clap::Parser;
 
 use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize, Serializer};
 use std::{
+    collections::HashMap,
     num::NonZeroUsize,
     path::{Path, PathBuf},
 };
@@ -20,6 +23,11 @@ pub struct Configuration {
     /// If a config file is given, it will override _all_ command line parameters!
     pub config_file: Option<PathBuf>,
 
+    #[clap(subcommand)]
+    #[serde(skip)]
+    /// Run a command against an existing index instead of starting the server
+    pub command: Option<Command>,
+
     #[clap(flatten)]
     #[serde(default)]
     pub source: StateSource,
@@ -81,11 +89,41 @@ pub struct Configuration {
     /// OpenAI API key
     pub openai_api_key: Option<SecretString>,
 
+    #[clap(flatten)]
+    #[serde(default)]
+    /// Chat completions backend used by the LLM client
+    pub llm: LlmProvider,
+
     #[clap(long)]
     #[serde(serialize_with = "serialize_secret_opt_str", default)]
     /// Github Access Token
     pub github_access_token: Option<SecretString>,
 
+    #[clap(long)]
+    /// Base URL of a GitLab instance, e.g. `https://gitlab.com`
+    pub gitlab_url: Option<String>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// GitLab Access Token, used with `--gitlab-url`
+    pub gitlab_access_token: Option<SecretString>,
+
+    #[clap(long)]
+    /// Base URL of a Gitea or Forgejo instance, e.g. `https://codeberg.org`
+    pub gitea_url: Option<String>,
+
+    #[clap(long)]
+    #[serde(serialize_with = "serialize_secret_opt_str", default)]
+    /// Gitea Access Token, used with `--gitea-url`
+    pub gitea_access_token: Option<SecretString>,
+
+    #[clap(skip)]
+    #[serde(default)]
+    /// Named weights for ranking content search results, selected per query with `ranking`.
+    ///
+    /// A profile named `default` replaces the built-in ranking for queries that don't select one.
+    pub ranking_profiles: HashMap<String, RankingProfile>,
+
     //
     // External dependencies
     //
@@ -175,6 +213,8 @@ impl Configuration {
         Self {
             config_file: b.config_file.or(a.config_file),
 
+            command: b.command.or(a.command),
+
             source: right_if_default!(b.source, a.source, Default::default()),
 
             index_dir: right_if_default!(b.index_dir, a.index_dir, default_index_dir()),
@@ -203,8 +243,24 @@ impl Configuration {
 
             openai_api_key: b.openai_api_key.or(a.openai_api_key),
 
+            llm: right_if_default!(b.llm, a.llm, Default::default()),
+
             github_access_token: b.github_access_token.or(a.github_access_token),
 
+            gitlab_url: b.gitlab_url.or(a.gitlab_url),
+
+            gitlab_access_token: b.gitlab_access_token.or(a.gitlab_access_token),
+
+            gitea_url: b.gitea_url.or(a.gitea_url),
+
+            gitea_access_token: b.gitea_access_token.or(a.gitea_access_token),
+
+            ranking_profiles: right_if_default!(
+                b.ranking_profiles,
+                a.ranking_profiles,
+                HashMap::new()
+            ),
+
             model_dir: right_if_default!(b.model_dir, a.model_dir, default_model_dir()),
 
             max_chunk_tokens: right_if_default!(
@@ -233,6 +289,22 @@ impl Configuration {
         }
     }
 
+    /// Look up a ranking profile by name, or the default profile if no name is given.
+    pub fn ranking_profile(&self, name: Option<&str>) -> Result<RankingProfile> {
+        match name {
+            Some(name) => self
+                .ranking_profiles
+                .get(name)
+                .cloned()
+                .with_context(|| format!("unknown ranking profile `{name}`")),
+            None => Ok(self
+                .ranking_profiles
+                .get("default")
+                .cloned()
+                .unwrap_or_default()),
+        }
+    }
+
     /// Directory where logs are written to
     pub fn log_dir(&self) -> PathBuf {
         self.index_dir.join("logs")
@@ -35,6 +35,16 @@ pub async fn initialize(config: &Configuration) -> Result<SqlitePool> {
     }
 }
 
+/// Open an existing database without creating or migrating it.
+pub async fn open_read_only(config: &Configuration) -> Result<SqlitePool> {
+    let data_dir = config.index_dir.to_string_lossy();
+    let url = format!("sqlite://{data_dir}/bleep.db?mode=ro");
+
+    SqlitePool::connect(&url)
+        .await
+        .context("failed to open database")
+}
+
 #[tracing::instrument()]
 async fn connect(url: &str) -> Result<SqlitePool> {
     let pool = SqlitePool::connect(url).await?;
@@ -0,0 +1,425 @@
+//! Consistency checks between the stores that hold an indexed repository.
+//!
+//! Every indexed file is kept in three places: a document in the file index, a `file_cache` row
+//! and the `chunk_cache` rows of its chunks in SQLite, and a Qdrant point for each chunk. A sync
+//! that's interrupted, or a crash between writes to these stores, can leave them out of step. As
+//! the cache decides which files are reindexed, such inconsistencies otherwise outlive any sync.
+//!
+//! Files skipped by the indexer, e.g. for having too many lines, have a `file_cache` row but no
+//! document or chunks. Only rows of files with chunks are therefore expected to have a document.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use tantivy::{schema::Value, Term};
+
+use crate::{
+    cache::{ChunkRow, FileCache},
+    indexes::{repo_documents, GlobalWriteHandle},
+    repo::{RepoRef, SyncStatus},
+    Application,
+};
+
+/// Length of the semantic part of a `file_cache` key, which is followed by the tantivy part.
+const SEMANTIC_KEY_LEN: usize = 64;
+
+/// The inconsistencies found in a repository.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub reporef: RepoRef,
+    /// Documents in the file index without a `file_cache` row
+    pub orphaned_documents: usize,
+    /// `file_cache` rows of files with chunks, but without a document
+    pub missing_documents: usize,
+    /// `chunk_cache` rows without a `file_cache` row
+    pub orphaned_chunks: usize,
+    /// `chunk_cache` rows without a Qdrant point
+    pub missing_points: usize,
+    /// Qdrant points without a `chunk_cache` row
+    pub orphaned_points: usize,
+    /// Whether orphans were deleted, and files with missing entries forgotten
+    pub repaired: bool,
+}
+
+impl Report {
+    pub fn is_consistent(&self) -> bool {
+        self.orphaned_documents == 0
+            && self.missing_documents == 0
+            && self.orphaned_chunks == 0
+            && self.missing_points == 0
+            && self.orphaned_points == 0
+    }
+
+    /// Whether some files have to be indexed again after a repair.
+    pub fn needs_reindex(&self) -> bool {
+        self.missing_documents > 0 || self.missing_points > 0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.reporef)?;
+
+        if self.is_consistent() {
+            return write!(f, "ok");
+        }
+
+        write!(
+            f,
+            "{} orphaned documents, {} missing documents, {} orphaned chunks, \
+             {} missing points, {} orphaned points",
+            self.orphaned_documents,
+            self.missing_documents,
+            self.orphaned_chunks,
+            self.missing_points,
+            self.orphaned_points
+        )?;
+
+        if self.repaired {
+            write!(f, " (repaired)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What has to be deleted to make a repository consistent.
+///
+/// Orphans are deleted, along with every entry of files that have missing entries. The cache then
+/// no longer knows about the latter, so they're indexed again on the next full sync.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Repair {
+    /// `unique_hash` of file documents
+    documents: Vec<String>,
+    /// `cache_hash` of `file_cache` rows
+    files: Vec<String>,
+    /// `chunk_hash` of `chunk_cache` rows
+    chunks: Vec<String>,
+    /// Ids of Qdrant points
+    points: Vec<String>,
+}
+
+/// Check the repositories in the pool, or only `reporef`, optionally repairing them.
+///
+/// Repositories that are being synced are skipped. A repair holds the index write lock, so it
+/// waits for running syncs to finish. Repaired repositories with missing entries have their
+/// indexed tip reset, which makes their next sync walk all files and reindex the forgotten ones.
+pub async fn run(app: &Application, reporef: Option<RepoRef>, repair: bool) -> Result<Vec<Report>> {
+    let writers = match repair {
+        true => Some(
+            app.indexes
+                .writers()
+                .await
+                .context("failed to open the indexes for writing")?,
+        ),
+        false => None,
+    };
+
+    let mut repos = vec![];
+    app.repo_pool
+        .scan_async(|k, repo| {
+            let syncing = matches!(repo.sync_status, SyncStatus::Syncing | SyncStatus::Indexing);
+            if reporef.as_ref().map_or(true, |r| r == k) && !syncing {
+                repos.push(k.clone());
+            }
+        })
+        .await;
+
+    if let Some(reporef) = reporef {
+        if repos.is_empty() {
+            bail!("repository {reporef} is not indexed, or is being synced");
+        }
+    }
+
+    let mut reports = vec![];
+    for reporef in repos {
+        let (mut report, plan) = check(app, &reporef).await?;
+
+        if let Some(writers) = &writers {
+            if !report.is_consistent() {
+                apply(app, writers, &reporef, plan).await?;
+                report.repaired = true;
+            }
+        }
+
+        reports.push(report);
+    }
+
+    let Some(writers) = writers else {
+        return Ok(reports);
+    };
+
+    writers.commit()?;
+
+    for report in reports.iter().filter(|r| r.repaired && r.needs_reindex()) {
+        app.repo_pool
+            .update_async(&report.reporef, |_, repo| {
+                repo.indexed_tip = None;
+                repo.last_index_unix_secs = 0;
+            })
+            .await;
+    }
+    app.config.source.save_pool(app.repo_pool.clone())?;
+
+    Ok(reports)
+}
+
+async fn check(app: &Application, reporef: &RepoRef) -> Result<(Report, Repair)> {
+    let file = &app.indexes.file;
+    let searcher = file.reader.searcher();
+
+    let mut documents = HashSet::new();
+    for addr in repo_documents(&searcher, file.source.repo_ref, reporef)? {
+        let doc = searcher.doc(addr)?;
+        if let Some(hash) = doc
+            .get_first(file.source.unique_hash)
+            .and_then(Value::as_text)
+        {
+            documents.insert(hash.to_owned());
+        }
+    }
+
+    let (files, chunks) = FileCache::new(app.sql.clone(), app.semantic.clone())
+        .rows(reporef)
+        .await?;
+
+    let mut points = HashMap::new();
+    app.semantic
+        .for_each_point(&reporef.to_string(), false, |payload| {
+            if let Some(id) = payload.id {
+                points.insert(id, payload.content_hash);
+            }
+            Ok(())
+        })
+        .await?;
+
+    Ok(compare(reporef, &documents, &files, &chunks, &points))
+}
+
+/// Compare the document hashes, cache rows and points (by id, with their file's semantic key) of
+/// a repository.
+fn compare(
+    reporef: &RepoRef,
+    documents: &HashSet<String>,
+    files: &[String],
+    chunks: &[ChunkRow],
+    points: &HashMap<String, String>,
+) -> (Report, Repair) {
+    let cache_keys = files
+        .iter()
+        .filter(|hash| hash.len() > SEMANTIC_KEY_LEN)
+        .map(|hash| {
+            let (semantic, tantivy) = hash.split_at(SEMANTIC_KEY_LEN);
+            (hash, semantic, tantivy)
+        })
+        .collect::<Vec<_>>();
+
+    let semantic_keys = cache_keys.iter().map(|k| k.1).collect::<HashSet<_>>();
+    let tantivy_keys = cache_keys.iter().map(|k| k.2).collect::<HashSet<_>>();
+    let chunked_files = chunks
+        .iter()
+        .map(|c| c.file_hash.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut report = Report {
+        reporef: reporef.clone(),
+        orphaned_documents: 0,
+        missing_documents: 0,
+        orphaned_chunks: 0,
+        missing_points: 0,
+        orphaned_points: 0,
+        repaired: false,
+    };
+    let mut plan = Repair::default();
+
+    for hash in documents {
+        if !tantivy_keys.contains(hash.as_str()) {
+            report.orphaned_documents += 1;
+            plan.documents.push(hash.clone());
+        }
+    }
+
+    // Files with missing entries, by their semantic key.
+    let mut forget = HashSet::new();
+
+    let mut cached_chunks = HashSet::new();
+    for chunk in chunks {
+        if !semantic_keys.contains(chunk.file_hash.as_str()) {
+            report.orphaned_chunks += 1;
+            plan.chunks.push(chunk.chunk_hash.clone());
+            continue;
+        }
+
+        cached_chunks.insert(chunk.chunk_hash.as_str());
+        if !points.contains_key(&chunk.chunk_hash) {
+            report.missing_points += 1;
+            forget.insert(chunk.file_hash.as_str());
+        }
+    }
+
+    for &(_, semantic, tantivy) in &cache_keys {
+        if chunked_files.contains(semantic) && !documents.contains(tantivy) {
+            report.missing_documents += 1;
+            forget.insert(semantic);
+        }
+    }
+
+    for (id, content_hash) in points {
+        if !cached_chunks.contains(id.as_str()) {
+            report.orphaned_points += 1;
+            plan.points.push(id.clone());
+        } else if forget.contains(content_hash.as_str()) {
+            plan.points.push(id.clone());
+        }
+    }
+
+    for &(hash, semantic, tantivy) in &cache_keys {
+        if forget.contains(semantic) {
+            plan.files.push(hash.clone());
+            if documents.contains(tantivy) {
+                plan.documents.push(tantivy.to_owned());
+            }
+        }
+    }
+
+    for chunk in chunks {
+        if forget.contains(chunk.file_hash.as_str()) {
+            plan.chunks.push(chunk.chunk_hash.clone());
+        }
+    }
+
+    (report, plan)
+}
+
+/// Delete the entries of a repair. Document deletions are applied once `writers` are committed.
+async fn apply(
+    app: &Application,
+    writers: &GlobalWriteHandle<'_>,
+    reporef: &RepoRef,
+    plan: Repair,
+) -> Result<()> {
+    let unique_hash = app.indexes.file.source.unique_hash;
+    for hash in &plan.documents {
+        writers
+            .file()
+            .delete_term(Term::from_field_text(unique_hash, hash));
+    }
+
+    if !plan.points.is_empty() {
+        app.semantic.delete_points(plan.points).await?;
+    }
+
+    FileCache::new(app.sql.clone(), app.semantic.clone())
+        .delete_rows(reporef, &plan.files, &plan.chunks)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo() -> RepoRef {
+        "github.com/BloopAI/bloop".parse().unwrap()
+    }
+
+    fn key(semantic: char, tantivy: &str) -> String {
+        format!("{}{tantivy}", semantic.to_string().repeat(SEMANTIC_KEY_LEN))
+    }
+
+    fn chunk(chunk_hash: &str, file: char) -> ChunkRow {
+        ChunkRow {
+            chunk_hash: chunk_hash.to_owned(),
+            file_hash: file.to_string().repeat(SEMANTIC_KEY_LEN),
+            branches: "HEAD".to_owned(),
+        }
+    }
+
+    fn point(id: &str, file: char) -> (String, String) {
+        (id.to_owned(), file.to_string().repeat(SEMANTIC_KEY_LEN))
+    }
+
+    #[test]
+    fn consistent() {
+        let documents = ["ta".to_owned(), "tb".to_owned()].into();
+        let files = [key('a', "ta"), key('b', "tb"), key('c', "tc")];
+        let chunks = [chunk("1", 'a'), chunk("2", 'a'), chunk("3", 'b')];
+        let points = [point("1", 'a'), point("2", 'a'), point("3", 'b')].into();
+
+        // `c` was skipped by the indexer, and has neither a document nor chunks.
+        let (report, plan) = compare(&repo(), &documents, &files, &chunks, &points);
+        assert!(report.is_consistent());
+        assert_eq!(plan, Repair::default());
+    }
+
+    #[test]
+    fn orphans() {
+        let documents = ["ta".to_owned(), "tx".to_owned()].into();
+        let files = [key('a', "ta")];
+        let chunks = [chunk("1", 'a'), chunk("2", 'x')];
+        let points = [point("1", 'a'), point("2", 'x'), point("3", 'y')].into();
+
+        let (report, mut plan) = compare(&repo(), &documents, &files, &chunks, &points);
+        assert_eq!(
+            report,
+            Report {
+                reporef: repo(),
+                orphaned_documents: 1,
+                missing_documents: 0,
+                orphaned_chunks: 1,
+                missing_points: 0,
+                orphaned_points: 2,
+                repaired: false,
+            }
+        );
+        assert!(!report.needs_reindex());
+
+        plan.points.sort();
+        assert_eq!(
+            plan,
+            Repair {
+                documents: vec!["tx".to_owned()],
+                files: vec![],
+                chunks: vec!["2".to_owned()],
+                points: vec!["2".to_owned(), "3".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn missing_entries_forget_the_file() {
+        let documents = ["tb".to_owned()].into();
+        let files = [key('a', "ta"), key('b', "tb")];
+        let chunks = [chunk("1", 'a'), chunk("2", 'b'), chunk("3", 'b')];
+        let points = [point("1", 'a'), point("2", 'b')].into();
+
+        let (report, mut plan) = compare(&repo(), &documents, &files, &chunks, &points);
+        assert_eq!(
+            report,
+            Report {
+                reporef: repo(),
+                orphaned_documents: 0,
+                missing_documents: 1,
+                orphaned_chunks: 0,
+                missing_points: 1,
+                orphaned_points: 0,
+                repaired: false,
+            }
+        );
+        assert!(report.needs_reindex());
+
+        plan.points.sort();
+        assert_eq!(
+            plan,
+            Repair {
+                documents: vec!["tb".to_owned()],
+                files: vec![key('a', "ta"), key('b', "tb")],
+                chunks: vec!["1".to_owned(), "2".to_owned(), "3".to_owned()],
+                points: vec!["1".to_owned(), "2".to_owned()],
+            }
+        );
+    }
+}
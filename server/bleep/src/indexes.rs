@@ -4,28 +4,33 @@ use anyhow::{Context, Result};
 use async_trait::async_trait;
 use smallvec::SmallVec;
 use tantivy::{
-    collector::{Collector, MultiFruit},
-    schema::Schema,
-    tokenizer::NgramTokenizer,
-    DocAddress, Document, IndexReader, IndexWriter, Score,
+    collector::{Collector, DocSetCollector, MultiFruit},
+    query::TermQuery,
+    schema::{Field, IndexRecordOption, Schema},
+    tokenizer::{LowerCaser, NgramTokenizer, TextAnalyzer},
+    DocAddress, Document, IndexReader, IndexWriter, Score, Searcher, Term,
 };
 
-mod analytics;
+pub mod analytics;
+pub mod commit;
 pub mod doc;
 pub mod file;
 pub mod reader;
 pub mod repo;
 mod schema;
+pub mod tokenizer;
 
+pub use commit::Commit;
 pub use doc::Doc;
 pub use file::File;
 pub use repo::Repo;
 use tracing::debug;
 
+use self::tokenizer::{CodeTokenizer, CODE_TOKENIZER};
 use crate::{
     background::SyncHandle,
     query::parser::Query,
-    repo::{RepoError, RepoMetadata, Repository},
+    repo::{RepoError, RepoMetadata, RepoRef, Repository},
     Configuration,
 };
 
@@ -61,12 +66,17 @@ impl<'a> GlobalWriteHandle<'a> {
         Ok(())
     }
 
+    /// The write handle of the file index.
+    pub(crate) fn file(&self) -> &IndexWriteHandle<'a> {
+        &self.handles[1]
+    }
+
     pub(crate) async fn index(
         &self,
         sync_handle: &SyncHandle,
         repo: &Repository,
     ) -> Result<Arc<RepoMetadata>, RepoError> {
-        let metadata = repo.get_repo_metadata().await;
+        let metadata = repo.get_repo_metadata(&sync_handle.reporef).await;
 
         for h in &self.handles {
             h.index(sync_handle, repo, &metadata).await?;
@@ -79,6 +89,7 @@ impl<'a> GlobalWriteHandle<'a> {
 pub struct Indexes {
     pub repo: Indexer<Repo>,
     pub file: Indexer<File>,
+    pub commit: Indexer<Commit>,
     pub doc: Doc,
     was_index_reset: bool,
     write_mutex: tokio::sync::Mutex<()>,
@@ -103,6 +114,12 @@ impl Indexes {
                 config.buffer_size,
                 config.max_threads,
             )?,
+            commit: Indexer::create(
+                Commit::new(),
+                config.index_path("commit").as_ref(),
+                config.buffer_size,
+                config.max_threads,
+            )?,
             doc: Doc::create(
                 sql,
                 config.index_path("doc").as_ref(),
@@ -125,6 +142,10 @@ impl Indexes {
             fs::remove_dir_all(config.index_path("content"))?;
             debug!("removed index content dir")
         }
+        if config.index_path("commit").as_ref().exists() {
+            fs::remove_dir_all(config.index_path("commit"))?;
+            debug!("removed index commit dir")
+        }
         Ok(())
     }
 
@@ -135,7 +156,11 @@ impl Indexes {
         debug!(id, "lock acquired");
 
         Ok(GlobalWriteHandle {
-            handles: vec![self.repo.write_handle()?, self.file.write_handle()?],
+            handles: vec![
+                self.repo.write_handle()?,
+                self.file.write_handle()?,
+                self.commit.write_handle()?,
+            ],
             _write_lock,
         })
     }
@@ -202,6 +227,16 @@ impl<'a> IndexWriteHandle<'a> {
             .await
     }
 
+    pub(crate) fn delete_term(&self, term: Term) {
+        self.writer.delete_term(term);
+    }
+
+    /// Add a document built outside of `Indexable::index_repository`, e.g. restored from a bundle.
+    pub(crate) fn add_document(&self, doc: Document) -> Result<()> {
+        self.writer.add_document(doc)?;
+        Ok(())
+    }
+
     pub fn commit(&mut self) -> Result<()> {
         self.writer.commit()?;
         self.reader.reload()?;
@@ -227,7 +262,7 @@ pub struct Indexer<T> {
 }
 
 impl<T: Indexable> Indexer<T> {
-    fn write_handle(&self) -> Result<IndexWriteHandle<'_>> {
+    pub(crate) fn write_handle(&self) -> Result<IndexWriteHandle<'_>> {
         Ok(IndexWriteHandle {
             source: &self.source,
             reader: &self.reader,
@@ -243,12 +278,24 @@ impl<T: Indexable> Indexer<T> {
         let mut index =
             tantivy::Index::open_or_create(tantivy::directory::MmapDirectory::open(path)?, schema)?;
 
+        Self::configure_index(&mut index, threads)?;
+
+        Ok(index)
+    }
+
+    fn configure_index(index: &mut tantivy::Index, threads: usize) -> Result<()> {
         index.set_multithread_executor(threads)?;
         index
             .tokenizers()
             .register("default", NgramTokenizer::new(1, 3, false)?);
+        index.tokenizers().register(
+            CODE_TOKENIZER,
+            TextAnalyzer::builder(CodeTokenizer)
+                .filter(LowerCaser)
+                .build(),
+        );
 
-        Ok(index)
+        Ok(())
     }
 
     /// Create an index using `source` at the specified path.
@@ -266,6 +313,25 @@ impl<T: Indexable> Indexer<T> {
         Ok(instance)
     }
 
+    /// Open an existing index at the specified path for reading only.
+    ///
+    /// No writer is created, so this can be used while another process holds the index lock.
+    pub fn open(source: T, path: &Path, threads: usize) -> Result<Self> {
+        let mut index = tantivy::Index::open_in_dir(path)
+            .with_context(|| format!("failed to open index at {}", path.display()))?;
+
+        Self::configure_index(&mut index, threads)?;
+
+        let reader = index.reader()?;
+        Ok(Self {
+            reader,
+            index,
+            source,
+            reindex_threads: threads,
+            reindex_buffer_size: 0,
+        })
+    }
+
     pub async fn query<'a, R, I, C>(
         &'a self,
         queries: I,
@@ -300,6 +366,26 @@ impl<T: Indexable> Indexer<T> {
     }
 }
 
+/// The addresses of all documents of a repository, in an index with a `repo_ref` string field.
+pub(crate) fn repo_documents(
+    searcher: &Searcher,
+    repo_ref: Field,
+    reporef: &RepoRef,
+) -> Result<Vec<DocAddress>> {
+    let query = TermQuery::new(
+        Term::from_field_text(repo_ref, &reporef.to_string()),
+        IndexRecordOption::Basic,
+    );
+
+    let mut docs = searcher
+        .search(&query, &DocSetCollector)?
+        .into_iter()
+        .collect::<Vec<_>>();
+    docs.sort();
+
+    Ok(docs)
+}
+
 pub struct SearchResults<'a, T> {
     pub docs: Box<dyn Iterator<Item = T> + Sync + Send + 'a>,
     pub metadata: MultiFruit,
@@ -1,5 +1,28 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use itertools::Itertools;
+use serde::Serialize;
+use tantivy::DocAddress;
+use tantivy_columnar::BytesColumn;
 use tokio::sync::mpsc;
 
+use super::{repo_documents, File, Indexer};
+use crate::{
+    db::SqlDb,
+    repo::{iterator::SkipReason, RepoRef},
+};
+
+/// Number of syncs per repository whose stats are kept.
+const SYNC_HISTORY_LEN: i64 = 100;
+
+/// Number of files listed by `largest_files`.
+const LARGEST_FILES: usize = 10;
+
 #[derive(Default)]
 pub struct WorkerStats {
     // size in bytes
@@ -8,6 +31,8 @@ pub struct WorkerStats {
     pub chunks: usize,
     // number of dir-entries reindexed by this worker
     pub reindex_count: usize,
+    // files whose contents weren't indexed
+    pub skipped: Vec<SkippedFile>,
 }
 
 impl std::ops::AddAssign for WorkerStats {
@@ -15,9 +40,19 @@ impl std::ops::AddAssign for WorkerStats {
         self.size += rhs.size;
         self.chunks += rhs.chunks;
         self.reindex_count += rhs.reindex_count;
+        self.skipped.extend(rhs.skipped);
     }
 }
 
+/// A file whose contents weren't indexed.
+pub struct SkippedFile {
+    /// The `file_cache` key of the file
+    pub cache_hash: String,
+    pub relative_path: String,
+    pub reason: SkipReason,
+    pub size: usize,
+}
+
 // the main entrypoint into gathering analytics for an index job
 pub struct StatsGatherer {
     // reciever of stats from worker threads
@@ -55,4 +90,257 @@ impl StatsGatherer {
             self.repo_stats += stats;
         }
     }
+
+    /// Record the stats of a finished sync, along with the files it skipped.
+    ///
+    /// A sync only processes files that changed since the last one, so skipped files are kept
+    /// for as long as their `file_cache` entry. This has to run after the cache is synchronized.
+    pub async fn save(
+        &self,
+        db: &SqlDb,
+        reporef: &RepoRef,
+        duration: Duration,
+        incremental: bool,
+    ) -> Result<()> {
+        let mut tx = db.begin().await?;
+        let repo_str = reporef.to_string();
+        let stats = &self.repo_stats;
+
+        let duration_ms = duration.as_millis() as i64;
+        let size = stats.size as i64;
+        let chunks = stats.chunks as i64;
+        let reindex_count = stats.reindex_count as i64;
+        let skipped_count = stats.skipped.len() as i64;
+
+        sqlx::query! {
+            "INSERT INTO index_stats \
+             (repo_ref, duration_ms, size, chunks, reindex_count, skipped_count, \
+              is_first_index, was_index_reset, incremental) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            repo_str,
+            duration_ms,
+            size,
+            chunks,
+            reindex_count,
+            skipped_count,
+            self.is_first_index,
+            self.was_index_reset,
+            incremental,
+        }
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query! {
+            "DELETE FROM index_stats \
+             WHERE repo_ref = ? AND id NOT IN ( \
+                 SELECT id FROM index_stats WHERE repo_ref = ? ORDER BY id DESC LIMIT ? \
+             )",
+            repo_str,
+            repo_str,
+            SYNC_HISTORY_LEN,
+        }
+        .execute(&mut tx)
+        .await?;
+
+        for file in &stats.skipped {
+            let reason = file.reason.as_str();
+            let size = file.size as i64;
+
+            sqlx::query! {
+                "INSERT OR REPLACE INTO skipped_files \
+                 (repo_ref, cache_hash, relative_path, reason, size) \
+                 VALUES (?, ?, ?, ?, ?)",
+                repo_str,
+                file.cache_hash,
+                file.relative_path,
+                reason,
+                size,
+            }
+            .execute(&mut tx)
+            .await?;
+        }
+
+        sqlx::query! {
+            "DELETE FROM skipped_files \
+             WHERE repo_ref = ? AND cache_hash NOT IN ( \
+                 SELECT cache_hash FROM file_cache WHERE repo_ref = ? \
+             )",
+            repo_str,
+            repo_str,
+        }
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// The stats of a single sync.
+#[derive(Serialize)]
+pub struct SyncStats {
+    pub created_at: NaiveDateTime,
+    pub duration_ms: i64,
+    /// Total size of the reindexed files and directories, in bytes
+    pub size: i64,
+    /// Number of new embedded chunks
+    pub chunks: i64,
+    /// Number of reindexed files and directories
+    pub reindex_count: i64,
+    pub skipped_count: i64,
+    pub is_first_index: bool,
+    pub was_index_reset: bool,
+    /// Whether only the changes since the previous sync were walked
+    pub incremental: bool,
+}
+
+#[derive(Serialize)]
+pub struct SkippedFileRow {
+    pub relative_path: String,
+    pub reason: String,
+    pub size: i64,
+}
+
+#[derive(Serialize)]
+pub struct FileSize {
+    pub relative_path: String,
+    pub lang: Option<String>,
+    pub size: usize,
+}
+
+/// The stats of the latest syncs of a repository, newest first.
+pub async fn sync_history(db: &SqlDb, reporef: &RepoRef) -> Result<Vec<SyncStats>> {
+    let repo_str = reporef.to_string();
+    Ok(sqlx::query_as! {
+        SyncStats,
+        "SELECT created_at, duration_ms, size, chunks, reindex_count, skipped_count, \
+         is_first_index, was_index_reset, incremental \
+         FROM index_stats \
+         WHERE repo_ref = ? \
+         ORDER BY id DESC",
+        repo_str,
+    }
+    .fetch_all(db.as_ref())
+    .await?)
+}
+
+/// The files of a repository whose contents aren't indexed, largest first.
+pub async fn skipped_files(db: &SqlDb, reporef: &RepoRef) -> Result<Vec<SkippedFileRow>> {
+    let repo_str = reporef.to_string();
+    Ok(sqlx::query_as! {
+        SkippedFileRow,
+        "SELECT relative_path, reason, size \
+         FROM skipped_files \
+         WHERE repo_ref = ? \
+         ORDER BY size DESC, relative_path",
+        repo_str,
+    }
+    .fetch_all(db.as_ref())
+    .await?)
+}
+
+/// The `file_cache` keys of the skipped files of a repository at the given relative paths.
+pub async fn skipped_keys(
+    db: &SqlDb,
+    reporef: &RepoRef,
+    paths: &HashSet<String>,
+) -> Result<HashSet<String>> {
+    let repo_str = reporef.to_string();
+    Ok(sqlx::query! {
+        "SELECT cache_hash, relative_path \
+         FROM skipped_files \
+         WHERE repo_ref = ?",
+        repo_str,
+    }
+    .fetch_all(db.as_ref())
+    .await?
+    .into_iter()
+    .filter(|row| paths.contains(&row.relative_path))
+    .map(|row| row.cache_hash)
+    .collect())
+}
+
+/// Delete the stats and skipped files of a repository.
+pub async fn delete(db: &SqlDb, reporef: &RepoRef) -> Result<()> {
+    let mut tx = db.begin().await?;
+    let repo_str = reporef.to_string();
+
+    sqlx::query! {
+        "DELETE FROM index_stats WHERE repo_ref = ?",
+        repo_str,
+    }
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query! {
+        "DELETE FROM skipped_files WHERE repo_ref = ?",
+        repo_str,
+    }
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Count the indexed files of a repository per language, and find the largest ones.
+///
+/// Files without a detected language are counted under an empty name. Only fast fields are read,
+/// so that the contents of files aren't loaded.
+pub fn file_stats(
+    indexer: &Indexer<File>,
+    reporef: &RepoRef,
+) -> Result<(BTreeMap<String, usize>, Vec<FileSize>)> {
+    let source = &indexer.source;
+    let searcher = indexer.reader.searcher();
+    let schema = searcher.schema();
+    let name = |field| schema.get_field_name(field);
+
+    let mut languages = BTreeMap::new();
+    let mut files = vec![];
+    let mut bytes = vec![];
+
+    let docs = repo_documents(&searcher, source.repo_ref, reporef)?;
+    for (segment_ord, docs) in &docs.into_iter().group_by(|addr| addr.segment_ord) {
+        let fast_fields = searcher.segment_reader(segment_ord).fast_fields();
+        let is_directory = fast_fields.bool(name(source.is_directory))?;
+        let indexed = fast_fields.bool(name(source.indexed))?;
+        let size = fast_fields.u64(name(source.size))?;
+        let (Some(lang), Some(path)) = (
+            fast_fields.bytes(name(source.lang))?,
+            fast_fields.bytes(name(source.raw_relative_path))?,
+        ) else {
+            continue;
+        };
+
+        let mut read = |column: &BytesColumn, doc| {
+            bytes.clear();
+            for ord in column.ords().values_for_doc(doc) {
+                column.ord_to_bytes(ord, &mut bytes)?;
+            }
+            anyhow::Ok(String::from_utf8_lossy(&bytes).into_owned())
+        };
+
+        for DocAddress { doc_id, .. } in docs {
+            if is_directory.first(doc_id) != Some(false) || indexed.first(doc_id) != Some(true) {
+                continue;
+            }
+
+            let lang = read(&lang, doc_id)?;
+            *languages.entry(lang.clone()).or_insert(0) += 1;
+
+            files.push(FileSize {
+                relative_path: read(&path, doc_id)?,
+                lang: Some(lang).filter(|l| !l.is_empty()),
+                size: size.first(doc_id).unwrap_or_default() as usize,
+            });
+        }
+    }
+
+    files.sort_by(|a, b| b.size.cmp(&a.size));
+    files.truncate(LARGEST_FILES);
+
+    Ok((languages, files))
 }
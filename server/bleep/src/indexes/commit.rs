@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use itertools::Itertools;
+use tantivy::{
+    doc,
+    schema::{Schema, Value},
+    DocAddress, Document, IndexWriter, Searcher, Term,
+};
+use tracing::{info, warn};
+
+pub use super::schema::Commit;
+use super::{repo_documents, Indexable};
+use crate::{
+    background::SyncHandle,
+    commits::{self, HistoryEntry},
+    repo::{iterator::BranchFilter, RepoMetadata, RepoRef, Repository},
+};
+
+/// The number of new commits we index for a repository in a single sync.
+///
+/// The newest commits which aren't indexed yet are picked first, so the history of very large
+/// repositories is filled in over several syncs.
+const MAX_COMMITS_PER_SYNC: usize = 5_000;
+
+#[async_trait]
+impl Indexable for Commit {
+    async fn index_repository(
+        &self,
+        SyncHandle {
+            ref reporef,
+            ref pipes,
+            ..
+        }: &SyncHandle,
+        repo: &Repository,
+        metadata: &RepoMetadata,
+        writer: &IndexWriter,
+    ) -> Result<()> {
+        let searcher = writer.index().reader()?.searcher();
+        let indexed = self.indexed_commits(&searcher, reporef)?;
+        let repo_name = reporef.indexed_name();
+        let disk_path = repo.disk_path.to_string_lossy().into_owned();
+        let branch_filter: BranchFilter = metadata
+            .config
+            .branch_filter(repo.branch_filter.as_ref())
+            .map(Into::into)
+            .unwrap_or_default();
+
+        let start = std::time::Instant::now();
+        let walk = commits::walk_history(
+            reporef,
+            &repo.disk_path,
+            &branch_filter,
+            MAX_COMMITS_PER_SYNC,
+            |id| indexed.contains(id),
+            |entry| {
+                if let Err(err) = self.add_commit(writer, reporef, &repo_name, &disk_path, entry) {
+                    warn!(%err, "failed to index commit; skipping");
+                }
+
+                !pipes.is_cancelled()
+            },
+        );
+
+        if pipes.is_cancelled() {
+            bail!("cancelled");
+        }
+
+        match walk {
+            Ok(walk) => {
+                // Commits which were rewritten or deleted, or are only on branches which are no
+                // longer indexed.
+                let unreachable = indexed.difference(&walk.reachable).collect::<Vec<_>>();
+                for id in &unreachable {
+                    writer.delete_term(Term::from_field_text(
+                        self.unique_hash,
+                        &unique_hash(reporef, id),
+                    ));
+                }
+
+                info!(
+                    ?repo.disk_path,
+                    count = walk.visited,
+                    deleted = unreachable.len(),
+                    "commit history indexing finished, took {:?}",
+                    start.elapsed()
+                );
+            }
+            // Local directories aren't necessarily git repositories, so this is not fatal.
+            Err(err) => warn!(%err, ?repo.disk_path, "failed to walk commit history"),
+        }
+
+        Ok(())
+    }
+
+    fn delete_by_repo(&self, writer: &IndexWriter, repo: &Repository) {
+        writer.delete_term(Term::from_field_text(
+            self.repo_disk_path,
+            &repo.disk_path.to_string_lossy(),
+        ));
+    }
+
+    fn schema(&self) -> Schema {
+        self.schema.clone()
+    }
+}
+
+impl Commit {
+    /// The ids of the commits of a repository which are already indexed.
+    fn indexed_commits(
+        &self,
+        searcher: &Searcher,
+        reporef: &RepoRef,
+    ) -> Result<HashSet<gix::ObjectId>> {
+        let name = searcher.schema().get_field_name(self.commit_id);
+        let mut commits = HashSet::new();
+        let mut id = String::new();
+
+        let docs = repo_documents(searcher, self.repo_ref, reporef)?;
+        for (segment_ord, docs) in &docs.into_iter().group_by(|addr| addr.segment_ord) {
+            let fast_fields = searcher.segment_reader(segment_ord).fast_fields();
+            let Some(column) = fast_fields.str(name)? else {
+                continue;
+            };
+
+            for DocAddress { doc_id, .. } in docs {
+                for ord in column.ords().values_for_doc(doc_id) {
+                    id.clear();
+                    column.ord_to_str(ord, &mut id)?;
+                    commits.insert(gix::ObjectId::from_hex(id.as_bytes())?);
+                }
+            }
+        }
+
+        Ok(commits)
+    }
+
+    fn add_commit(
+        &self,
+        writer: &IndexWriter,
+        reporef: &RepoRef,
+        repo_name: &str,
+        disk_path: &str,
+        entry: HistoryEntry,
+    ) -> Result<()> {
+        let content = format!("{}\n{}", entry.message, entry.diff);
+
+        writer.add_document(doc!(
+            self.unique_hash => unique_hash(reporef, &entry.id),
+            self.repo_disk_path => disk_path,
+            self.repo_ref => reporef.to_string(),
+            self.repo_name => repo_name,
+            self.raw_repo_name => repo_name.as_bytes(),
+            self.commit_id => entry.id.to_string(),
+            self.author => entry.author,
+            self.date => entry.timestamp,
+            self.message => entry.message,
+            self.paths => entry.paths.join("\n"),
+            self.diff => entry.diff,
+            self.content => content,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Add the fields which aren't stored back to a document read from another index.
+    pub(crate) fn restore_document(&self, doc: &mut Document, repo_disk_path: &str) {
+        let text = |field| {
+            doc.get_first(field)
+                .and_then(Value::as_text)
+                .unwrap_or_default()
+                .to_owned()
+        };
+
+        let repo_name = text(self.repo_name);
+        let content = format!("{}\n{}", text(self.message), text(self.diff));
+
+        doc.add_text(self.repo_disk_path, repo_disk_path);
+        doc.add_bytes(self.raw_repo_name, repo_name.into_bytes());
+        doc.add_text(self.content, content);
+    }
+}
+
+fn unique_hash(reporef: &RepoRef, id: &gix::oid) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(reporef.to_string().as_bytes());
+    hasher.update(id.as_bytes());
+    hasher.finalize().to_hex().to_string()
+}
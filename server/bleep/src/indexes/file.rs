@@ -1,18 +1,21 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     panic::AssertUnwindSafe,
     path::{Path, PathBuf},
-    sync::atomic::{AtomicU64, Ordering},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use anyhow::{bail, Result};
 use async_trait::async_trait;
 use rayon::prelude::*;
 use tantivy::{
-    collector::TopDocs,
+    collector::{DocSetCollector, TopDocs},
     doc,
     query::{BooleanQuery, Query, QueryParser, TermQuery},
-    schema::{IndexRecordOption, Schema, Term},
+    schema::{IndexRecordOption, Schema, Term, Value},
     IndexWriter,
 };
 use tokenizers as _;
@@ -20,7 +23,7 @@ use tokio::runtime::Handle;
 use tracing::{error, info, trace, warn};
 
 pub use super::{
-    analytics::{StatsGatherer, WorkerStats},
+    analytics::{SkippedFile, StatsGatherer, WorkerStats},
     schema::File,
 };
 
@@ -28,12 +31,15 @@ pub use super::{
 use std::time::Instant;
 
 use super::{
+    analytics,
     reader::{ContentDocument, ContentReader, FileDocument, FileReader},
     DocumentRead, Indexable, Indexer,
 };
 use crate::{
     background::SyncHandle,
     cache::{CacheKeys, FileCache, FileCacheSnapshot},
+    collector::BytesFilterCollector,
+    commits,
     intelligence::TreeSitterFile,
     query::compiler::{case_permutations, trigrams},
     repo::{iterator::*, RepoMetadata, RepoRef, Repository},
@@ -47,6 +53,7 @@ struct Workload<'a> {
     repo_disk_path: &'a Path,
     repo_name: &'a str,
     repo_metadata: &'a RepoMetadata,
+    last_commits: &'a HashMap<String, i64>,
     relative_path: PathBuf,
     normalized_path: PathBuf,
     stats_tx: tokio::sync::mpsc::UnboundedSender<WorkerStats>,
@@ -72,6 +79,13 @@ impl<'a> Workload<'a> {
                     })
                     .unwrap_or(&b"__no_filter_override"[..]),
             );
+            hash.update(
+                self.repo_metadata
+                    .config
+                    .language(&self.relative_path)
+                    .unwrap_or_default()
+                    .as_bytes(),
+            );
             hash.finalize().to_hex().to_string()
         };
 
@@ -80,17 +94,50 @@ impl<'a> Workload<'a> {
             let mut hash = blake3::Hasher::new();
             hash.update(semantic_hash.as_ref());
             hash.update(branch_list.join("\n").as_bytes());
+            hash.update(&self.last_commit().to_le_bytes());
             hash.finalize().to_hex().to_string()
         };
 
         CacheKeys::new(semantic_hash, tantivy_hash)
     }
 
+    /// The time of the last commit that touched the entry, or of the last commit of the
+    /// repository if the entry isn't committed.
+    fn last_commit(&self) -> u64 {
+        let path = self.relative_path.to_string_lossy();
+        self.last_commits
+            .get(path.as_ref())
+            .copied()
+            .or(self.repo_metadata.last_commit_unix_secs)
+            .unwrap_or(0)
+            .max(0) as u64
+    }
+
     fn transmit_stats(&self, stats: WorkerStats) {
         if let Err(e) = self.stats_tx.send(stats) {
             warn!("failed to transmit worker stats: {e}");
         }
     }
+
+    fn transmit_skipped(
+        &self,
+        cache_keys: &CacheKeys,
+        relative_path: &str,
+        size: usize,
+        reason: SkipReason,
+    ) {
+        self.transmit_stats(WorkerStats {
+            size,
+            reindex_count: 1,
+            skipped: vec![SkippedFile {
+                cache_hash: format!("{}{}", cache_keys.semantic(), cache_keys.tantivy()),
+                relative_path: relative_path.to_owned(),
+                reason,
+                size,
+            }],
+            ..Default::default()
+        });
+    }
 }
 
 #[async_trait]
@@ -100,6 +147,7 @@ impl Indexable for File {
         SyncHandle {
             ref reporef,
             ref file_cache,
+            ref filter_updates,
             ref pipes,
             ref app,
             ..
@@ -108,7 +156,14 @@ impl Indexable for File {
         repo_metadata: &RepoMetadata,
         writer: &IndexWriter,
     ) -> Result<()> {
-        let file_filter = FileFilter::compile(&repo.file_filter)?;
+        let file_filter =
+            FileFilter::compile(&repo.file_filter)?.with_repo_config(&repo_metadata.config)?;
+        let branch_filter = || -> Option<BranchFilter> {
+            repo_metadata
+                .config
+                .branch_filter(repo.branch_filter.as_ref())
+                .map(Into::into)
+        };
         let cache = file_cache.retrieve(reporef).await;
         let repo_name = reporef.indexed_name();
         let processed = &AtomicU64::new(0);
@@ -116,35 +171,78 @@ impl Indexable for File {
         stats_gatherer.is_first_index = cache.is_empty();
         stats_gatherer.was_index_reset = app.indexes.was_index_reset;
 
+        let start = std::time::Instant::now();
+
+        // Only the changes since the last index need to be walked, unless the cache or the
+        // filters have changed since.
+        let diff = repo
+            .indexed_tip
+            .as_ref()
+            .filter(|_| reporef.is_remote())
+            .filter(|_| !cache.is_empty() && !app.indexes.was_index_reset)
+            .filter(|_| filter_updates.file_filter.is_none())
+            .filter(|_| filter_updates.branch_filter.is_none())
+            .map(|since| GitWalker::open_diff(reporef, &repo.disk_path, branch_filter(), since));
+
+        if let Some(Err(ref err)) = diff {
+            info!(?err, ?repo.disk_path, "can't diff with the last index; walking the whole tree");
+        }
+
+        // When walking the changes, only the entries that changed need their last commit.
+        let last_commits = {
+            let since = match diff {
+                Some(Ok(_)) => repo.indexed_tip.as_ref().map(|tip| tip.commit.as_str()),
+                _ => None,
+            };
+            let branches = if reporef.is_remote() {
+                branch_filter().unwrap_or_default()
+            } else {
+                BranchFilter::Head
+            };
+
+            tokio::task::block_in_place(|| {
+                commits::last_commit_times(reporef, &repo.disk_path, &branches, since)
+            })
+            .unwrap_or_else(|err| {
+                info!(?err, ?repo.disk_path, "can't find the last commit of each file");
+                Default::default()
+            })
+        };
+
         let worker_stats_tx = stats_gatherer.sender();
+        let workload = |dir_entry: &RepoDirEntry| {
+            let relative_path = {
+                let entry_srcpath = PathBuf::from(dir_entry.path());
+                entry_srcpath
+                    .strip_prefix(&repo.disk_path)
+                    .map(ToOwned::to_owned)
+                    .unwrap_or(entry_srcpath)
+            };
+            let normalized_path = repo.disk_path.join(&relative_path);
+
+            Workload {
+                repo_disk_path: &repo.disk_path,
+                repo_name: &repo_name,
+                file_filter: &file_filter,
+                repo_ref: reporef,
+                relative_path,
+                normalized_path,
+                repo_metadata,
+                last_commits: &last_commits,
+                cache: &cache,
+                stats_tx: worker_stats_tx.clone(),
+            }
+        };
+
         let file_worker = |count: usize| {
             let cache = &cache;
+            let workload = &workload;
             let callback = move |dir_entry: RepoDirEntry| {
                 let completed = processed.fetch_add(1, Ordering::Relaxed);
                 pipes.index_percent(((completed as f32 / count as f32) * 100f32) as u8);
 
-                let worker_stats_tx = worker_stats_tx.clone();
                 let entry_disk_path = dir_entry.path().to_owned();
-                let relative_path = {
-                    let entry_srcpath = PathBuf::from(&entry_disk_path);
-                    entry_srcpath
-                        .strip_prefix(&repo.disk_path)
-                        .map(ToOwned::to_owned)
-                        .unwrap_or(entry_srcpath)
-                };
-                let normalized_path = repo.disk_path.join(&relative_path);
-
-                let workload = Workload {
-                    repo_disk_path: &repo.disk_path,
-                    repo_name: &repo_name,
-                    file_filter: &file_filter,
-                    repo_ref: reporef,
-                    relative_path,
-                    normalized_path,
-                    repo_metadata,
-                    cache,
-                    stats_tx: worker_stats_tx,
-                };
+                let workload = workload(&dir_entry);
 
                 trace!(entry_disk_path, "queueing entry");
 
@@ -168,14 +266,29 @@ impl Indexable for File {
             }
         };
 
-        let start = std::time::Instant::now();
+        if let Some(Ok((changed, removed))) = diff {
+            info!(
+                ?repo.disk_path,
+                changed = changed.len(),
+                removed = removed.len(),
+                "indexing changes since the last index"
+            );
 
-        if reporef.is_remote() {
-            let walker = GitWalker::open_repository(
-                reporef,
-                &repo.disk_path,
-                repo.branch_filter.as_ref().map(Into::into),
-            )?;
+            // Entries that weren't walked are kept, except for the ones that changed. Their keys
+            // depend on the rules they were indexed with, so they're looked up by path.
+            let skipped = analytics::skipped_keys(&app.sql, reporef, &removed).await?;
+            let indexed = app.indexes.file.stored_keys(reporef, removed)?;
+            cache.keep_all();
+            cache.mark_stale(|keys| {
+                indexed.contains(keys.tantivy())
+                    || skipped.contains(&format!("{}{}", keys.semantic(), keys.tantivy()))
+            });
+
+            let count = changed.len();
+            changed.for_each(pipes, file_worker(count));
+            repo_metadata.incremental.store(true, Ordering::Relaxed);
+        } else if reporef.is_remote() {
+            let walker = GitWalker::open_repository(reporef, &repo.disk_path, branch_filter())?;
             let count = walker.len();
             walker.for_each(pipes, file_worker(count));
         } else {
@@ -214,6 +327,14 @@ impl Indexable for File {
             })
             .await?;
 
+        let incremental = repo_metadata.incremental.load(Ordering::Relaxed);
+        if let Err(err) = stats_gatherer
+            .save(&app.sql, reporef, start.elapsed(), incremental)
+            .await
+        {
+            warn!(?err, "failed to save index stats");
+        }
+
         pipes.index_percent(100);
         Ok(())
     }
@@ -231,6 +352,40 @@ impl Indexable for File {
 }
 
 impl Indexer<File> {
+    /// The keys of the documents of a repository at the given relative paths, which end with a
+    /// `/` for directories.
+    pub(crate) fn stored_keys(
+        &self,
+        reporef: &RepoRef,
+        paths: HashSet<String>,
+    ) -> Result<HashSet<String>> {
+        let searcher = self.reader.searcher();
+        let query = TermQuery::new(
+            Term::from_field_text(self.source.repo_ref, &reporef.to_string()),
+            IndexRecordOption::Basic,
+        );
+
+        let paths = Arc::new(paths);
+        let collector = BytesFilterCollector::new(
+            self.source.raw_relative_path,
+            move |path| std::str::from_utf8(path).is_ok_and(|path| paths.contains(path)),
+            DocSetCollector,
+        );
+
+        searcher
+            .search(&query, &collector)?
+            .into_iter()
+            .map(|addr| -> Result<_> {
+                let doc = searcher.doc(addr)?;
+                Ok(doc
+                    .get_first(self.source.unique_hash)
+                    .and_then(Value::as_text)
+                    .unwrap_or_default()
+                    .to_owned())
+            })
+            .collect()
+    }
+
     pub async fn skim_fuzzy_path_match(
         &self,
         repo_refs: impl IntoIterator<Item = RepoRef>,
@@ -496,7 +651,7 @@ impl File {
         trace!("processing file");
 
         let cache_keys = workload.cache_keys(&dir_entry);
-        let last_commit = workload.repo_metadata.last_commit_unix_secs.unwrap_or(0);
+        let last_commit = workload.last_commit();
 
         match dir_entry {
             _ if workload.cache.is_fresh(&cache_keys) => {
@@ -504,7 +659,7 @@ impl File {
             }
             RepoDirEntry::Dir(dir) => {
                 trace!("writing dir document");
-                let doc = dir.build_document(self, &workload, last_commit as u64, &cache_keys);
+                let doc = dir.build_document(self, &workload, last_commit, &cache_keys);
                 writer.add_document(doc)?;
                 trace!("dir document written");
             }
@@ -515,7 +670,7 @@ impl File {
                         self,
                         &workload,
                         &cache_keys,
-                        last_commit as u64,
+                        last_commit,
                         workload.cache.parent(),
                     )
                     .ok_or(anyhow::anyhow!("failed to build document"))?;
@@ -549,6 +704,69 @@ impl File {
 
         Ok(())
     }
+
+    /// Add the fields which aren't stored back to a document read from another index.
+    ///
+    /// These are all derived from stored fields, except for the last commit time, which is only
+    /// available as a fast field.
+    pub(crate) fn restore_document(
+        &self,
+        doc: &mut tantivy::schema::Document,
+        repo_disk_path: &str,
+        last_commit: u64,
+    ) {
+        let text = |field| {
+            doc.get_first(field)
+                .and_then(Value::as_text)
+                .unwrap_or_default()
+                .to_owned()
+        };
+
+        let content = text(self.content);
+        let symbols = text(self.symbols);
+        let repo_name = text(self.repo_name);
+        let relative_path = text(self.relative_path);
+
+        let is_directory = doc
+            .get_first(self.is_directory)
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+        let indexed = doc
+            .get_first(self.indexed)
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+
+        if indexed && !is_directory {
+            let symbol_kinds = doc
+                .get_first(self.symbol_locations)
+                .and_then(Value::as_bytes)
+                .and_then(|bytes| bincode::deserialize::<SymbolLocations>(bytes).ok())
+                .unwrap_or_default()
+                .list()
+                .into_iter()
+                .map(|sym| sym.kind.to_ascii_lowercase())
+                .collect::<HashSet<_>>();
+
+            for kind in symbol_kinds {
+                doc.add_bytes(self.symbol_kinds, kind.into_bytes());
+            }
+
+            doc.add_text(self.content_words, &content);
+            doc.add_text(self.symbol_words, &symbols);
+        }
+
+        let lines_avg = match content.lines().count() {
+            0 => 0.0,
+            lines => content.len() as f64 / lines as f64,
+        };
+
+        doc.add_text(self.repo_disk_path, repo_disk_path);
+        doc.add_f64(self.avg_line_length, lines_avg);
+        doc.add_u64(self.last_commit_unix_seconds, last_commit);
+        doc.add_bytes(self.raw_content, content.into_bytes());
+        doc.add_bytes(self.raw_repo_name, repo_name.into_bytes());
+        doc.add_bytes(self.raw_relative_path, relative_path.into_bytes());
+    }
 }
 
 impl RepoDir {
@@ -575,8 +793,8 @@ impl RepoDir {
         let branches = self.branches.join("\n");
         let stats = WorkerStats {
             size: self.size(),
-            chunks: 0,
             reindex_count: 1,
+            ..Default::default()
         };
         workload.transmit_stats(stats);
 
@@ -634,17 +852,24 @@ impl RepoFile {
 
         let branches = self.branches.join("\n");
         let explicitly_allowed = file_filter.is_allowed(relative_path);
-        let indexed = explicitly_allowed.unwrap_or_else(|| self.should_index());
+        let skip_reason = match explicitly_allowed {
+            Some(true) => None,
+            Some(false) => Some(SkipReason::Excluded),
+            None => self.skip_reason(),
+        };
         let mut stats = WorkerStats {
             size: self.size(),
             reindex_count: 1,
             ..Default::default()
         };
 
-        if !indexed {
+        if let Some(reason) = skip_reason {
+            workload.transmit_skipped(cache_keys, &relative_path_str, self.size(), reason);
+
             let lang_str = repo_metadata
-                .langs
-                .get(normalized_path, b"")
+                .config
+                .language(relative_path)
+                .or_else(|| repo_metadata.langs.get(normalized_path, b""))
                 .unwrap_or_else(|| {
                     warn!(?normalized_path, "Path not found in language map");
                     ""
@@ -672,16 +897,28 @@ impl RepoFile {
             ));
         }
 
+        // Files which aren't valid UTF-8 are taken to be binary.
         let mut buffer = match self.buffer() {
             Ok(b) => b,
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                workload.transmit_skipped(
+                    cache_keys,
+                    &relative_path_str,
+                    self.size(),
+                    SkipReason::Binary,
+                );
+                return None;
+            }
             Err(err) => {
                 warn!(?err, "failed to open file buffer; skipping file");
                 return None;
             }
         };
+
         let lang_str = repo_metadata
-            .langs
-            .get(normalized_path, buffer.as_ref())
+            .config
+            .language(relative_path)
+            .or_else(|| repo_metadata.langs.get(normalized_path, buffer.as_ref()))
             .unwrap_or_else(|| {
                 warn!(?normalized_path, "Path not found in language map");
                 ""
@@ -700,9 +937,10 @@ impl RepoFile {
             }
         };
 
+        let symbol_list = symbol_locations.list();
+
         // flatten the list of symbols into a string with just text
-        let symbols = symbol_locations
-            .list()
+        let symbols = symbol_list
             .iter()
             .map(|sym| buffer[sym.range.start.byte..sym.range.end.byte].to_owned())
             .collect::<HashSet<_>>()
@@ -710,6 +948,12 @@ impl RepoFile {
             .collect::<Vec<_>>()
             .join("\n");
 
+        // the distinct symbol kinds, for `kind:` filters
+        let symbol_kinds = symbol_list
+            .into_iter()
+            .map(|sym| sym.kind.to_ascii_lowercase())
+            .collect::<HashSet<_>>();
+
         // add an NL if this file is not NL-terminated
         if !buffer.ends_with('\n') {
             buffer += "\n";
@@ -725,6 +969,12 @@ impl RepoFile {
         if !matches!(explicitly_allowed, Some(true))
             && line_end_indices.len() > MAX_LINE_COUNT as usize
         {
+            workload.transmit_skipped(
+                cache_keys,
+                &relative_path_str,
+                self.size(),
+                SkipReason::TooLarge,
+            );
             return None;
         }
 
@@ -749,7 +999,8 @@ impl RepoFile {
         stats.chunks += insert_stats.new;
         workload.transmit_stats(stats);
 
-        Some(doc!(
+        let mut doc = doc!(
+            schema.size => buffer.len() as u64,
             schema.raw_content => buffer.as_bytes(),
             schema.raw_repo_name => repo_name.as_bytes(),
             schema.raw_relative_path => relative_path_str.as_bytes(),
@@ -758,16 +1009,24 @@ impl RepoFile {
             schema.relative_path => relative_path_str,
             schema.repo_ref => repo_ref.to_string(),
             schema.repo_name => *repo_name,
+            schema.content_words => buffer.as_str(),
             schema.content => buffer,
             schema.line_end_indices => line_end_indices,
             schema.lang => lang_str.to_ascii_lowercase().as_bytes(),
             schema.avg_line_length => lines_avg,
             schema.last_commit_unix_seconds => last_commit,
             schema.symbol_locations => bincode::serialize(&symbol_locations).unwrap(),
+            schema.symbol_words => symbols.as_str(),
             schema.symbols => symbols,
             schema.branches => branches,
             schema.is_directory => false,
             schema.indexed => true,
-        ))
+        );
+
+        for kind in symbol_kinds {
+            doc.add_bytes(schema.symbol_kinds, kind.into_bytes());
+        }
+
+        Some(doc)
     }
 }
@@ -1,16 +1,19 @@
-use anyhow::Result;
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
 use async_trait::async_trait;
 use tantivy::{
     schema::{Field, Value},
     Index,
 };
 
-use super::{file::File, repo::Repo, DocumentRead};
+use super::{commit::Commit, file::File, repo::Repo, DocumentRead};
 use crate::{
     intelligence::TreeSitterFile,
     query::{
         compiler::Compiler,
         parser::{self, Query, Target},
+        structural::Pattern,
     },
     repo::RepoRef,
     symbol::SymbolLocations,
@@ -74,6 +77,18 @@ pub struct RepoDocument {
     pub repo_ref: String,
 }
 
+#[derive(Debug)]
+pub struct CommitDocument {
+    pub repo_name: String,
+    pub repo_ref: String,
+    pub commit_id: String,
+    pub author: String,
+    pub date: u64,
+    pub message: String,
+    pub paths: Vec<String>,
+    pub diff: String,
+}
+
 pub struct ContentReader;
 
 #[async_trait]
@@ -86,9 +101,9 @@ impl DocumentRead for ContentReader {
             query,
             Query {
                 open: Some(false) | None,
-                target: Some(Target::Content(..) | Target::Symbol(..)),
+                target: Some(Target::Content(..) | Target::Symbol(..) | Target::Ast(..)),
                 ..
-            }
+            } if !query.is_commit_search()
         )
     }
 
@@ -107,12 +122,21 @@ impl DocumentRead for ContentReader {
             .literal(schema.repo_name, |q| q.repo.clone())
             .literal(schema.branches, |q| q.branch.clone())
             .byte_string(schema.lang, |q| q.lang.as_ref().map(AsRef::as_ref))
+            .byte_string(schema.symbol_kinds, |q| q.kind.as_ref().map(AsRef::as_ref))
             .literal(schema.symbols, |q| {
                 q.target.as_ref().and_then(Target::symbol).cloned()
             })
-            .literal(schema.content, |q| {
-                q.target.as_ref().and_then(Target::content).cloned()
+            .literal(schema.content, |q| match q.target.as_ref()? {
+                // Structural patterns are prefiltered by the code they contain literally.
+                Target::Ast(pattern) => Pattern::parse(pattern).ok().map(|p| p.prefilter()),
+                target => target.content().cloned(),
             })
+            .identifiers(schema.content, Some(schema.content_words))
+            .identifiers(schema.symbols, Some(schema.symbol_words))
+            .range(schema.last_commit_unix_seconds, |q| q.modified_range())
+            .raw(schema.relative_path, schema.raw_relative_path)
+            .raw(schema.repo_name, schema.raw_repo_name)
+            .raw(schema.content, schema.raw_content)
             .compile(queries, tantivy_index)
     }
 
@@ -166,10 +190,11 @@ impl DocumentRead for FileReader {
     fn query_matches(&self, query: &Query<'_>) -> bool {
         matches!(
             query,
-            // Match both language or filename searches. Handles searches like:
+            // Match language, filename or recency searches. Handles searches like:
             //   lang:Rust
             //   path:server
             //   lang:Rust path:server
+            //   modified:<30d
             Query {
                 open: Some(false) | None,
                 target: None,
@@ -180,7 +205,23 @@ impl DocumentRead for FileReader {
                 target: None,
                 path: Some(..),
                 ..
-            }
+            } | Query {
+                open: Some(false) | None,
+                target: None,
+                modified_before: Some(..),
+                ..
+            } | Query {
+                open: Some(false) | None,
+                target: None,
+                modified_after: Some(..),
+                ..
+            } if !query.is_commit_search()
+        ) || (
+            // Or searches that only exclude files, like `-path:test` or `-foo`
+            query.open != Some(true)
+                && query.target.is_none()
+                && !query.excluded.is_empty()
+                && !query.is_commit_search()
         )
     }
 
@@ -198,6 +239,13 @@ impl DocumentRead for FileReader {
             .literal(schema.repo_name, |q| q.repo.clone())
             .literal(schema.branches, |q| q.branch.clone())
             .byte_string(schema.lang, |q| q.lang.as_ref().map(AsRef::as_ref))
+            .range(schema.last_commit_unix_seconds, |q| q.modified_range())
+            // File searches have no content target, but can exclude files by their content.
+            .literal(schema.content, |q| q.target.as_ref()?.content().cloned())
+            .identifiers(schema.content, None)
+            .raw(schema.relative_path, schema.raw_relative_path)
+            .raw(schema.repo_name, schema.raw_repo_name)
+            .raw(schema.content, schema.raw_content)
             .compile(queries, tantivy_index)
     }
 
@@ -237,8 +285,12 @@ impl DocumentRead for RepoReader {
                 repo: Some(..),
                 path: None,
                 target: None,
+                modified_before: None,
+                modified_after: None,
                 ..
-            }
+            } if !query.is_commit_search()
+                // Repos can only be excluded by name.
+                && query.excluded.iter().all(|q| q.repo.is_some())
         )
     }
 
@@ -253,6 +305,7 @@ impl DocumentRead for RepoReader {
     {
         Compiler::new()
             .literal(schema.name, |q| q.repo.clone())
+            .raw(schema.name, schema.raw_name)
             .compile(queries, tantivy_index)
     }
 
@@ -269,6 +322,86 @@ impl DocumentRead for RepoReader {
     }
 }
 
+pub struct CommitReader;
+
+#[async_trait]
+impl DocumentRead for CommitReader {
+    type Document = CommitDocument;
+    type Schema = Commit;
+
+    fn query_matches(&self, query: &Query<'_>) -> bool {
+        query.open != Some(true) && query.is_commit_search()
+    }
+
+    fn compile<'a, I>(
+        &self,
+        schema: &Commit,
+        queries: I,
+        tantivy_index: &Index,
+    ) -> Result<Box<dyn tantivy::query::Query>>
+    where
+        I: Iterator<Item = &'a Query<'a>>,
+    {
+        // Commits are indexed once for all of a repo's branches, without recording which of them
+        // they are on.
+        let queries = queries.collect::<Vec<_>>();
+        if queries.iter().any(|q| q.branch.is_some()) {
+            bail!("commits can't be searched by `branch:`");
+        }
+
+        Compiler::new()
+            .literal(schema.repo_name, |q| q.repo.clone())
+            .literal(schema.paths, |q| q.path.clone())
+            .literal(schema.author, |q| q.author.clone())
+            .literal(schema.content, |q| {
+                q.commit
+                    .clone()
+                    .or_else(|| q.target.as_ref().and_then(Target::content).cloned())
+            })
+            .identifiers(schema.content, None)
+            .raw(schema.repo_name, schema.raw_repo_name)
+            .range(schema.date, |q| {
+                if q.before.is_none() && q.after.is_none() {
+                    return None;
+                }
+
+                Some((
+                    q.after.map(Bound::Included).unwrap_or(Bound::Unbounded),
+                    q.before.map(Bound::Excluded).unwrap_or(Bound::Unbounded),
+                ))
+            })
+            .compile(queries.into_iter(), tantivy_index)
+    }
+
+    fn read_document(&self, schema: &Commit, doc: tantivy::Document) -> Self::Document {
+        let repo_name = read_text_field(&doc, schema.repo_name);
+        let repo_ref = read_text_field(&doc, schema.repo_ref);
+        let commit_id = read_text_field(&doc, schema.commit_id);
+        let author = read_text_field(&doc, schema.author);
+        let date = doc
+            .get_first(schema.date)
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+        let message = read_text_field(&doc, schema.message);
+        let paths = read_text_field(&doc, schema.paths)
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect();
+        let diff = read_text_field(&doc, schema.diff);
+
+        CommitDocument {
+            repo_name,
+            repo_ref,
+            commit_id,
+            author,
+            date,
+            message,
+            paths,
+            diff,
+        }
+    }
+}
+
 pub struct OpenReader;
 
 #[derive(Debug)]
@@ -330,6 +463,8 @@ impl DocumentRead for OpenReader {
                 _ => None,
             })
             .byte_string(schema.lang, |q| q.lang.as_ref().map(AsRef::as_ref))
+            .raw(schema.relative_path, schema.raw_relative_path)
+            .raw(schema.repo_name, schema.raw_repo_name)
             .compile(queries, tantivy_index)
     }
 
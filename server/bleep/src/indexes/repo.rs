@@ -1,13 +1,13 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use tantivy::{doc, schema::Schema, IndexWriter, Term};
+use tantivy::{doc, schema::Schema, Document, IndexWriter, Term};
 use tracing::info;
 
 pub use super::schema::Repo;
 use super::Indexable;
 use crate::{
     background::SyncHandle,
-    repo::{RepoMetadata, Repository},
+    repo::{RepoMetadata, RepoRef, Repository},
 };
 
 impl Default for Repo {
@@ -28,14 +28,7 @@ impl Indexable for Repo {
         // Make sure we delete any stale references to this repository when indexing.
         self.delete_by_repo(writer, repo);
 
-        writer.add_document(doc!(
-            // We don't have organization support for now.
-            self.org => "",
-            self.disk_path => repo.disk_path.to_string_lossy().into_owned(),
-            self.name => reporef.indexed_name(),
-            self.raw_name => reporef.indexed_name().as_bytes(),
-            self.repo_ref => reporef.to_string(),
-        ))?;
+        writer.add_document(self.build_document(reporef, repo))?;
 
         info!(
             ?repo.disk_path,
@@ -56,3 +49,16 @@ impl Indexable for Repo {
         self.schema.clone()
     }
 }
+
+impl Repo {
+    pub(crate) fn build_document(&self, reporef: &RepoRef, repo: &Repository) -> Document {
+        doc!(
+            // We don't have organization support for now.
+            self.org => "",
+            self.disk_path => repo.disk_path.to_string_lossy().into_owned(),
+            self.name => reporef.indexed_name(),
+            self.raw_name => reporef.indexed_name().as_bytes(),
+            self.repo_ref => reporef.to_string(),
+        )
+    }
+}
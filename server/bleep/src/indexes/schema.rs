@@ -6,6 +6,8 @@ use tantivy::schema::{
     FAST, INDEXED, STORED, STRING, TEXT,
 };
 
+use super::tokenizer::CODE_TOKENIZER;
+
 #[cfg(feature = "debug")]
 use {
     histogram::Histogram,
@@ -47,6 +49,15 @@ pub struct File {
     pub symbols: Field,
     pub symbol_locations: Field,
 
+    /// the words of identifiers in `content` and `symbols`, split by the code tokenizer, e.g.:
+    /// ["retrypolicy", "retry", "policy"]
+    pub content_words: Field,
+    pub symbol_words: Field,
+
+    /// the distinct kinds of symbols defined in this file, as classified
+    /// by the language's namespaces, e.g.: ["function", "struct"]
+    pub symbol_kinds: Field,
+
     /// fast fields for scoring
     pub lang: Field,
     pub avg_line_length: Field,
@@ -65,6 +76,9 @@ pub struct File {
 
     /// Whether this entry is a file or a directory
     pub is_directory: Field,
+
+    /// Size of the indexed contents in bytes, for stats that shouldn't read the contents
+    pub size: Field,
 }
 
 impl File {
@@ -90,9 +104,19 @@ impl File {
         let symbols = builder.add_text_field("symbols", trigram.clone());
         let symbol_locations =
             builder.add_bytes_field("symbol_locations", BytesOptions::default().set_stored());
+        let symbol_kinds =
+            builder.add_bytes_field("symbol_kinds", BytesOptions::default().set_indexed());
 
         let branches = builder.add_text_field("branches", trigram);
 
+        let words = TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(CODE_TOKENIZER)
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+        );
+        let content_words = builder.add_text_field("content_words", words.clone());
+        let symbol_words = builder.add_text_field("symbol_words", words);
+
         let lang = builder.add_bytes_field(
             "lang",
             BytesOptions::default().set_stored().set_indexed() | FAST,
@@ -105,7 +129,8 @@ impl File {
         let raw_relative_path = builder.add_bytes_field("raw_relative_path", FAST);
 
         let is_directory = builder.add_bool_field("is_directory", FAST | STORED);
-        let indexed = builder.add_bool_field("indexed", STORED);
+        let indexed = builder.add_bool_field("indexed", FAST | STORED);
+        let size = builder.add_u64_field("size", FAST | STORED);
 
         Self {
             schema: builder.build(),
@@ -118,6 +143,9 @@ impl File {
             line_end_indices,
             symbols,
             symbol_locations,
+            content_words,
+            symbol_words,
+            symbol_kinds,
             lang,
             avg_line_length,
             last_commit_unix_seconds,
@@ -127,6 +155,7 @@ impl File {
             branches,
             is_directory,
             indexed,
+            size,
 
             #[cfg(feature = "debug")]
             histogram: Arc::new(Histogram::builder().build().unwrap().into()),
@@ -193,6 +222,111 @@ impl Repo {
     }
 }
 
+/// A schema for indexing the commit history of a repository, with one
+/// document per commit.
+#[derive(Clone)]
+pub struct Commit {
+    pub(super) schema: Schema,
+
+    /// Unique ID for the commit in a repo
+    pub unique_hash: Field,
+
+    /// Path to the root of the repo on disk
+    pub repo_disk_path: Field,
+
+    /// Unique repo identifier, of the form:
+    ///  local: local//path/to/repo
+    /// github: github.com/org/repo
+    pub repo_ref: Field,
+
+    /// Indexed repo name, of the form:
+    ///  local: repo
+    /// github: github.com/org/repo
+    pub repo_name: Field,
+    pub raw_repo_name: Field,
+
+    /// Full hex id of the commit
+    pub commit_id: Field,
+
+    /// Author of the commit, of the form `Name <email>`
+    pub author: Field,
+
+    /// Author timestamp, in seconds since the unix epoch
+    pub date: Field,
+
+    /// Full commit message
+    pub message: Field,
+
+    /// Paths touched by the commit, separated by newlines
+    pub paths: Field,
+
+    /// Unified diff of the commit against its first parent
+    pub diff: Field,
+
+    /// The message and the diff, for searching
+    pub content: Field,
+}
+
+impl Commit {
+    pub fn new() -> Self {
+        let mut builder = SchemaBuilder::new();
+        let trigram_indexing = TextFieldIndexing::default()
+            .set_tokenizer("default")
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+        let trigram = TextOptions::default()
+            .set_stored()
+            .set_indexing_options(trigram_indexing.clone());
+
+        let unique_hash = builder.add_text_field("unique_hash", STRING | STORED);
+
+        let repo_disk_path = builder.add_text_field("repo_disk_path", STRING);
+        let repo_ref = builder.add_text_field("repo_ref", STRING | STORED);
+        let repo_name = builder.add_text_field("repo_name", STRING | STORED);
+        let raw_repo_name = builder.add_bytes_field("raw_repo_name", FAST);
+
+        let commit_id = builder.add_text_field("commit_id", STRING | STORED | FAST);
+        let author = builder.add_text_field("author", trigram.clone());
+        let date = builder.add_u64_field("date", INDEXED | FAST | STORED);
+
+        let message = builder.add_text_field("message", STORED);
+        let paths = builder.add_text_field("paths", trigram.clone());
+        let diff = builder.add_text_field("diff", STORED);
+
+        // The message and diff are already stored on their own, so the
+        // searchable field doesn't need to be.
+        let content = builder.add_text_field(
+            "content",
+            TextOptions::default().set_indexing_options(trigram_indexing),
+        );
+
+        Self {
+            schema: builder.build(),
+            unique_hash,
+            repo_disk_path,
+            repo_ref,
+            repo_name,
+            raw_repo_name,
+            commit_id,
+            author,
+            date,
+            message,
+            paths,
+            diff,
+            content,
+        }
+    }
+
+    pub fn schema(&self) -> Schema {
+        self.schema.clone()
+    }
+}
+
+impl Default for Commit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Clone)]
 pub struct Section {
     pub(super) schema: Schema,
@@ -0,0 +1,197 @@
+//! A tokenizer for source code, which splits identifiers into their words.
+//!
+//! `RetryPolicy`, `retryPolicy`, `retry_policy` and `retry-policy` are all split into `retry` and
+//! `policy`, at consecutive positions, so that a phrase query for `retry policy` matches each of
+//! them. The whole identifier is kept as a token too, at the position of its first word.
+
+use tantivy::tokenizer::{Token, TokenStream, Tokenizer};
+
+/// Name under which the tokenizer is registered with each index.
+pub const CODE_TOKENIZER: &str = "code";
+
+#[derive(Clone, Default)]
+pub struct CodeTokenizer;
+
+pub struct CodeTokenStream {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Tokenizer for CodeTokenizer {
+    type TokenStream<'a> = CodeTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        let mut tokens = Vec::new();
+        let mut position = 0;
+
+        for (offset, identifier) in identifiers(text) {
+            let words = word_indices(identifier);
+            if words.is_empty() {
+                continue;
+            }
+
+            if words.len() > 1 {
+                tokens.push(token(offset, identifier, position));
+            }
+
+            for (word_offset, word) in words {
+                tokens.push(token(offset + word_offset, word, position));
+                position += 1;
+            }
+        }
+
+        CodeTokenStream { tokens, index: 0 }
+    }
+}
+
+impl TokenStream for CodeTokenStream {
+    fn advance(&mut self) -> bool {
+        // `index` points one past the current token, so that `token` is valid after `advance`.
+        if self.index < self.tokens.len() {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.index - 1]
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.tokens[self.index - 1]
+    }
+}
+
+fn token(offset: usize, text: &str, position: usize) -> Token {
+    Token {
+        offset_from: offset,
+        offset_to: offset + text.len(),
+        position,
+        text: text.to_owned(),
+        position_length: 1,
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Find the identifiers in a piece of text, along with their byte offsets.
+fn identifiers(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut rest = text.char_indices();
+
+    std::iter::from_fn(move || {
+        let (start, _) = rest.find(|(_, c)| is_identifier_char(*c))?;
+        let mut end = text.len();
+
+        for (i, c) in rest.by_ref() {
+            if !is_identifier_char(c) {
+                end = i;
+                break;
+            }
+        }
+
+        Some((start, &text[start..end]))
+    })
+}
+
+/// Split an identifier into its words, on `_` and `-`, and where its case changes.
+///
+/// A run of capitals is kept together, except for a final capital which starts a new word:
+/// `HTTPServer` is split into `HTTP` and `Server`. Digits belong to the word before them.
+pub fn split_identifier(identifier: &str) -> impl Iterator<Item = &str> {
+    word_indices(identifier).into_iter().map(|(_, word)| word)
+}
+
+/// Split an identifier like `split_identifier`, along with the byte offset of each word.
+fn word_indices(identifier: &str) -> Vec<(usize, &str)> {
+    let chars = identifier.char_indices().collect::<Vec<_>>();
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (n, &(i, c)) in chars.iter().enumerate() {
+        if c == '_' || c == '-' {
+            if let Some(s) = start.take() {
+                words.push((s, &identifier[s..i]));
+            }
+            continue;
+        }
+
+        let prev = n.checked_sub(1).map(|p| chars[p].1);
+        let next = chars.get(n + 1).map(|&(_, c)| c);
+
+        let boundary = c.is_uppercase()
+            && match prev {
+                Some(p) if p.is_lowercase() || p.is_numeric() => true,
+                Some(p) if p.is_uppercase() => next.map_or(false, char::is_lowercase),
+                _ => false,
+            };
+
+        match start {
+            Some(s) if boundary => {
+                words.push((s, &identifier[s..i]));
+                start = Some(i);
+            }
+            None => start = Some(i),
+            _ => {}
+        }
+    }
+
+    if let Some(s) = start {
+        words.push((s, &identifier[s..]));
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(identifier: &str) -> Vec<&str> {
+        split_identifier(identifier).collect()
+    }
+
+    #[test]
+    fn split_identifiers() {
+        assert_eq!(split("RetryPolicy"), ["Retry", "Policy"]);
+        assert_eq!(split("retryPolicy"), ["retry", "Policy"]);
+        assert_eq!(split("retry_policy"), ["retry", "policy"]);
+        assert_eq!(split("RETRY_POLICY"), ["RETRY", "POLICY"]);
+        assert_eq!(split("retry-policy"), ["retry", "policy"]);
+        assert_eq!(split("HTTPServer"), ["HTTP", "Server"]);
+        assert_eq!(split("http2Client"), ["http2", "Client"]);
+        assert_eq!(split("__init__"), ["init"]);
+        assert_eq!(split("retry"), ["retry"]);
+        assert!(split("_").is_empty());
+    }
+
+    #[test]
+    fn tokenize() {
+        let mut tokenizer = CodeTokenizer;
+        let mut stream = tokenizer.token_stream("let p = RetryPolicy::new(max_retries);");
+
+        let mut tokens = Vec::new();
+        while stream.advance() {
+            let token = stream.token();
+            tokens.push((token.text.as_str(), token.position, token.offset_from));
+        }
+
+        assert_eq!(
+            tokens,
+            [
+                ("let", 0, 0),
+                ("p", 1, 4),
+                ("RetryPolicy", 2, 8),
+                ("Retry", 2, 8),
+                ("Policy", 3, 13),
+                ("new", 4, 21),
+                ("max_retries", 5, 25),
+                ("max", 5, 25),
+                ("retries", 6, 29),
+            ]
+        );
+    }
+}
@@ -9,6 +9,9 @@ pub use {
     scope_resolution::{NodeKind, ScopeGraph},
 };
 
+use std::ops::Range;
+
+use crate::query::structural::Pattern;
 use scope_resolution::ResolutionMethod;
 use tree_sitter::{Parser, Tree};
 
@@ -82,6 +85,16 @@ impl<'a> TreeSitterFile<'a> {
             .collect::<Vec<_>>())
     }
 
+    /// Find the byte ranges of all nodes matching a structural search pattern.
+    pub fn structural_matches(
+        self,
+        pattern: &Pattern,
+    ) -> Result<Vec<Range<usize>>, TreeSitterFileError> {
+        pattern
+            .find(self.tree.root_node(), self.src, (self.language.grammar)())
+            .map_err(TreeSitterFileError::QueryError)
+    }
+
     /// Produce a lexical scope-graph for this TreeSitterFile.
     pub fn scope_graph(self) -> Result<ScopeGraph, TreeSitterFileError> {
         let query = self
@@ -1,5 +1,6 @@
 //! Handlers for code-navigation:
 //! - scope-graph based handler that operates only in the owning file
+//! - import based handler that follows imports to the exporting file
 //! - search based handler that operates on any file belonging to the repo
 
 use std::{collections::HashSet, ops::Not};
@@ -15,6 +16,8 @@ use crate::{
 use rayon::prelude::*;
 use serde::Serialize;
 
+mod imports;
+
 #[derive(Debug, Serialize)]
 pub struct FileSymbols {
     /// The file to which the following occurrences belong
@@ -211,7 +214,10 @@ impl<'a, 'b> CodeNavigationContext<'a, 'b> {
             let local_definitions = self.local_definitions();
             let repo_wide_definitions = local_definitions
                 .is_none()
-                .then(|| self.repo_wide_definitions())
+                .then(|| {
+                    self.imported_definitions()
+                        .unwrap_or_else(|| self.repo_wide_definitions())
+                })
                 .unwrap_or_default();
 
             let local_references = self.local_references();
@@ -231,14 +237,17 @@ impl<'a, 'b> CodeNavigationContext<'a, 'b> {
                 .collect()
         } else if self.is_import() {
             let local_references = self.local_references();
-            let repo_wide_definitions = self.repo_wide_definitions();
+            let repo_wide_definitions = self
+                .imported_definitions()
+                .unwrap_or_else(|| self.repo_wide_definitions());
 
             repo_wide_definitions
                 .into_iter()
                 .chain(local_references)
                 .collect()
         } else {
-            Vec::new()
+            // Names that aren't part of the scope graph, such as `Name` in Go's `pkg.Name`
+            self.imported_definitions().unwrap_or_default()
         }
     }
 
@@ -332,36 +341,77 @@ impl<'a, 'b> CodeNavigationContext<'a, 'b> {
     fn repo_wide_definitions(&self) -> Vec<FileSymbols> {
         self.non_source_documents()
             .par_bridge()
-            .filter_map(|doc| {
-                let scope_graph = doc.symbol_locations.scope_graph()?;
-                let content = doc.content.as_bytes();
-                let mut data = scope_graph
-                    .graph
-                    .node_indices()
-                    .filter(|idx| scope_graph.is_top_level(*idx))
-                    .filter(|idx| {
-                        if let Some(NodeKind::Def(d)) = scope_graph.get_node(*idx) {
-                            d.name(content) == self.active_token_text().as_bytes()
-                        } else {
-                            false
-                        }
-                    })
-                    .map(|idx| Occurrence {
-                        kind: OccurrenceKind::Definition,
-                        range: scope_graph.graph[idx].range(),
-                        snippet: to_occurrence(doc, scope_graph.graph[idx].range(), self.snipper),
-                    })
-                    .collect::<Vec<_>>();
+            .filter_map(|doc| self.top_level_definitions(doc, self.active_token_text()))
+            .collect()
+    }
 
-                data.sort_by_key(|occurrence| occurrence.range.start.byte);
+    /// Definitions in the files that the active token is imported from.
+    ///
+    /// Returns `None` if the import can't be resolved to any definition, so callers can fall
+    /// back to a name-based lookup.
+    fn imported_definitions(&self) -> Option<Vec<FileSymbols>> {
+        let source = self.source_document();
+        let lang = source.lang.as_deref()?;
+
+        // References point to the import node that introduced them
+        let range = source
+            .symbol_locations
+            .scope_graph()
+            .and_then(|sg| {
+                let idx = sg.node_by_range(self.token.start_byte, self.token.end_byte)?;
+                let import = sg.imports(idx).next()?;
+                let range = sg.graph[import].range();
+                Some(range.start.byte..range.end.byte)
+            })
+            .unwrap_or_else(|| self.active_token_range());
+
+        let import = imports::parse(lang, &source.content, range)?;
+        let candidates = imports::resolve(
+            &source.relative_path,
+            &import.module,
+            self.non_source_documents()
+                .map(|doc| doc.relative_path.as_str()),
+        );
+
+        candidates.into_iter().find_map(|group| {
+            let definitions = self
+                .non_source_documents()
+                .filter(|doc| group.contains(&doc.relative_path.as_str()))
+                .filter_map(|doc| self.top_level_definitions(doc, &import.name))
+                .collect::<Vec<_>>();
+
+            definitions.is_empty().not().then_some(definitions)
+        })
+    }
 
-                data.is_empty().not().then(|| FileSymbols {
-                    file: doc.relative_path.to_owned(),
-                    repo: doc.repo_ref.parse().unwrap(),
-                    data,
-                })
+    fn top_level_definitions(&self, doc: &ContentDocument, name: &str) -> Option<FileSymbols> {
+        let scope_graph = doc.symbol_locations.scope_graph()?;
+        let content = doc.content.as_bytes();
+        let mut data = scope_graph
+            .graph
+            .node_indices()
+            .filter(|idx| scope_graph.is_top_level(*idx))
+            .filter(|idx| {
+                if let Some(NodeKind::Def(d)) = scope_graph.get_node(*idx) {
+                    d.name(content) == name.as_bytes()
+                } else {
+                    false
+                }
             })
-            .collect()
+            .map(|idx| Occurrence {
+                kind: OccurrenceKind::Definition,
+                range: scope_graph.graph[idx].range(),
+                snippet: to_occurrence(doc, scope_graph.graph[idx].range(), self.snipper),
+            })
+            .collect::<Vec<_>>();
+
+        data.sort_by_key(|occurrence| occurrence.range.start.byte);
+
+        data.is_empty().not().then(|| FileSymbols {
+            file: doc.relative_path.to_owned(),
+            repo: doc.repo_ref.parse().unwrap(),
+            data,
+        })
     }
 
     fn local_references(&self) -> Option<FileSymbols> {
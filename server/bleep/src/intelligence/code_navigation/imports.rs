@@ -0,0 +1,689 @@
+//! Import-aware resolution of cross-file definitions.
+//!
+//! Import nodes in the scope graph only cover the imported name, so the module it comes from is
+//! recovered from the surrounding import statement. That module is then resolved to files in the
+//! repository, using the conventions of each language:
+//!
+//! - Rust: `use crate::a::b::Item` looks in `src/a/b.rs`, `src/a/b/mod.rs`, then `src/a.rs`, ...
+//! - Python: `from .a.b import item` looks in `a/b.py` or `a/b/__init__.py`
+//! - JS/TS: `import { item } from './a/b'` looks in `a/b.ts`, `a/b/index.js`, ...
+//! - Go: `pkg.Item` looks in every file of the directory matching the import path of `pkg`
+
+use std::ops::Range;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// A name imported from another module.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Import {
+    /// The name of the definition in the exporting module.
+    ///
+    /// This differs from the name in the importing file if the import was renamed with `as`.
+    pub name: String,
+    pub module: Module,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Module {
+    /// Path segments, including a leading `crate`, `self` or `super`
+    Rust(Vec<String>),
+    /// Number of leading dots, and the dotted path
+    Python { level: usize, segments: Vec<String> },
+    /// The module specifier, e.g. `./a/b` or `@/a/b`
+    Js(String),
+    /// The import path of the package
+    Go(String),
+}
+
+/// Find the import that introduces the name at `range` in `src`.
+///
+/// For Go, `range` is the selected name in `pkg.Name`, as Go imports whole packages.
+pub(super) fn parse(lang: &str, src: &str, range: Range<usize>) -> Option<Import> {
+    src.get(range.clone())?;
+    match lang {
+        "Rust" => rust_import(src, range),
+        "Python" => python_import(src, range),
+        "JavaScript" | "JSX" | "TypeScript" | "TSX" => js_import(src, range),
+        "Go" => go_import(src, range),
+        _ => None,
+    }
+    .filter(|import| !import.name.is_empty())
+}
+
+/// Resolve the module of an import to files among `paths`.
+///
+/// Returns groups of equally good candidates, best first.
+pub(super) fn resolve<'p>(
+    source_path: &str,
+    module: &Module,
+    paths: impl Iterator<Item = &'p str>,
+) -> Vec<Vec<&'p str>> {
+    let paths = paths.collect::<Vec<_>>();
+    let exact = |candidates: Vec<String>| {
+        candidates
+            .iter()
+            .filter_map(|c| paths.iter().find(|p| **p == c.as_str()).copied())
+            .collect::<Vec<_>>()
+    };
+
+    match module {
+        Module::Rust(segments) => rust_module_dirs(source_path, segments, &paths)
+            .into_iter()
+            .map(&exact)
+            .filter(|group| !group.is_empty())
+            .collect(),
+
+        Module::Python { level: 0, segments } => {
+            let module = segments.join("/");
+            let mut found = paths
+                .iter()
+                .copied()
+                .filter(|p| {
+                    ends_with_path(p, &format!("{module}.py"))
+                        || ends_with_path(p, &format!("{module}/__init__.py"))
+                })
+                .collect::<Vec<_>>();
+
+            found.sort_by_key(|p| std::cmp::Reverse(common_prefix(p, source_path)));
+            found.into_iter().map(|p| vec![p]).collect()
+        }
+
+        Module::Python { level, segments } => {
+            let mut dir = parent(source_path);
+            for _ in 1..*level {
+                dir = parent(dir);
+            }
+
+            let module = join(dir, &segments.join("/"));
+            let group = exact(vec![
+                format!("{module}.py"),
+                format!("{module}/__init__.py"),
+            ]);
+
+            [group].into_iter().filter(|g| !g.is_empty()).collect()
+        }
+
+        Module::Js(specifier) => {
+            let group = if specifier.starts_with('.') {
+                let Some(module) = normalize(&join(parent(source_path), specifier)) else {
+                    return vec![];
+                };
+
+                exact(js_module_files(&module))
+            } else {
+                // Path aliases like `@/a/b` usually point somewhere into the repository, so
+                // match the rest of the specifier against the end of the paths.
+                let specifier = specifier.trim_start_matches("@/").trim_start_matches("~/");
+
+                let mut found = paths
+                    .iter()
+                    .copied()
+                    .filter(|p| {
+                        js_module_files(specifier)
+                            .iter()
+                            .any(|c| ends_with_path(p, c))
+                    })
+                    .collect::<Vec<_>>();
+
+                found.sort_by_key(|p| std::cmp::Reverse(common_prefix(p, source_path)));
+                found.truncate(1);
+                found
+            };
+
+            [group].into_iter().filter(|g| !g.is_empty()).collect()
+        }
+
+        Module::Go(import_path) => {
+            // The package is the longest directory that is a suffix of the import path.
+            let package = paths
+                .iter()
+                .filter(|p| p.ends_with(".go"))
+                .map(|p| parent(p))
+                .filter(|dir| {
+                    !dir.is_empty()
+                        && (import_path.as_str() == *dir
+                            || import_path.ends_with(&format!("/{dir}")))
+                })
+                .max_by_key(|dir| dir.len());
+
+            let Some(package) = package else {
+                return vec![];
+            };
+
+            let group = paths
+                .iter()
+                .copied()
+                .filter(|p| p.ends_with(".go") && parent(p) == package)
+                .collect::<Vec<_>>();
+
+            vec![group]
+        }
+    }
+}
+
+fn rust_import(src: &str, range: Range<usize>) -> Option<Import> {
+    let before = &src[..range.start];
+    let start = before
+        .match_indices("use")
+        .map(|(i, _)| i)
+        .filter(|&i| is_keyword_at(before, i, "use"))
+        .last()?;
+
+    let tree = &before[start + "use".len()..];
+    if tree.contains(';') {
+        return None;
+    }
+
+    // Walk the use tree up to the imported name, keeping track of the path prefix of each
+    // nested group, e.g. `a::{b::{c, d}, ` leaves us with `a`.
+    let mut path = Vec::new();
+    let mut groups = Vec::new();
+    let mut renamed = false;
+
+    let mut chars = tree.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' => {
+                groups.push(path.len());
+                renamed = false;
+            }
+            ',' => {
+                path.truncate(groups.last().copied().unwrap_or(0));
+                renamed = false;
+            }
+            '}' => {
+                groups.pop();
+                path.truncate(groups.last().copied().unwrap_or(0));
+                renamed = false;
+            }
+            c if is_ident_char(c) => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, c)) = chars.peek().copied() {
+                    if !is_ident_char(c) {
+                        break;
+                    }
+                    end = j + c.len_utf8();
+                    chars.next();
+                }
+
+                match &tree[i..end] {
+                    "as" => renamed = true,
+                    ident => path.push(ident.trim_start_matches("r#").to_owned()),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let name = if renamed {
+        path.pop()?
+    } else {
+        src[range].to_owned()
+    };
+
+    // `use foo;` imports a crate, which we don't resolve
+    if path.is_empty() {
+        return None;
+    }
+
+    Some(Import {
+        name,
+        module: Module::Rust(path),
+    })
+}
+
+fn python_import(src: &str, range: Range<usize>) -> Option<Import> {
+    let before = &src[..range.start];
+    let start = before
+        .match_indices("from")
+        .map(|(i, _)| i)
+        .filter(|&i| is_keyword_at(before, i, "from"))
+        .filter(|&i| {
+            // only at the start of a line
+            before[..i]
+                .rsplit('\n')
+                .next()
+                .map_or(true, |line| line.trim().is_empty())
+        })
+        .last()?;
+
+    let statement = &before[start + "from".len()..];
+
+    // Make sure we're still in the same statement: line breaks are only allowed within
+    // parentheses or after a backslash.
+    let mut depth = 0i32;
+    let mut prev = ' ';
+    for c in statement.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '\n' if depth <= 0 && prev != '\\' => return None,
+            _ => {}
+        }
+        prev = c;
+    }
+
+    let (module, names) = statement.split_once(" import")?;
+    let module = module.trim();
+    if module.is_empty() || module.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let level = module.chars().take_while(|c| *c == '.').count();
+    let segments = module[level..]
+        .split('.')
+        .filter(|s| !s.is_empty())
+        .map(ToOwned::to_owned)
+        .collect();
+
+    let name = renamed_from(names, " as").unwrap_or_else(|| src[range].to_owned());
+
+    Some(Import {
+        name,
+        module: Module::Python { level, segments },
+    })
+}
+
+fn js_import(src: &str, range: Range<usize>) -> Option<Import> {
+    static SPECIFIER: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"^[^;]*?(?:\bfrom\s*|\brequire\s*\(\s*)['"]([^'"\n]+)['"]"#).unwrap()
+    });
+
+    let before = &src[..range.start];
+
+    // `import * as ns from 'module'` imports the whole module
+    if before.trim_end().ends_with("* as") {
+        return None;
+    }
+
+    let specifier = SPECIFIER.captures(&src[range.end..])?.get(1)?.as_str();
+    let name = renamed_from(before, " as").unwrap_or_else(|| src[range].to_owned());
+
+    Some(Import {
+        name,
+        module: Module::Js(specifier.to_owned()),
+    })
+}
+
+fn go_import(src: &str, range: Range<usize>) -> Option<Import> {
+    static IMPORT_SPEC: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"(?m)^\s*(?:import\s+)?(?:([A-Za-z_]\w*)\s+)?"([^"\n]+)"\s*$"#).unwrap()
+    });
+
+    let package = src[..range.start].strip_suffix('.')?;
+    let package = &package[package
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_ident_char(*c))
+        .last()?
+        .0..];
+
+    // imports come before any declaration
+    let header = src.find("\nfunc ").map_or(src, |end| &src[..end]);
+    let import_path = IMPORT_SPEC.captures_iter(header).find_map(|c| {
+        let path = c.get(2)?.as_str();
+        let name = match c.get(1) {
+            Some(alias) => alias.as_str(),
+            None => go_package_name(path),
+        };
+
+        (name == package).then(|| path.to_owned())
+    })?;
+
+    Some(Import {
+        name: src[range].to_owned(),
+        module: Module::Go(import_path),
+    })
+}
+
+/// The default package name of a Go import path
+fn go_package_name(path: &str) -> &str {
+    let mut segments = path.rsplit('/');
+    let last = segments.next().unwrap_or(path);
+
+    // major versions, `example.com/pkg/v2`
+    let last = match segments.next() {
+        Some(prev)
+            if last.len() > 1
+                && last.starts_with('v')
+                && last[1..].chars().all(|c| c.is_ascii_digit()) =>
+        {
+            prev
+        }
+        _ => last,
+    };
+
+    // `gopkg.in/yaml.v3`
+    last.split('.').next().unwrap_or(last)
+}
+
+/// If `prefix` ends with `original as`, return `original`.
+fn renamed_from(prefix: &str, keyword: &str) -> Option<String> {
+    let original = prefix.trim_end().strip_suffix(keyword)?.trim_end();
+    let start = original
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_ident_char(*c))
+        .last()?
+        .0;
+
+    Some(original[start..].to_owned())
+}
+
+/// Candidate files of a Rust module path, for successively shorter prefixes of the path.
+///
+/// The last segments may name items rather than modules, as in `crate::a::Item::Variant`.
+fn rust_module_dirs(source_path: &str, segments: &[String], paths: &[&str]) -> Vec<Vec<String>> {
+    let (dir, rest) = match segments.first().map(String::as_str) {
+        Some("crate") => (crate_root(source_path), &segments[1..]),
+        Some("self") => (module_dir(source_path), &segments[1..]),
+        Some("super") => {
+            let mut dir = module_dir(source_path);
+            let supers = segments.iter().take_while(|s| *s == "super").count();
+            for _ in 0..supers {
+                dir = parent(dir);
+            }
+            (dir, &segments[supers..])
+        }
+        Some(krate) => {
+            // Another crate in the same repository
+            let lib = [krate.to_owned(), krate.replace('_', "-")]
+                .into_iter()
+                .find_map(|name| {
+                    paths
+                        .iter()
+                        .find(|p| ends_with_path(p, &format!("{name}/src/lib.rs")))
+                        .map(|p| parent(p))
+                });
+
+            match lib {
+                Some(dir) => (dir, &segments[1..]),
+                // 2015 edition paths are relative to the crate root
+                None => (crate_root(source_path), segments),
+            }
+        }
+        None => return vec![],
+    };
+
+    (0..=rest.len())
+        .rev()
+        .map(|len| {
+            let module = join(dir, &rest[..len].join("/"));
+            if len == 0 {
+                vec![
+                    format!("{module}.rs"),
+                    join(&module, "mod.rs"),
+                    join(&module, "lib.rs"),
+                    join(&module, "main.rs"),
+                ]
+            } else {
+                vec![format!("{module}.rs"), format!("{module}/mod.rs")]
+            }
+        })
+        .collect()
+}
+
+/// The `src` directory the file belongs to, or the directory of the file.
+fn crate_root(source_path: &str) -> &str {
+    let mut dir = parent(source_path);
+    while !dir.is_empty() {
+        if dir == "src" || dir.ends_with("/src") {
+            return dir;
+        }
+        dir = parent(dir);
+    }
+
+    parent(source_path)
+}
+
+/// The directory containing the submodules of the module defined by the file.
+fn module_dir(source_path: &str) -> &str {
+    match source_path.rsplit('/').next() {
+        Some("mod.rs" | "lib.rs" | "main.rs") => parent(source_path),
+        _ => source_path.trim_end_matches(".rs"),
+    }
+}
+
+fn js_module_files(module: &str) -> Vec<String> {
+    const EXTENSIONS: &[&str] = &["ts", "tsx", "d.ts", "js", "jsx", "mjs", "cjs"];
+
+    let module = module.trim_end_matches('/');
+    std::iter::once(module.to_owned())
+        .chain(EXTENSIONS.iter().map(|ext| format!("{module}.{ext}")))
+        .chain(EXTENSIONS.iter().map(|ext| format!("{module}/index.{ext}")))
+        .collect()
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+fn join(dir: &str, path: &str) -> String {
+    match (dir.is_empty(), path.is_empty()) {
+        (true, _) => path.to_owned(),
+        (_, true) => dir.to_owned(),
+        _ => format!("{dir}/{path}"),
+    }
+}
+
+/// Resolve `.` and `..` components, failing on paths outside the repository.
+fn normalize(path: &str) -> Option<String> {
+    let mut out = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                out.pop()?;
+            }
+            c => out.push(c),
+        }
+    }
+
+    Some(out.join("/"))
+}
+
+fn ends_with_path(path: &str, suffix: &str) -> bool {
+    path == suffix || path.ends_with(&format!("/{suffix}"))
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.split('/')
+        .zip(b.split('/'))
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '#'
+}
+
+fn is_keyword_at(text: &str, i: usize, keyword: &str) -> bool {
+    let prev = text[..i].chars().next_back();
+    let next = text[i + keyword.len()..].chars().next();
+    !prev.is_some_and(is_ident_char) && next.is_some_and(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import_of(lang: &str, src: &str, name: &str) -> Option<Import> {
+        let start = src.rfind(name).unwrap();
+        parse(lang, src, start..start + name.len())
+    }
+
+    fn rust(segments: &[&str]) -> Module {
+        Module::Rust(segments.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn rust_use_trees() {
+        let import = |src, name| import_of("Rust", src, name).unwrap();
+
+        assert_eq!(
+            import("use crate::a::b::Item;", "Item").module,
+            rust(&["crate", "a", "b"])
+        );
+        assert_eq!(
+            import("use crate::a::{b::{One, Two}, Three};", "Three").module,
+            rust(&["crate", "a"])
+        );
+        assert_eq!(
+            import("use crate::a::{b::{One, Two}, Three};", "Two").module,
+            rust(&["crate", "a", "b"])
+        );
+        assert_eq!(
+            import("pub(crate) use super::a::Item as Renamed;", "Renamed"),
+            Import {
+                name: "Item".into(),
+                module: rust(&["super", "a"]),
+            }
+        );
+        assert_eq!(import_of("Rust", "use anyhow;", "anyhow"), None);
+    }
+
+    #[test]
+    fn python_from_imports() {
+        assert_eq!(
+            import_of(
+                "Python",
+                "from ..a.b import (\n    one,\n    two,\n)",
+                "two"
+            )
+            .unwrap(),
+            Import {
+                name: "two".into(),
+                module: Module::Python {
+                    level: 2,
+                    segments: vec!["a".into(), "b".into()],
+                },
+            }
+        );
+        assert_eq!(
+            import_of("Python", "from a import one\nprint(one)", "one"),
+            None
+        );
+    }
+
+    #[test]
+    fn js_imports() {
+        let src = "import Default, { one as two } from './a/b';\nimport * as ns from 'c';";
+        assert_eq!(
+            import_of("TypeScript", src, "two").unwrap(),
+            Import {
+                name: "one".into(),
+                module: Module::Js("./a/b".into()),
+            }
+        );
+        assert_eq!(
+            import_of("JavaScript", src, "Default").unwrap().module,
+            Module::Js("./a/b".into())
+        );
+        assert_eq!(import_of("JavaScript", src, "ns"), None);
+        assert_eq!(
+            import_of("JavaScript", "const { one } = require(\"../x\")", "one")
+                .unwrap()
+                .module,
+            Module::Js("../x".into())
+        );
+    }
+
+    #[test]
+    fn go_packages() {
+        let src = r#"package main
+
+import (
+	"fmt"
+	yaml "gopkg.in/yaml.v3"
+	"example.com/repo/internal/store/v2"
+)
+
+func main() {
+	store.Open()
+	yaml.Marshal()
+}
+"#;
+
+        assert_eq!(
+            import_of("Go", src, "Open").unwrap().module,
+            Module::Go("example.com/repo/internal/store/v2".into())
+        );
+        assert_eq!(
+            import_of("Go", src, "Marshal").unwrap().module,
+            Module::Go("gopkg.in/yaml.v3".into())
+        );
+        assert_eq!(import_of("Go", src, "main"), None);
+    }
+
+    #[test]
+    fn resolve_modules() {
+        let paths = [
+            "server/src/lib.rs",
+            "server/src/a.rs",
+            "server/src/a/b.rs",
+            "server/src/c/mod.rs",
+            "utils/src/lib.rs",
+            "py/pkg/__init__.py",
+            "py/pkg/mod.py",
+            "py/pkg/sub/x.py",
+            "web/src/a/b.ts",
+            "web/src/a/index.tsx",
+            "internal/store/v2/db.go",
+            "internal/store/v2/open.go",
+        ];
+        let resolve = |source, module| resolve(source, &module, paths.iter().copied());
+
+        assert_eq!(
+            resolve("server/src/c/mod.rs", rust(&["crate", "a", "b", "Item"])),
+            vec![
+                vec!["server/src/a/b.rs"],
+                vec!["server/src/a.rs"],
+                vec!["server/src/lib.rs"]
+            ]
+        );
+        assert_eq!(
+            resolve("server/src/a.rs", rust(&["super", "c"]))[0],
+            vec!["server/src/c/mod.rs"]
+        );
+        assert_eq!(
+            resolve("server/src/a.rs", rust(&["utils", "Item"])),
+            vec![vec!["utils/src/lib.rs"]]
+        );
+        assert_eq!(
+            resolve(
+                "py/pkg/sub/x.py",
+                Module::Python {
+                    level: 2,
+                    segments: vec!["mod".into()]
+                }
+            ),
+            vec![vec!["py/pkg/mod.py"]]
+        );
+        assert_eq!(
+            resolve(
+                "py/main.py",
+                Module::Python {
+                    level: 0,
+                    segments: vec!["pkg".into()]
+                }
+            ),
+            vec![vec!["py/pkg/__init__.py"]]
+        );
+        assert_eq!(
+            resolve("web/src/main.ts", Module::Js("./a/b".into())),
+            vec![vec!["web/src/a/b.ts"]]
+        );
+        assert_eq!(
+            resolve("web/src/main.ts", Module::Js("@/a".into())),
+            vec![vec!["web/src/a/index.tsx"]]
+        );
+        assert_eq!(
+            resolve(
+                "cmd/main.go",
+                Module::Go("example.com/repo/internal/store/v2".into())
+            ),
+            vec![vec!["internal/store/v2/db.go", "internal/store/v2/open.go"]]
+        );
+    }
+}
@@ -24,7 +24,7 @@ use git_version as _;
 #[cfg(all(feature = "debug", not(tokio_unstable)))]
 use console_subscriber as _;
 
-use remotes::github;
+use remotes::{gitea, github, gitlab};
 use secrecy::ExposeSecret;
 use state::PersistedState;
 use std::fs::canonicalize;
@@ -49,18 +49,22 @@ use tracing_subscriber::{
 
 mod agent;
 mod background;
+mod bundle;
 mod cache;
 mod collector;
 mod commits;
 mod config;
 mod db;
 mod env;
+mod fsck;
 mod llm;
 mod remotes;
 mod repo;
+mod saved_search;
 mod scraper;
 mod webserver;
 
+pub mod cli;
 pub mod indexes;
 pub mod intelligence;
 pub mod periodic;
@@ -126,6 +130,28 @@ impl Application {
         let config = Arc::new(config);
         info!(?config, "effective configuration");
 
+        // Self-hosted forges have to be registered before we parse any `RepoRef`
+        let credentials = config
+            .source
+            .load_state_or("credentials", remotes::Backends::default())?;
+        credentials.register_forge_hosts();
+
+        if let Some((url, token)) = config
+            .gitlab_url
+            .as_ref()
+            .zip(config.gitlab_access_token.clone())
+        {
+            credentials.set_gitlab(gitlab::Auth::new(url, token));
+        }
+
+        if let Some((url, token)) = config
+            .gitea_url
+            .as_ref()
+            .zip(config.gitea_access_token.clone())
+        {
+            credentials.set_gitea(gitea::Auth::new(url, token));
+        }
+
         // Load repositories
         let repo_pool = config.source.initialize_pool()?;
 
@@ -164,9 +190,7 @@ impl Application {
 
         Ok(Self {
             sync_queue: SyncQueue::start(config.clone()),
-            credentials: config
-                .source
-                .load_state_or("credentials", remotes::Backends::default())?,
+            credentials,
             user_profiles: config.source.load_or_default("user_profiles")?,
             sql,
             indexes,
@@ -1,2 +1,4 @@
+pub mod anthropic;
 pub mod call;
 pub mod client;
+pub mod provider;
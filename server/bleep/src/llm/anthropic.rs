@@ -0,0 +1,541 @@
+//! The Anthropic Messages API wire format.
+//!
+//! Bleep's messages follow the OpenAI function calling format. Here we map them onto Anthropic
+//! messages with `tool_use` and `tool_result` content blocks, and map the streamed events back
+//! onto [`Delta`]s, so that callers cannot tell the two providers apart.
+
+use anyhow::bail;
+use async_stream::try_stream;
+use futures_util::{pin_mut, Stream, StreamExt};
+use reqwest_eventsource::EventSource;
+use tracing::error;
+
+use super::{
+    call::{Delta, FunctionCallDelta, MAX_TOKEN_DURATION},
+    client::api,
+    provider::LlmProvider,
+};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic requires `max_tokens` to be set on every request.
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+#[derive(serde::Serialize, Debug)]
+struct MessagesRequest {
+    model: String,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice>,
+    max_tokens: u32,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
+    stream: bool,
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+struct Message {
+    role: Role,
+    content: Vec<ContentBlock>,
+}
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+    },
+}
+
+#[derive(serde::Serialize, Debug)]
+struct Tool {
+    name: String,
+    description: String,
+    input_schema: api::Parameters,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ToolChoice {
+    /// Force the model to call one of the tools.
+    Any,
+}
+
+/// A complete, non-streamed response.
+#[derive(serde::Deserialize, Debug)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+}
+
+/// A server-sent event of a streamed response.
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    ContentBlockStart {
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        delta: BlockDelta,
+    },
+    MessageStop,
+    Error {
+        error: serde_json::Value,
+    },
+    // `message_start`, `message_delta`, `content_block_stop` and `ping` carry nothing we use
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta { text: String },
+    InputJsonDelta { partial_json: String },
+}
+
+impl ContentBlock {
+    fn into_delta(self) -> Option<Delta> {
+        match self {
+            Self::Text { text } if text.is_empty() => None,
+            Self::Text { text } => Some(Delta::Content(Some(text))),
+            Self::ToolUse { name, input, .. } => Some(Delta::FunctionCall(FunctionCallDelta {
+                name: Some(name),
+                // When streaming, the input is sent separately as `input_json_delta`s
+                arguments: match input {
+                    serde_json::Value::Object(map) if map.is_empty() => String::new(),
+                    input => input.to_string(),
+                },
+            })),
+            Self::ToolResult { .. } => None,
+        }
+    }
+}
+
+/// Resolve the Anthropic model to request.
+///
+/// Bleep asks for OpenAI models by name, which Anthropic doesn't serve, so they must be mapped
+/// to an Anthropic model in the provider's `models`.
+fn model_name(provider: &LlmProvider, requested: Option<&str>) -> anyhow::Result<String> {
+    let model = provider.model_name(requested);
+    if model.starts_with("gpt-") {
+        bail!("`{model}` is an OpenAI model; map it to an Anthropic model in `llm.models`");
+    }
+
+    Ok(model)
+}
+
+/// Convert bleep's messages to a system prompt and a list of Anthropic messages.
+///
+/// System messages are concatenated into the system prompt. Function calls and returns become
+/// `tool_use` and `tool_result` blocks, with ids derived from their position in the
+/// conversation. Consecutive messages of the same role are merged, as Anthropic requires
+/// alternating roles.
+fn convert_messages(messages: &[api::Message]) -> (Option<String>, Vec<Message>) {
+    let mut system = vec![];
+    let mut converted: Vec<Message> = vec![];
+    let mut last_tool_use_id = None;
+
+    for (i, message) in messages.iter().enumerate() {
+        let (role, block) = match message {
+            api::Message::PlainText { role, content } if role == "system" => {
+                system.push(content.clone());
+                continue;
+            }
+            api::Message::PlainText { role, content } => (
+                if role == "assistant" {
+                    Role::Assistant
+                } else {
+                    Role::User
+                },
+                ContentBlock::Text {
+                    text: content.clone(),
+                },
+            ),
+            api::Message::FunctionCall { function_call, .. } => {
+                let id = format!("toolu_{i}");
+                last_tool_use_id = Some(id.clone());
+
+                (
+                    Role::Assistant,
+                    ContentBlock::ToolUse {
+                        id,
+                        name: function_call.name.clone().unwrap_or_default(),
+                        input: serde_json::from_str(&function_call.arguments)
+                            .unwrap_or_else(|_| serde_json::json!({})),
+                    },
+                )
+            }
+            api::Message::FunctionReturn { name, content, .. } => match last_tool_use_id.take() {
+                Some(tool_use_id) => (
+                    Role::User,
+                    ContentBlock::ToolResult {
+                        tool_use_id,
+                        content: content.clone(),
+                    },
+                ),
+                // A function return without a preceding call is not valid as a `tool_result`
+                None => (
+                    Role::User,
+                    ContentBlock::Text {
+                        text: format!("Output of function `{name}`:\n\n{content}"),
+                    },
+                ),
+            },
+        };
+
+        match converted.last_mut() {
+            Some(last) if last.role == role => last.content.push(block),
+            _ => converted.push(Message {
+                role,
+                content: vec![block],
+            }),
+        }
+    }
+
+    let system = (!system.is_empty()).then(|| system.join("\n\n"));
+    (system, converted)
+}
+
+pub async fn messages_call(
+    provider: &LlmProvider,
+    req: api::LLMRequest,
+) -> anyhow::Result<impl Stream<Item = Result<Delta, api::Error>>> {
+    let (system, messages) = convert_messages(&req.messages.messages);
+
+    let tools = req
+        .functions
+        .map(|f| f.functions)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|f| Tool {
+            name: f.name,
+            description: f.description,
+            input_schema: f.parameters,
+        })
+        .collect::<Vec<_>>();
+
+    let request = MessagesRequest {
+        model: model_name(provider, req.model.as_deref())?,
+        messages,
+        system,
+        // Bleep only passes functions when it expects a function call in return, and
+        // Anthropic models tend to explain themselves before calling a tool unless forced.
+        tool_choice: (!tools.is_empty()).then_some(ToolChoice::Any),
+        tools,
+        max_tokens: req.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        temperature: req.temperature.unwrap_or(0.0),
+        stop_sequences: req.extra_stop_sequences,
+        stream: provider.streaming,
+    };
+
+    let builder = provider
+        .request(&reqwest::Client::new(), "messages", req.api_key.as_deref())
+        .header("anthropic-version", ANTHROPIC_VERSION)
+        .json(&request);
+
+    if !provider.streaming {
+        return Ok(complete(builder).await?.left_stream());
+    }
+
+    // This should never fail, as our request body is not a stream.
+    let mut response = EventSource::new(builder).expect("failed to build request");
+    response.set_retry_policy(Box::new(reqwest_eventsource::retry::Never));
+
+    match response.next().await {
+        Some(Ok(reqwest_eventsource::Event::Open)) => {}
+        Some(Err(reqwest_eventsource::Error::InvalidStatusCode(status, _))) => {
+            error!("{}", &status);
+            return Err(api::Error::BadOpenAiRequest.into());
+        }
+        Some(Err(e)) => {
+            error!("{}", e);
+            return Err(api::Error::BadOpenAiRequest.into());
+        }
+        _ => return Err(api::Error::BadOpenAiRequest.into()),
+    }
+
+    let message_stream = try_stream! {
+        for await result in response {
+            // The `reqwest_eventsource` library uses errors to signal a successful stream close,
+            // so we make sure to avoid passing down this close message as an error.
+            if matches!(result, Err(reqwest_eventsource::Error::StreamEnded)) {
+                break;
+            }
+
+            let msg = match result {
+                Ok(reqwest_eventsource::Event::Message(msg)) => msg,
+                Ok(_) => Err(api::Error::BadOpenAiRequest)?,
+                Err(e) => {
+                    error!("{}", e);
+                    Err(api::Error::BadOpenAiRequest)?
+                }
+            };
+
+            let event: StreamEvent = serde_json::from_str(&msg.data).map_err(|e| {
+                error!(data = %msg.data, "{}", e);
+                api::Error::BadOpenAiRequest
+            })?;
+
+            match event {
+                StreamEvent::ContentBlockStart { content_block } => {
+                    if let Some(delta) = content_block.into_delta() {
+                        yield delta;
+                    }
+                }
+                StreamEvent::ContentBlockDelta { delta: BlockDelta::TextDelta { text } } => {
+                    if !text.is_empty() {
+                        yield Delta::Content(Some(text));
+                    }
+                }
+                StreamEvent::ContentBlockDelta { delta: BlockDelta::InputJsonDelta { partial_json } } => {
+                    if !partial_json.is_empty() {
+                        yield Delta::FunctionCall(FunctionCallDelta {
+                            name: None,
+                            arguments: partial_json,
+                        });
+                    }
+                }
+                StreamEvent::MessageStop => break,
+                StreamEvent::Error { error } => {
+                    error!(%error, "Anthropic stream errored");
+                    Err(api::Error::BadOpenAiRequest)?
+                }
+                StreamEvent::Other => {}
+            }
+        }
+    };
+
+    let stream = try_stream! {
+        // We modify the message stream to include a timeout.
+        let message_stream = tokio_stream::StreamExt::timeout(message_stream, MAX_TOKEN_DURATION)
+            .map(|r| r.map_err(|_| api::Error::TokenDelayTooLarge).and_then(|r2| r2));
+
+        pin_mut!(message_stream);
+
+        for await result in message_stream {
+            let delta: Delta = result?;
+            yield delta;
+        }
+    };
+
+    Ok(stream.right_stream())
+}
+
+/// Perform a non-streaming request, and return the answer as a stream of content blocks.
+async fn complete(
+    builder: reqwest::RequestBuilder,
+) -> anyhow::Result<impl Stream<Item = Result<Delta, api::Error>>> {
+    let response = builder.send().await.map_err(|e| {
+        error!("{}", e);
+        api::Error::BadOpenAiRequest
+    })?;
+
+    if !response.status().is_success() {
+        error!("{}", response.status());
+        return Err(api::Error::BadOpenAiRequest.into());
+    }
+
+    let response: MessagesResponse = response.json().await.map_err(|e| {
+        error!("{}", e);
+        api::Error::BadOpenAiRequest
+    })?;
+
+    Ok(futures_util::stream::iter(
+        response
+            .content
+            .into_iter()
+            .filter_map(ContentBlock::into_delta)
+            .map(Ok),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::{ApiFormat, AuthStyle};
+    use axum::{http::header, routing::post, Json, Router};
+    use futures_util::TryStreamExt;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn convert_agent_history() {
+        let history = vec![
+            api::Message::system("You are a helpful assistant"),
+            api::Message::user("where is the retry policy?"),
+            api::Message::function_call(&api::FunctionCall {
+                name: Some("code".to_owned()),
+                arguments: r#"{"query": "retry policy"}"#.to_owned(),
+            }),
+            api::Message::function_return("code", "src/retry.rs"),
+            api::Message::user("Call a function. Do not answer"),
+        ];
+
+        let (system, messages) = convert_messages(&history);
+
+        assert_eq!(system.as_deref(), Some("You are a helpful assistant"));
+        assert_eq!(
+            messages,
+            vec![
+                Message {
+                    role: Role::User,
+                    content: vec![ContentBlock::Text {
+                        text: "where is the retry policy?".to_owned()
+                    }],
+                },
+                Message {
+                    role: Role::Assistant,
+                    content: vec![ContentBlock::ToolUse {
+                        id: "toolu_2".to_owned(),
+                        name: "code".to_owned(),
+                        input: serde_json::json!({ "query": "retry policy" }),
+                    }],
+                },
+                Message {
+                    role: Role::User,
+                    content: vec![
+                        ContentBlock::ToolResult {
+                            tool_use_id: "toolu_2".to_owned(),
+                            content: "src/retry.rs".to_owned(),
+                        },
+                        ContentBlock::Text {
+                            text: "Call a function. Do not answer".to_owned()
+                        },
+                    ],
+                },
+            ]
+        );
+    }
+
+    /// Serve `router` on a random local port, returning an Anthropic provider pointing at it.
+    fn serve(router: Router) -> LlmProvider {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+
+        LlmProvider {
+            format: ApiFormat::Anthropic,
+            base_url: Some(format!("http://{addr}/v1")),
+            auth: Some(AuthStyle::None),
+            models: [(
+                "gpt-4-turbo".to_owned(),
+                "claude-3-opus-20240229".to_owned(),
+            )]
+            .into(),
+            ..Default::default()
+        }
+    }
+
+    fn request() -> api::LLMRequest {
+        api::LLMRequest {
+            api_key: None,
+            messages: api::Messages {
+                messages: vec![api::Message::user("where is the retry policy?")],
+            },
+            functions: None,
+            max_tokens: None,
+            temperature: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            model: None,
+            extra_stop_sequences: vec![],
+        }
+    }
+
+    async fn collect(provider: &LlmProvider) -> Vec<String> {
+        messages_call(provider, request())
+            .await
+            .unwrap()
+            .map_ok(|delta| delta.to_string())
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn tool_use_events_become_function_call_deltas() {
+        let provider = serve(Router::new().route(
+            "/v1/messages",
+            post(|Json(body): Json<serde_json::Value>| async move {
+                assert_eq!(body["model"], "claude-3-opus-20240229");
+                assert_eq!(body["stream"], true);
+
+                (
+                    [(header::CONTENT_TYPE, "text/event-stream")],
+                    [
+                        r#"{"type":"message_start","message":{"id":"msg_1"}}"#,
+                        r#"{"type":"content_block_start","index":0,"content_block":{"type":"tool_use","id":"toolu_1","name":"code","input":{}}}"#,
+                        r#"{"type":"ping"}"#,
+                        r#"{"type":"content_block_delta","index":0,"delta":{"type":"input_json_delta","partial_json":"{\"query\": "}}"#,
+                        r#"{"type":"content_block_delta","index":0,"delta":{"type":"input_json_delta","partial_json":"\"retry\"}"}}"#,
+                        r#"{"type":"content_block_stop","index":0}"#,
+                        r#"{"type":"message_stop"}"#,
+                    ]
+                    .map(|data| format!("data: {data}\n\n"))
+                    .concat(),
+                )
+            }),
+        ));
+
+        assert_eq!(
+            collect(&provider).await,
+            vec![
+                r#"{"name":"code","arguments":""}"#,
+                r#"{"name":null,"arguments":"{\"query\": "}"#,
+                r#"{"name":null,"arguments":"\"retry\"}"}"#,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn non_streaming_text() {
+        let mut provider = serve(Router::new().route(
+            "/v1/messages",
+            post(|| async {
+                Json(serde_json::json!({
+                    "content": [{ "type": "text", "text": "In src/retry.rs" }]
+                }))
+            }),
+        ));
+        provider.streaming = false;
+
+        assert_eq!(collect(&provider).await, vec!["In src/retry.rs"]);
+    }
+
+    #[tokio::test]
+    async fn unmapped_openai_models_are_rejected() {
+        let mut provider = serve(Router::new());
+        provider.models.clear();
+
+        let err = messages_call(&provider, request()).await.err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "`gpt-4-turbo` is an OpenAI model; map it to an Anthropic model in `llm.models`"
+        );
+    }
+}
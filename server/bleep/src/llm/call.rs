@@ -6,9 +6,13 @@ use reqwest_eventsource::EventSource;
 use tokio::sync::Mutex;
 use tracing::error;
 
-use super::client::api;
+use super::{
+    anthropic,
+    client::api,
+    provider::{ApiFormat, LlmProvider},
+};
 
-const MAX_TOKEN_DURATION: Duration = Duration::from_secs(16);
+pub(super) const MAX_TOKEN_DURATION: Duration = Duration::from_secs(16);
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct ChatCompletion {
@@ -43,14 +47,66 @@ impl fmt::Display for Delta {
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FunctionCallDelta {
-    name: Option<String>,
+    pub(super) name: Option<String>,
     #[serde(default)]
-    arguments: String,
+    pub(super) arguments: String,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct ChatMessage {
     content: Option<String>,
+    function_call: Option<FunctionCallDelta>,
+}
+
+/// A complete, non-streamed chat completion.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Completion {
+    choices: Vec<CompletionChoice>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct CompletionChoice {
+    message: ChatMessage,
+}
+
+impl Delta {
+    /// Parse a function call out of a plain text answer.
+    ///
+    /// This is used for providers that lack native function calling, where the model is
+    /// instructed to answer with a JSON object like `{"name": "code", "arguments": {...}}`.
+    pub fn parse_function_call(text: &str) -> Result<Self, api::Error> {
+        let (Some(start), Some(end)) = (text.find('{'), text.rfind('}')) else {
+            error!(%text, "no function call found in LLM output");
+            return Err(api::Error::BadOpenAiRequest);
+        };
+
+        #[derive(serde::Deserialize)]
+        struct TextFunctionCall {
+            name: String,
+            #[serde(default)]
+            arguments: serde_json::Value,
+        }
+
+        let call = text
+            .get(start..=end)
+            .and_then(|json| serde_json::from_str::<TextFunctionCall>(json).ok())
+            .ok_or_else(|| {
+                error!(%text, "malformed function call in LLM output");
+                api::Error::BadOpenAiRequest
+            })?;
+
+        let arguments = match call.arguments {
+            // Some models return the arguments pre-serialized, like the OpenAI API does
+            serde_json::Value::String(s) => s,
+            serde_json::Value::Null => "{}".to_owned(),
+            other => other.to_string(),
+        };
+
+        Ok(Self::FunctionCall(FunctionCallDelta {
+            name: Some(call.name),
+            arguments,
+        }))
+    }
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -78,12 +134,22 @@ struct OpenAiRequest {
 }
 
 pub async fn llm_call(
+    provider: &LlmProvider,
     req: api::LLMRequest,
 ) -> anyhow::Result<impl Stream<Item = Result<Delta, api::Error>>> {
-    let model = match req.model.as_deref() {
-        Some(model) => model.to_owned(),
-        None => "gpt-4-turbo".into(),
-    };
+    Ok(match provider.format {
+        ApiFormat::OpenAi => openai_call(provider, req).await?.left_stream(),
+        ApiFormat::Anthropic => anthropic::messages_call(provider, req)
+            .await?
+            .right_stream(),
+    })
+}
+
+async fn openai_call(
+    provider: &LlmProvider,
+    req: api::LLMRequest,
+) -> anyhow::Result<impl Stream<Item = Result<Delta, api::Error>>> {
+    let model = provider.model_name(req.model.as_deref());
 
     let builder = {
         let request = OpenAiRequest {
@@ -99,15 +165,22 @@ pub async fn llm_call(
             temperature: req.temperature.unwrap_or(0.0),
             presence_penalty: req.presence_penalty,
             frequency_penalty: req.frequency_penalty,
-            stream: true,
+            stream: provider.streaming,
         };
 
-        reqwest::Client::new()
-            .post("https://api.openai.com/v1/chat/completions")
-            .bearer_auth(req.openai_key)
+        provider
+            .request(
+                &reqwest::Client::new(),
+                "chat/completions",
+                req.api_key.as_deref(),
+            )
             .json(&request)
     };
 
+    if !provider.streaming {
+        return Ok(complete(builder).await?.left_stream());
+    }
+
     // This should never fail, as our request body is not a stream.
     let mut response = EventSource::new(builder).expect("failed to build request");
     response.set_retry_policy(Box::new(reqwest_eventsource::retry::Never));
@@ -200,5 +273,152 @@ pub async fn llm_call(
         }
     };
 
-    Ok(stream)
+    Ok(stream.right_stream())
+}
+
+/// Perform a non-streaming request, and return the answer as a single-item stream.
+async fn complete(
+    builder: reqwest::RequestBuilder,
+) -> anyhow::Result<impl Stream<Item = Result<Delta, api::Error>>> {
+    let response = builder.send().await.map_err(|e| {
+        error!("{}", e);
+        api::Error::BadOpenAiRequest
+    })?;
+
+    if !response.status().is_success() {
+        error!("{}", response.status());
+        return Err(api::Error::BadOpenAiRequest.into());
+    }
+
+    let completion: Completion = response.json().await.map_err(|e| {
+        error!("{}", e);
+        api::Error::BadOpenAiRequest
+    })?;
+
+    let delta = completion
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| match choice.message {
+            ChatMessage {
+                function_call: Some(call),
+                ..
+            } => Some(Delta::FunctionCall(call)),
+            ChatMessage {
+                content: Some(content),
+                ..
+            } if !content.is_empty() => Some(Delta::Content(Some(content))),
+            _ => None,
+        });
+
+    Ok(futures_util::stream::iter(delta.map(Ok)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::AuthStyle;
+    use axum::{http::header, routing::post, Json, Router};
+
+    /// Serve `router` on a random local port, returning the base URL of the API.
+    fn serve(router: Router) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+
+        format!("http://{addr}/v1")
+    }
+
+    fn request() -> api::LLMRequest {
+        api::LLMRequest {
+            api_key: None,
+            messages: api::Messages {
+                messages: vec![api::Message::user("hello")],
+            },
+            functions: None,
+            max_tokens: None,
+            temperature: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            model: None,
+            extra_stop_sequences: vec![],
+        }
+    }
+
+    async fn collect(provider: &LlmProvider) -> Vec<String> {
+        llm_call(provider, request())
+            .await
+            .unwrap()
+            .map_ok(|delta| delta.to_string())
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn streaming_local_server() {
+        let base_url = serve(Router::new().route(
+            "/v1/chat/completions",
+            post(|| async {
+                (
+                    [(header::CONTENT_TYPE, "text/event-stream")],
+                    "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n\
+                     data: {\"choices\":[{\"delta\":{\"content\":\"Hello\"}}]}\n\n\
+                     data: {\"choices\":[{\"delta\":{\"content\":\" world\"}}]}\n\n\
+                     data: [DONE]\n\n",
+                )
+            }),
+        ));
+
+        let provider = LlmProvider {
+            base_url: Some(base_url),
+            auth: Some(AuthStyle::None),
+            ..Default::default()
+        };
+
+        assert_eq!(collect(&provider).await, vec!["Hello", " world"]);
+    }
+
+    #[tokio::test]
+    async fn non_streaming_local_server() {
+        let base_url = serve(Router::new().route(
+            "/v1/chat/completions",
+            post(|| async {
+                Json(serde_json::json!({
+                    "choices": [{
+                        "message": { "role": "assistant", "content": "Hello world" }
+                    }]
+                }))
+            }),
+        ));
+
+        let provider = LlmProvider {
+            base_url: Some(base_url),
+            auth: Some(AuthStyle::None),
+            streaming: false,
+            ..Default::default()
+        };
+
+        assert_eq!(collect(&provider).await, vec!["Hello world"]);
+    }
+
+    #[test]
+    fn parse_text_function_call() {
+        let delta = Delta::parse_function_call(
+            "Sure! {\"name\": \"code\", \"arguments\": {\"query\": \"retry policy\"}}",
+        )
+        .unwrap();
+
+        assert_eq!(
+            delta.to_string(),
+            r#"{"name":"code","arguments":"{\"query\":\"retry policy\"}"}"#
+        );
+
+        assert!(Delta::parse_function_call("I don't know").is_err());
+    }
 }
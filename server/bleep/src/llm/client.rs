@@ -1,11 +1,11 @@
 use std::time::Duration;
 
 use anyhow::{anyhow, bail};
-use futures::{Stream, StreamExt};
+use futures::{Stream, StreamExt, TryStreamExt};
 use secrecy::ExposeSecret;
 use tracing::{debug, error, warn};
 
-use super::call::llm_call;
+use super::call::{llm_call, Delta};
 use crate::{periodic::sync_github_status_once, Application};
 
 pub mod api {
@@ -75,7 +75,7 @@ pub mod api {
 
     #[derive(Debug, serde::Serialize, serde::Deserialize)]
     pub struct LLMRequest {
-        pub openai_key: String,
+        pub api_key: Option<String>,
         pub messages: Messages,
         pub functions: Option<Functions>,
         pub max_tokens: Option<u32>,
@@ -335,19 +335,33 @@ impl Client {
         messages: &[api::Message],
         functions: Option<&[api::Function]>,
     ) -> Result<impl Stream<Item = anyhow::Result<String>>, ChatError> {
-        let mut stream = Box::pin(
-            llm_call(api::LLMRequest {
-                openai_key: self
-                    .app
-                    .config
-                    .openai_api_key
-                    .as_ref()
-                    .expect("OpenAI API key not set")
-                    .expose_secret()
-                    .to_string(),
-                messages: api::Messages {
-                    messages: messages.to_owned(),
-                },
+        let provider = &self.app.config.llm;
+        let api_key = self
+            .app
+            .config
+            .openai_api_key
+            .as_ref()
+            .map(|key| key.expose_secret().to_string());
+
+        if api_key.is_none() && provider.requires_key() {
+            return Err(ChatError::Other(anyhow!("LLM API key not set")));
+        }
+
+        // Providers without native function calling get the function definitions in the
+        // prompt instead, and we parse the function call out of the answer.
+        let emulate_functions = functions.is_some() && !provider.function_calling;
+        let (messages, functions) = match functions {
+            Some(functions) if emulate_functions => {
+                (emulated_function_messages(messages, functions), None)
+            }
+            _ => (messages.to_owned(), functions),
+        };
+
+        let stream = llm_call(
+            provider,
+            api::LLMRequest {
+                api_key,
+                messages: api::Messages { messages },
                 functions: functions.map(|funcs| api::Functions {
                     functions: funcs.to_owned(),
                 }),
@@ -357,9 +371,15 @@ impl Client {
                 frequency_penalty: self.frequency_penalty,
                 model: self.model.clone(),
                 extra_stop_sequences: vec![],
-            })
-            .await?,
-        );
+            },
+        )
+        .await?;
+
+        let mut stream = if emulate_functions {
+            Box::pin(collect_function_call(stream).left_stream())
+        } else {
+            Box::pin(stream.right_stream())
+        };
 
         let first_item = stream.next().await;
         match first_item {
@@ -394,3 +414,54 @@ impl Client {
             }))
     }
 }
+
+/// Rewrite a conversation for a provider without native function calling.
+///
+/// Function definitions are described in an extra system message, and earlier function calls
+/// and returns are turned into plain text messages, which every provider understands.
+fn emulated_function_messages(
+    messages: &[api::Message],
+    functions: &[api::Function],
+) -> Vec<api::Message> {
+    let definitions = serde_json::to_string_pretty(functions).unwrap_or_default();
+    let instructions = format!(
+        "You can call the following functions:\n\n{definitions}\n\n\
+         To call a function, respond ONLY with a JSON object of the form \
+         {{\"name\": \"<function name>\", \"arguments\": {{...}}}}. \
+         Do not write anything else."
+    );
+
+    messages
+        .iter()
+        .map(|m| match m {
+            api::Message::FunctionCall { function_call, .. } => api::Message::assistant(
+                &serde_json::json!({
+                    "name": function_call.name,
+                    "arguments": serde_json::from_str::<serde_json::Value>(&function_call.arguments)
+                        .unwrap_or_else(|_| function_call.arguments.clone().into()),
+                })
+                .to_string(),
+            ),
+            api::Message::FunctionReturn { name, content, .. } => {
+                api::Message::user(&format!("Output of function `{name}`:\n\n{content}"))
+            }
+            m => m.clone(),
+        })
+        .chain(std::iter::once(api::Message::system(&instructions)))
+        .collect()
+}
+
+/// Buffer a text answer and parse it as a single function call.
+fn collect_function_call(
+    stream: impl Stream<Item = Result<Delta, api::Error>>,
+) -> impl Stream<Item = Result<Delta, api::Error>> {
+    futures::stream::once(async move {
+        let text = stream
+            .try_fold(String::new(), |acc, delta| async move {
+                Ok(acc + &delta.to_string())
+            })
+            .await?;
+
+        Delta::parse_function_call(&text)
+    })
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+/// The model requested when the caller does not specify one.
+const DEFAULT_MODEL: &str = "gpt-4-turbo";
+
+/// The wire format spoken by the LLM provider.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiFormat {
+    /// The OpenAI chat completions API, with function calling
+    #[default]
+    #[value(name = "openai")]
+    #[serde(rename = "openai")]
+    OpenAi,
+
+    /// The Anthropic Messages API, with tool use
+    Anthropic,
+}
+
+/// How the API key is attached to outgoing requests.
+#[derive(Serialize, Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthStyle {
+    /// `Authorization: Bearer <key>`, as used by OpenAI and most compatible servers
+    Bearer,
+
+    /// `api-key: <key>`, as used by Azure OpenAI deployments
+    ApiKey,
+
+    /// `x-api-key: <key>`, as used by the Anthropic API
+    XApiKey,
+
+    /// Send no credentials at all, e.g. for a local llama.cpp or Ollama server
+    None,
+}
+
+/// A chat completions backend, speaking one of the supported wire formats.
+#[derive(Serialize, Deserialize, Args, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct LlmProvider {
+    /// Wire format of the LLM provider's API
+    #[clap(long = "llm-format", value_enum, default_value_t)]
+    #[serde(default)]
+    pub format: ApiFormat,
+
+    /// Base URL of the LLM provider's API. Defaults to the official endpoint of `format`
+    #[clap(long = "llm-base-url")]
+    #[serde(default)]
+    pub base_url: Option<String>,
+
+    /// How the API key is sent to the LLM provider. Defaults to the official style of `format`
+    #[clap(long = "llm-auth", value_enum)]
+    #[serde(default)]
+    pub auth: Option<AuthStyle>,
+
+    /// Extra query string sent with every request, e.g. `api-version=2024-02-01` on Azure
+    #[clap(long = "llm-query-string")]
+    #[serde(default)]
+    pub query_string: Option<String>,
+
+    /// Whether the provider supports OpenAI-style function calling.
+    ///
+    /// If not, function definitions are described in the prompt and the
+    /// model's answer is parsed as a function call.
+    #[clap(long = "llm-function-calling", default_value_t = true, action = clap::ArgAction::Set)]
+    #[serde(default = "default_true")]
+    pub function_calling: bool,
+
+    /// Whether the provider supports streaming responses over server-sent events
+    #[clap(long = "llm-streaming", default_value_t = true, action = clap::ArgAction::Set)]
+    #[serde(default = "default_true")]
+    pub streaming: bool,
+
+    /// Map from bloop's model names to the provider's model or deployment names.
+    ///
+    /// Only configurable through the config file.
+    #[clap(skip)]
+    #[serde(default)]
+    pub models: HashMap<String, String>,
+}
+
+impl Default for LlmProvider {
+    fn default() -> Self {
+        Self {
+            format: ApiFormat::default(),
+            base_url: None,
+            auth: None,
+            query_string: None,
+            function_calling: true,
+            streaming: true,
+            models: HashMap::new(),
+        }
+    }
+}
+
+impl LlmProvider {
+    /// Resolve the model name to send to the provider.
+    pub fn model_name(&self, requested: Option<&str>) -> String {
+        let model = requested.unwrap_or(DEFAULT_MODEL);
+        self.models
+            .get(model)
+            .cloned()
+            .unwrap_or_else(|| model.to_owned())
+    }
+
+    /// The full URL of an endpoint, relative to the base URL.
+    pub fn endpoint_url(&self, path: &str) -> String {
+        let base_url = self.base_url.as_deref().unwrap_or(match self.format {
+            ApiFormat::OpenAi => "https://api.openai.com/v1",
+            ApiFormat::Anthropic => "https://api.anthropic.com/v1",
+        });
+
+        let mut url = format!("{}/{}", base_url.trim_end_matches('/'), path);
+
+        if let Some(query) = self.query_string.as_deref().filter(|q| !q.is_empty()) {
+            url.push('?');
+            url.push_str(query.trim_start_matches('?'));
+        }
+
+        url
+    }
+
+    /// The style used to authenticate requests.
+    pub fn auth_style(&self) -> AuthStyle {
+        self.auth.unwrap_or(match self.format {
+            ApiFormat::OpenAi => AuthStyle::Bearer,
+            ApiFormat::Anthropic => AuthStyle::XApiKey,
+        })
+    }
+
+    /// Build a `POST` request to an endpoint, with credentials attached.
+    pub fn request(
+        &self,
+        client: &reqwest::Client,
+        path: &str,
+        key: Option<&str>,
+    ) -> reqwest::RequestBuilder {
+        let builder = client.post(self.endpoint_url(path));
+
+        match (self.auth_style(), key) {
+            (AuthStyle::Bearer, Some(key)) => builder.bearer_auth(key),
+            (AuthStyle::ApiKey, Some(key)) => builder.header("api-key", key),
+            (AuthStyle::XApiKey, Some(key)) => builder.header("x-api-key", key),
+            (AuthStyle::None, _) | (_, None) => builder,
+        }
+    }
+
+    /// Whether requests to this provider need an API key.
+    pub fn requires_key(&self) -> bool {
+        self.auth_style() != AuthStyle::None
+    }
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_urls_and_models() {
+        let openai = LlmProvider::default();
+        assert_eq!(
+            openai.endpoint_url("chat/completions"),
+            "https://api.openai.com/v1/chat/completions"
+        );
+        assert_eq!(openai.auth_style(), AuthStyle::Bearer);
+        assert_eq!(openai.model_name(None), "gpt-4-turbo");
+        assert_eq!(openai.model_name(Some("gpt-4-0613")), "gpt-4-0613");
+
+        let azure = LlmProvider {
+            base_url: Some("https://bloop.openai.azure.com/openai/deployments/gpt4/".into()),
+            auth: Some(AuthStyle::ApiKey),
+            query_string: Some("api-version=2024-02-01".into()),
+            models: [("gpt-4-turbo".to_owned(), "gpt4".to_owned())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            azure.endpoint_url("chat/completions"),
+            "https://bloop.openai.azure.com/openai/deployments/gpt4/chat/completions?api-version=2024-02-01"
+        );
+        assert_eq!(azure.model_name(None), "gpt4");
+        assert_eq!(azure.model_name(Some("gpt-4-0613")), "gpt-4-0613");
+
+        let anthropic = LlmProvider {
+            format: ApiFormat::Anthropic,
+            ..Default::default()
+        };
+        assert_eq!(
+            anthropic.endpoint_url("messages"),
+            "https://api.anthropic.com/v1/messages"
+        );
+        assert_eq!(anthropic.auth_style(), AuthStyle::XApiKey);
+    }
+}
@@ -73,19 +73,33 @@ pub async fn sync_github_status_once(app: &Application) {
 
 pub(crate) async fn update_repo_list(app: &Application) {
     if let Some(gh) = app.credentials.github() {
-        let repos = match gh.current_repo_list().await {
+        match gh.current_repo_list().await {
             Ok(repos) => {
                 debug!("fetched new repo list");
-                repos
+                app.credentials.set_github(gh.update_repositories(repos));
             }
-            Err(err) => {
-                debug!(?err, "failed to update repo list");
-                return;
+            Err(err) => debug!(?err, "failed to update repo list"),
+        }
+    }
+
+    if let Some(gl) = app.credentials.gitlab() {
+        match gl.current_repo_list().await {
+            Ok(repos) => {
+                debug!("fetched new gitlab repo list");
+                app.credentials.set_gitlab(gl.update_repositories(repos));
             }
-        };
+            Err(err) => debug!(?err, "failed to update gitlab repo list"),
+        }
+    }
 
-        let new = gh.update_repositories(repos);
-        app.credentials.set_github(new);
+    if let Some(gt) = app.credentials.gitea() {
+        match gt.current_repo_list().await {
+            Ok(repos) => {
+                debug!("fetched new gitea repo list");
+                app.credentials.set_gitea(gt.update_repositories(repos));
+            }
+            Err(err) => debug!(?err, "failed to update gitea repo list"),
+        }
     }
 }
 
@@ -1,7 +1,9 @@
 pub mod compiler;
 pub mod execute;
+pub mod glob;
 pub mod languages;
 pub mod parser;
 pub mod planner;
 pub mod ranking;
 pub mod stopwords;
+pub mod structural;
@@ -2,20 +2,25 @@ use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
     mem,
+    ops::Bound,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use compact_str::CompactString;
 use either::Either;
+use regex::bytes::{Regex as ByteRegex, RegexBuilder as ByteRegexBuilder};
 use smallvec::SmallVec;
 use tantivy::{
-    query::{AllQuery, BooleanQuery, BoostQuery, TermQuery},
-    schema::{Field, IndexRecordOption},
-    Index, Term,
+    query::{
+        AllQuery, BooleanQuery, BoostQuery, EmptyScorer, EnableScoring, Explanation, Occur,
+        PhraseQuery, RangeQuery, Scorer, TermQuery, Weight,
+    },
+    schema::{Field, FieldType, IndexRecordOption},
+    DocId, DocSet, Index, Score, SegmentReader, TantivyError, Term, TERMINATED,
 };
 
 use crate::query::{
-    parser::{Literal, Query},
+    parser::{Literal, Query, Target},
     planner,
 };
 
@@ -29,6 +34,9 @@ enum Extraction<'a> {
 
     /// Match a string against a tantivy `bytes` field.
     ByteString(&'a Cow<'a, str>),
+
+    /// Match a range of values in a tantivy `u64` field.
+    Range(Bound<u64>, Bound<u64>),
 }
 
 /// A closure that tries to pull out an `Extraction` variant, given a `Query` reference.
@@ -38,6 +46,8 @@ type Extractor = dyn for<'a> FnMut(&'a Query<'a>) -> Option<Extraction<'a>>;
 pub struct Compiler {
     priority: HashSet<Field>,
     extractors: HashMap<Field, Box<Extractor>>,
+    identifiers: HashMap<Field, Option<Field>>,
+    raw: HashMap<Field, Field>,
 }
 
 impl Compiler {
@@ -69,6 +79,25 @@ impl Compiler {
         self
     }
 
+    /// Match plain identifiers in a literal field however their words are cased and joined.
+    ///
+    /// See `Literal::identifier_regex_str`. If a `words_field` indexed with the code tokenizer is
+    /// given, documents that contain the words of an identifier in order are ranked higher.
+    pub fn identifiers(mut self, tantivy_field: Field, words_field: Option<Field>) -> Self {
+        self.identifiers.insert(tantivy_field, words_field);
+        self
+    }
+
+    /// Match excluded literals of a field against its raw text, held in a fast `bytes` field.
+    ///
+    /// The n-grams of a literal can all be present in a document that doesn't contain it, so
+    /// excluding every document that matches them would exclude too much. Excluded literals of
+    /// fields tokenized into n-grams are rejected unless their raw text is known.
+    pub fn raw(mut self, tantivy_field: Field, raw_field: Field) -> Self {
+        self.raw.insert(tantivy_field, raw_field);
+        self
+    }
+
     /// Add a byte string field to the compiler.
     ///
     /// Matches `Cow<str>` against a tantivy `bytes` field.
@@ -83,8 +112,26 @@ impl Compiler {
         self
     }
 
+    /// Add a `u64` range field to the compiler.
+    ///
+    /// Matches the lower and upper bounds returned by the closure against an indexed tantivy
+    /// `u64` field.
+    pub fn range<F>(mut self, tantivy_field: Field, mut extractor: F) -> Self
+    where
+        F: for<'b> FnMut(&'b Query<'b>) -> Option<(Bound<u64>, Bound<u64>)> + 'static,
+    {
+        self.extractors.insert(
+            tantivy_field,
+            Box::new(move |q| extractor(q).map(|(lower, upper)| Extraction::Range(lower, upper))),
+        );
+        self
+    }
+
     /// Compile a list of queries into a single Tantivy query that matches any
     /// of them.
+    ///
+    /// Excluded terms of each query are compiled with the same extractors, and added as
+    /// `MustNot` clauses. See `Compiler::raw`.
     pub fn compile<'a, I>(mut self, queries: I, index: &Index) -> Result<DynQuery>
     where
         I: Iterator<Item = &'a Query<'a>>,
@@ -92,70 +139,36 @@ impl Compiler {
         let mut sub_queries: SmallVec<[DynQuery; 2]> = SmallVec::new();
 
         for query in queries {
-            let mut intersection = Vec::new();
-
-            for (field, extractor) in &mut self.extractors {
-                let Some(extraction) = extractor(query) else {
-                    continue;
-                };
-
-                let field_query = match extraction {
-                    Extraction::Literal(Literal::Plain(text)) => {
-                        let mut tokenizer = index
-                            .tokenizer_for_field(*field)
-                            .context("field is missing tokenizer")?;
-
-                        let mut token_stream = tokenizer.token_stream(&text);
-                        let tokens = std::iter::from_fn(move || {
-                            token_stream.next().map(|tok| CompactString::new(&tok.text))
-                        })
-                        .collect::<Vec<_>>();
-
-                        // We skip case insensitive matching if a token
-                        let terms = if query.is_case_sensitive()
-                            || tokens.iter().any(|t| t.len() > MAX_CASE_PERMUTATION_LEN)
-                        {
-                            tokens
-                                .into_iter()
-                                .map(|s| str_to_query(*field, &s))
-                                .collect::<Vec<_>>()
-                        } else {
-                            tokens
-                                .into_iter()
-                                .map(|s| {
-                                    let terms = case_permutations(&s)
-                                        .map(|s| str_to_query(*field, &s))
-                                        .collect();
-
-                                    Box::new(BooleanQuery::union(terms)) as DynQuery
-                                })
-                                .collect()
-                        };
-
-                        let mut field_query: DynQuery = Box::new(BooleanQuery::intersection(terms));
+            let case_sensitive = query.is_case_sensitive();
+            let mut clauses = self
+                .compile_fields(query, case_sensitive, false, index)?
+                .into_iter()
+                .map(|q| (Occur::Must, q))
+                .collect::<Vec<_>>();
 
-                        if self.priority.contains(field) {
-                            field_query = Box::new(BoostQuery::new(field_query, 10.0));
-                        }
+            if !query.excluded.is_empty() {
+                // A boolean query made only of `MustNot` clauses matches nothing.
+                if clauses.is_empty() {
+                    clauses.push((Occur::Must, Box::new(AllQuery) as DynQuery));
+                }
 
-                        field_query
-                    }
-                    Extraction::Literal(Literal::Regex(regex)) => {
-                        let plan = planner::plan(&regex)?;
-                        plan_to_query(plan, *field, query.is_case_sensitive())
+                for excluded in &query.excluded {
+                    if let Some(Target::Ast(pattern)) = &excluded.target {
+                        bail!("structural pattern `{}` can't be excluded", &**pattern);
                     }
 
-                    Extraction::ByteString(bs) => {
-                        let term = Term::from_field_bytes(*field, bs.as_bytes());
-                        let q = TermQuery::new(term, IndexRecordOption::Basic);
-                        Box::new(q) as DynQuery
+                    let terms = self.compile_fields(excluded, case_sensitive, true, index)?;
+
+                    // Each excluded query holds a single term, which this index can't match.
+                    if terms.is_empty() {
+                        bail!("negated terms of this kind can't be used in this search");
                     }
-                };
 
-                intersection.push(field_query);
+                    clauses.push((Occur::MustNot, Box::new(BooleanQuery::intersection(terms))));
+                }
             }
 
-            sub_queries.push(Box::new(BooleanQuery::intersection(intersection)));
+            sub_queries.push(Box::new(BooleanQuery::new(clauses)));
         }
 
         Ok(if sub_queries.len() == 1 {
@@ -164,6 +177,158 @@ impl Compiler {
             Box::new(BooleanQuery::union(sub_queries.into_vec()))
         })
     }
+
+    /// Compile every field extracted from a single query into a list of queries, which should
+    /// all match.
+    ///
+    /// When `excluded` is set, the resulting queries are used as `MustNot` clauses, and only match
+    /// documents which contain the literals exactly. As the trigram plan of a regex may match any
+    /// document, such regexes are rejected outright.
+    fn compile_fields<'a>(
+        &mut self,
+        query: &'a Query<'a>,
+        case_sensitive: bool,
+        excluded: bool,
+        index: &Index,
+    ) -> Result<Vec<DynQuery>> {
+        let mut intersection = Vec::new();
+
+        for (field, extractor) in &mut self.extractors {
+            let Some(extraction) = extractor(query) else {
+                continue;
+            };
+
+            // The regex that excluded documents must match exactly.
+            let exact_regex = match &extraction {
+                Extraction::Literal(literal) if excluded => {
+                    Some(if self.identifiers.contains_key(field) {
+                        literal.identifier_regex_str()
+                    } else {
+                        literal.regex_str()
+                    })
+                }
+                _ => None,
+            };
+            let is_plain = matches!(extraction, Extraction::Literal(Literal::Plain(..)));
+
+            let mut field_query = match extraction {
+                Extraction::Literal(literal)
+                    if self.identifiers.contains_key(field)
+                        && literal.identifier_words().is_some() =>
+                {
+                    let plan = planner::plan(&literal.identifier_regex_str())?;
+                    let field_query = plan_to_query(plan, *field, case_sensitive);
+
+                    // Phrases only rank documents, which exclusions don't need.
+                    match self.identifiers[field] {
+                        Some(words_field) if !excluded => {
+                            let terms = literal
+                                .identifier_words()
+                                .unwrap()
+                                .into_iter()
+                                .map(|word| {
+                                    Term::from_field_text(words_field, &word.to_lowercase())
+                                })
+                                .collect();
+
+                            Box::new(BooleanQuery::new(vec![
+                                (Occur::Must, field_query),
+                                (Occur::Should, Box::new(PhraseQuery::new(terms))),
+                            ]))
+                        }
+                        _ => field_query,
+                    }
+                }
+                Extraction::Literal(Literal::Plain(text)) => {
+                    let mut tokenizer = index
+                        .tokenizer_for_field(*field)
+                        .context("field is missing tokenizer")?;
+
+                    let mut token_stream = tokenizer.token_stream(&text);
+                    let tokens = std::iter::from_fn(move || {
+                        token_stream.next().map(|tok| CompactString::new(&tok.text))
+                    })
+                    .collect::<Vec<_>>();
+
+                    // We skip case insensitive matching if a token
+                    let terms = if case_sensitive
+                        || tokens.iter().any(|t| t.len() > MAX_CASE_PERMUTATION_LEN)
+                    {
+                        tokens
+                            .into_iter()
+                            .map(|s| str_to_query(*field, &s))
+                            .collect::<Vec<_>>()
+                    } else {
+                        tokens
+                            .into_iter()
+                            .map(|s| {
+                                let terms = case_permutations(&s)
+                                    .map(|s| str_to_query(*field, &s))
+                                    .collect();
+
+                                Box::new(BooleanQuery::union(terms)) as DynQuery
+                            })
+                            .collect()
+                    };
+
+                    let mut field_query: DynQuery = Box::new(BooleanQuery::intersection(terms));
+
+                    if self.priority.contains(field) && !excluded {
+                        field_query = Box::new(BoostQuery::new(field_query, 10.0));
+                    }
+
+                    field_query
+                }
+                Extraction::Literal(Literal::Regex(regex)) => {
+                    let plan = planner::plan(&regex)?;
+
+                    if excluded && plan.is_unbounded() {
+                        bail!("excluded regex `{}` is too broad", &*regex);
+                    }
+
+                    plan_to_query(plan, *field, case_sensitive)
+                }
+
+                Extraction::ByteString(bs) => {
+                    let term = Term::from_field_bytes(*field, bs.as_bytes());
+                    let q = TermQuery::new(term, IndexRecordOption::Basic);
+                    Box::new(q) as DynQuery
+                }
+
+                Extraction::Range(lower, upper) => {
+                    let name = index.schema().get_field_name(*field).to_owned();
+                    Box::new(RangeQuery::new_u64_bounds(name, lower, upper)) as DynQuery
+                }
+            };
+
+            if let Some(regex) = exact_regex {
+                let schema = index.schema();
+                let name = schema.get_field_name(*field);
+
+                match self.raw.get(field) {
+                    Some(&raw_field) => {
+                        let regex = ByteRegexBuilder::new(&regex)
+                            .multi_line(true)
+                            .case_insensitive(!case_sensitive)
+                            .build()?;
+
+                        field_query = Box::new(RawFilterQuery {
+                            query: field_query,
+                            raw_field,
+                            regex,
+                        });
+                    }
+                    // Plain text is matched exactly by fields which aren't split into n-grams.
+                    None if is_plain && !is_ngram_field(index, *field) => {}
+                    None => bail!("`{name}` terms can't be excluded from this search"),
+                }
+            }
+
+            intersection.push(field_query);
+        }
+
+        Ok(intersection)
+    }
 }
 
 fn plan_to_query(plan: planner::Fragment, field: Field, case_sensitive: bool) -> DynQuery {
@@ -212,6 +377,121 @@ fn str_to_query(field: Field, s: &str) -> DynQuery {
     Box::new(q) as DynQuery
 }
 
+/// Whether a text field is indexed with the n-gram tokenizer, which is registered as `default`.
+fn is_ngram_field(index: &Index, field: Field) -> bool {
+    match index.schema().get_field_entry(field).field_type() {
+        FieldType::Str(options) => options
+            .get_indexing_options()
+            .map_or(false, |indexing| indexing.tokenizer() == "default"),
+        _ => false,
+    }
+}
+
+/// A query matching the documents of another query whose raw text matches a regex.
+#[derive(Clone, Debug)]
+struct RawFilterQuery {
+    query: DynQuery,
+    raw_field: Field,
+    regex: ByteRegex,
+}
+
+impl tantivy::query::Query for RawFilterQuery {
+    fn weight(&self, enable_scoring: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        Ok(Box::new(RawFilterWeight {
+            weight: self.query.weight(enable_scoring)?,
+            raw_field: self.raw_field,
+            regex: self.regex.clone(),
+        }))
+    }
+}
+
+struct RawFilterWeight {
+    weight: Box<dyn Weight>,
+    raw_field: Field,
+    regex: ByteRegex,
+}
+
+impl Weight for RawFilterWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let name = reader.schema().get_field_name(self.raw_field);
+        let Some(column) = reader.fast_fields().bytes(name)? else {
+            return Ok(Box::new(EmptyScorer));
+        };
+
+        let mut scorer = RawFilterScorer {
+            scorer: self.weight.scorer(reader, boost)?,
+            column,
+            regex: self.regex.clone(),
+            buffer: vec![],
+        };
+        scorer.skip_mismatches();
+
+        Ok(Box::new(scorer))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.seek(doc) != doc {
+            return Err(TantivyError::InvalidArgument(format!(
+                "document {doc} does not match"
+            )));
+        }
+
+        Ok(Explanation::new("RawFilterQuery", scorer.score()))
+    }
+}
+
+struct RawFilterScorer {
+    scorer: Box<dyn Scorer>,
+    column: tantivy_columnar::BytesColumn,
+    regex: ByteRegex,
+    buffer: Vec<u8>,
+}
+
+impl RawFilterScorer {
+    /// Advance the inner scorer to the next document whose raw text matches.
+    fn skip_mismatches(&mut self) -> DocId {
+        let mut doc = self.scorer.doc();
+        while doc != TERMINATED && !self.is_match(doc) {
+            doc = self.scorer.advance();
+        }
+
+        doc
+    }
+
+    fn is_match(&mut self, doc: DocId) -> bool {
+        self.buffer.clear();
+        for ord in self.column.ords().values_for_doc(doc) {
+            if self.column.ord_to_bytes(ord, &mut self.buffer).is_err() {
+                return false;
+            }
+        }
+
+        self.regex.is_match(&self.buffer)
+    }
+}
+
+impl DocSet for RawFilterScorer {
+    fn advance(&mut self) -> DocId {
+        self.scorer.advance();
+        self.skip_mismatches()
+    }
+
+    fn doc(&self) -> DocId {
+        self.scorer.doc()
+    }
+
+    fn size_hint(&self) -> u32 {
+        self.scorer.size_hint()
+    }
+}
+
+impl Scorer for RawFilterScorer {
+    fn score(&mut self) -> Score {
+        self.scorer.score()
+    }
+}
+
 /// Split a string into trigrams, returning a bigram or unigram if the string is shorter than 3
 /// characters.
 pub fn trigrams(s: &str) -> impl Iterator<Item = CompactString> {
@@ -313,8 +593,6 @@ pub fn case_permutations(s: &str) -> impl Iterator<Item = CompactString> {
 
 #[cfg(test)]
 mod tests {
-    use tantivy::query::Occur;
-
     use super::*;
 
     #[test]
@@ -388,4 +666,115 @@ mod tests {
             assert_eq!(term.term().value().as_str().unwrap(), expected);
         }
     }
+
+    #[test]
+    fn test_excluded_terms() {
+        let mut builder = tantivy::schema::SchemaBuilder::new();
+        let path = builder.add_text_field("path", tantivy::schema::TEXT);
+        let raw_path = builder.add_bytes_field("raw_path", tantivy::schema::FAST);
+        let index = Index::create_in_ram(builder.build());
+
+        let queries = crate::query::parser::parse("path:src -path:test").unwrap();
+        let query = Compiler::new()
+            .literal(path, |q| q.path.clone())
+            .raw(path, raw_path)
+            .compile(queries.iter(), &index)
+            .unwrap();
+
+        let query = query.downcast_ref::<BooleanQuery>().unwrap();
+        let occurs = query.clauses().iter().map(|(o, _)| *o).collect::<Vec<_>>();
+        assert_eq!(occurs, [Occur::Must, Occur::MustNot]);
+
+        // Exclusions on their own match everything else.
+        let queries = crate::query::parser::parse("-path:test").unwrap();
+        let query = Compiler::new()
+            .literal(path, |q| q.path.clone())
+            .raw(path, raw_path)
+            .compile(queries.iter(), &index)
+            .unwrap();
+
+        let query = query.downcast_ref::<BooleanQuery>().unwrap();
+        let (occur, all) = &query.clauses()[0];
+        assert_eq!(*occur, Occur::Must);
+        assert!(all.downcast_ref::<AllQuery>().is_some());
+
+        // An excluded regex that matches anything would exclude everything.
+        let queries = crate::query::parser::parse("path:src -path:/.*/").unwrap();
+        assert!(Compiler::new()
+            .literal(path, |q| q.path.clone())
+            .raw(path, raw_path)
+            .compile(queries.iter(), &index)
+            .is_err());
+
+        // Without their raw text, paths can't be excluded exactly.
+        let queries = crate::query::parser::parse("path:src -path:test").unwrap();
+        assert!(Compiler::new()
+            .literal(path, |q| q.path.clone())
+            .compile(queries.iter(), &index)
+            .is_err());
+    }
+
+    #[test]
+    fn test_excluded_literals_match_exactly() {
+        use tantivy::{
+            collector::Count,
+            doc,
+            schema::{SchemaBuilder, TextFieldIndexing, TextOptions, FAST},
+            tokenizer::NgramTokenizer,
+        };
+
+        let mut builder = SchemaBuilder::new();
+        let content = builder.add_text_field(
+            "content",
+            TextOptions::default().set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer("default")
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            ),
+        );
+        let raw_content = builder.add_bytes_field("raw_content", FAST);
+        let index = Index::create_in_ram(builder.build());
+        index
+            .tokenizers()
+            .register("default", NgramTokenizer::new(1, 3, false).unwrap());
+
+        let mut writer = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        let texts = [
+            "let foobar = 1;",
+            "foo and bar",
+            "baz",
+            "retry_policy",
+            "policy retry",
+        ];
+        for text in texts {
+            writer
+                .add_document(doc!(content => text, raw_content => text.as_bytes().to_vec()))
+                .unwrap();
+        }
+        writer.commit().unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let count = |q: &str| {
+            let queries = crate::query::parser::parse(q).unwrap();
+            let query = Compiler::new()
+                .literal(content, |q| q.target.as_ref()?.content().cloned())
+                .identifiers(content, None)
+                .raw(content, raw_content)
+                .compile(queries.iter(), &index)
+                .unwrap();
+
+            searcher.search(&query, &Count).unwrap()
+        };
+
+        // The n-grams of `foobar` are all in `foo and bar`, which doesn't contain it.
+        assert_eq!(count("-foobar"), 4);
+        assert_eq!(count("-FOOBAR"), 4);
+        assert_eq!(count("foo -foobar"), 1);
+        assert_eq!(count("-/fo+bar/"), 4);
+        assert_eq!(count("-foo -baz"), 2);
+
+        // Identifiers are excluded however they're joined, but not when their words are apart.
+        assert_eq!(count("-\"retry policy\""), 4);
+        assert_eq!(count("-RetryPolicy"), 4);
+    }
 }
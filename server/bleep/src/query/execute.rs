@@ -1,26 +1,43 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     sync::Arc,
+    time::Instant,
 };
 
-use super::{parser, ranking::DocumentTweaker};
+use super::{
+    parser, planner,
+    ranking::{CommitTweaker, DocumentTweaker, RankingProfile},
+    structural::Pattern,
+};
 use crate::{
     collector::{BytesFilterCollector, FrequencyCollector},
     indexes::{
-        reader::{base_name, ContentReader, FileReader, OpenReader, RepoReader},
-        DocumentRead, File, Indexable, Indexer, Indexes, Repo,
+        reader::{base_name, CommitReader, ContentReader, FileReader, OpenReader, RepoReader},
+        Commit, DocumentRead, File, Indexable, Indexer, Indexes, Repo,
     },
+    intelligence::TreeSitterFile,
     repo::RepoRef,
-    snippet::{HighlightedString, SnippedFile, Snipper},
-    Application,
+    snippet::{HighlightedString, SnippedFile, Snipper, Snippet},
+    Application, SqlDb,
 };
 
 use anyhow::{bail, Result};
 use async_trait::async_trait;
+use futures::{Stream, StreamExt};
 use regex::{bytes::RegexBuilder as ByteRegexBuilder, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
-use tantivy::collector::{MultiCollector, TopDocs};
+use tantivy::{
+    collector::{MultiCollector, TopDocs},
+    schema::Field,
+};
+
+/// The number of candidate documents that are checked one by one, for structural `ast:` searches
+/// and symbol searches restricted by `kind:`.
+const MAX_FILTERED_CANDIDATES: usize = 1000;
+
+/// The number of repos that are searched at the same time by a streamed query.
+const STREAM_CONCURRENCY: usize = 4;
 
 const fn default_page_size() -> usize {
     100
@@ -79,6 +96,16 @@ pub struct ApiQuery {
     /// The number of lines of context in the snippet after the search result
     #[serde(alias = "ca", default = "default_context")]
     pub context_after: usize,
+
+    /// The name of a ranking profile from the configuration, used to order content results
+    #[serde(default)]
+    pub ranking: Option<String>,
+
+    /// The ranking profile named by `ranking`.
+    // NB: Like `project_id`, this is set manually after deserialization, as it depends on the
+    // configuration.
+    #[serde(skip)]
+    pub ranking_profile: RankingProfile,
 }
 
 #[derive(Serialize)]
@@ -91,10 +118,31 @@ pub struct QueryResponse {
     pub data: Vec<QueryResult>,
     /// Stats for nerds
     pub stats: ResultStats,
+    /// The collectors the results were gathered with, to explain how a query is run
+    #[serde(skip)]
+    pub(crate) collectors: Vec<String>,
 }
 
 impl crate::webserver::ApiResponse for QueryResponse {}
 
+/// An event of a streamed query, see `ApiQuery::query_stream`.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum QueryEvent {
+    /// A page of results from one repo, found with the given index
+    Results {
+        repo: String,
+        index: &'static str,
+        data: Vec<QueryResult>,
+    },
+
+    /// Sent once every repo has been searched
+    Stats {
+        metadata: PagingMetadata,
+        stats: ResultStats,
+    },
+}
+
 /// Metadata pertaining to the query response, such as paging info
 #[derive(Default, Serialize)]
 #[non_exhaustive]
@@ -111,6 +159,10 @@ pub struct PagingMetadata {
     /// total number of search results across all pages, only populated
     /// if the client requests it
     total_count: Option<usize>,
+
+    /// whether only the first candidates were checked for matches, so that `total_count` only
+    /// counts the results among them
+    truncated: bool,
 }
 
 #[derive(Default, Serialize, Deserialize, Debug)]
@@ -138,6 +190,9 @@ pub enum QueryResult {
     #[serde(rename = "dir")]
     Directory(DirectoryData),
 
+    #[serde(rename = "commit")]
+    Commit(CommitResultData),
+
     // Only returned by autocomplete
     #[serde(rename = "flag")]
     Flag(String),
@@ -148,15 +203,15 @@ pub enum QueryResult {
 
 #[derive(Serialize)]
 pub struct RepositoryResultData {
-    name: HighlightedString,
+    pub(crate) name: HighlightedString,
     repo_ref: String,
 }
 
 #[derive(Serialize)]
 pub struct FileResultData {
-    repo_name: String,
-    relative_path: HighlightedString,
-    repo_ref: RepoRef,
+    pub(crate) repo_name: String,
+    pub(crate) relative_path: HighlightedString,
+    pub(crate) repo_ref: RepoRef,
     lang: Option<String>,
     branches: String,
     indexed: bool,
@@ -187,8 +242,8 @@ impl FileResultData {
 
 #[derive(Serialize, Debug)]
 pub struct FileData {
-    repo_name: String,
-    relative_path: String,
+    pub(crate) repo_name: String,
+    pub(crate) relative_path: String,
     repo_ref: String,
     lang: Option<String>,
     contents: String,
@@ -201,12 +256,26 @@ pub struct FileData {
 
 #[derive(Serialize)]
 pub struct DirectoryData {
-    repo_name: String,
-    relative_path: String,
+    pub(crate) repo_name: String,
+    pub(crate) relative_path: String,
     repo_ref: String,
     entries: Vec<DirEntry>,
 }
 
+#[derive(Serialize)]
+pub struct CommitResultData {
+    pub(crate) repo_name: String,
+    repo_ref: String,
+    pub(crate) commit_id: String,
+    pub(crate) author: HighlightedString,
+    /// Author timestamp, in seconds since the unix epoch
+    pub(crate) timestamp: u64,
+    pub(crate) message: HighlightedString,
+    paths: Vec<String>,
+    /// Matches in the diff of the commit
+    snippets: Vec<Snippet>,
+}
+
 #[derive(Serialize, PartialEq, Eq, Hash, Clone, Debug)]
 pub struct DirEntry {
     name: String,
@@ -231,16 +300,89 @@ pub trait ExecuteQuery {
     ) -> Result<QueryResponse>;
 }
 
+/// Describe a collector reading `field`, to explain how a query is run.
+fn describe<T>(indexer: &Indexer<T>, collector: &str, field: Field) -> String {
+    format!(
+        "{collector}({})",
+        indexer.index.schema().get_field_name(field)
+    )
+}
+
 impl ApiQuery {
     pub async fn query(self: Arc<Self>, app: &Application) -> Result<QueryResponse> {
         let raw_query = self.q.clone();
         let queries = self
-            .restrict_queries(parser::parse(&raw_query)?, app)
+            .restrict_queries(parser::parse(&raw_query)?, &app.sql)
             .await?;
         tracing::debug!("compiled query as {queries:?}");
         self.query_with(Arc::clone(&app.indexes), queries).await
     }
 
+    /// Run this query like `query` does, but search each repo separately, yielding its results as
+    /// soon as they are found.
+    ///
+    /// Every repo is paginated on its own, so a page may contain up to `page_size` results per
+    /// repo. The final `QueryEvent::Stats` sums up the totals and frequencies of all repos.
+    pub fn query_stream(
+        self: Arc<Self>,
+        app: Application,
+    ) -> impl Stream<Item = Result<QueryEvent>> {
+        async_stream::try_stream! {
+            let queries = self
+                .restrict_queries(parser::parse(&self.q)?, &app.sql)
+                .await?;
+
+            let mut by_repo = BTreeMap::<_, Vec<_>>::new();
+            for q in queries {
+                by_repo.entry(q.repo_str().unwrap_or_default()).or_default().push(q);
+            }
+
+            let this = &self;
+            let indexes = &app.indexes;
+            let mut responses = futures::stream::iter(by_repo)
+                .map(|(repo, queries)| async move {
+                    let Some(reader) = ReaderKind::select(&queries) else {
+                        bail!("mangled query")
+                    };
+
+                    let response = this
+                        .query_indexers(&indexes.repo, &indexes.file, &indexes.commit, queries)
+                        .await?;
+
+                    anyhow::Ok((repo, reader, response))
+                })
+                .buffer_unordered(STREAM_CONCURRENCY);
+
+            let mut total_count = Some(0);
+            let mut truncated = false;
+            let mut stats = ResultStats::default();
+
+            while let Some(response) = responses.next().await {
+                let (repo, reader, response) = response?;
+
+                total_count = total_count
+                    .zip(response.metadata.total_count)
+                    .map(|(a, b)| a + b);
+                truncated |= response.metadata.truncated;
+                stats.merge(response.stats);
+
+                if !response.data.is_empty() {
+                    yield QueryEvent::Results {
+                        repo,
+                        index: reader.name(),
+                        data: response.data,
+                    };
+                }
+            }
+
+            yield QueryEvent::Stats {
+                metadata: PagingMetadata::new(self.page, self.page_size, total_count)
+                    .with_truncated(truncated),
+                stats,
+            };
+        }
+    }
+
     /// This restricts a set of input parser queries.
     ///
     /// We trim down the input by:
@@ -258,7 +400,7 @@ impl ApiQuery {
     pub async fn restrict_queries<'a>(
         &self,
         queries: impl IntoIterator<Item = parser::Query<'a>>,
-        app: &Application,
+        sql: &SqlDb,
     ) -> Result<Vec<parser::Query<'a>>> {
         let repo_branches = sqlx::query! {
             "SELECT repo_ref, branch
@@ -266,7 +408,7 @@ impl ApiQuery {
             WHERE project_id = ?",
             self.project_id,
         }
-        .fetch_all(&*app.sql)
+        .fetch_all(&**sql)
         .await?
         .into_iter()
         .map(|row| {
@@ -345,6 +487,21 @@ impl ApiQuery {
         self: Arc<Self>,
         indexes: Arc<Indexes>,
         queries: Vec<parser::Query<'_>>,
+    ) -> Result<QueryResponse> {
+        self.query_indexers(&indexes.repo, &indexes.file, &indexes.commit, queries)
+            .await
+    }
+
+    /// Execute a set of parsed queries against the repo, file and commit indexes.
+    ///
+    /// This is the core of `query_with`, which does not require the full set of `Indexes`, and
+    /// so can be used with indexes that are opened read-only.
+    pub async fn query_indexers(
+        &self,
+        repo: &Indexer<Repo>,
+        file: &Indexer<File>,
+        commit: &Indexer<Commit>,
+        queries: Vec<parser::Query<'_>>,
     ) -> Result<QueryResponse> {
         // FIXME: this for-loop prevents us from ever producing heterogenous
         // results.
@@ -370,23 +527,82 @@ impl ApiQuery {
         // For the time-being, we take the easy way out by prioritizing the first
         // target of the query, in this case `symbol:foo`. Queries that produce
         // homogenous results will work as expected: `repo:foo or repo:bar`.
-        for q in &queries {
-            if ContentReader.query_matches(q) {
-                tracing::trace!("executing with ContentReader");
-                return ContentReader.execute(&indexes.file, &queries, &self).await;
-            } else if RepoReader.query_matches(q) {
-                tracing::trace!("executing with RepoReader");
-                return RepoReader.execute(&indexes.repo, &queries, &self).await;
-            } else if FileReader.query_matches(q) {
-                tracing::trace!("executing with FileReader");
-                return FileReader.execute(&indexes.file, &queries, &self).await;
-            } else if OpenReader.query_matches(q) {
-                tracing::trace!("executing with OpenReader");
-                return OpenReader.execute(&indexes.file, &queries, &self).await;
-            }
+        let Some(reader) = ReaderKind::select(&queries) else {
+            bail!("mangled query")
+        };
+
+        tracing::trace!("executing with {reader:?}");
+        match reader {
+            ReaderKind::Commit => CommitReader.execute(commit, &queries, self).await,
+            ReaderKind::Content => ContentReader.execute(file, &queries, self).await,
+            ReaderKind::Repo => RepoReader.execute(repo, &queries, self).await,
+            ReaderKind::File => FileReader.execute(file, &queries, self).await,
+            ReaderKind::Open => OpenReader.execute(file, &queries, self).await,
         }
+    }
+
+    /// Explain how this query is parsed, planned, compiled and executed, without logging it.
+    pub async fn explain(self: Arc<Self>, app: &Application) -> Result<Explanation> {
+        let mut timings = Timings::default();
 
-        bail!("mangled query")
+        let parsed = parser::parse(&self.q)?;
+        timings.stage("parse");
+
+        let queries = self.restrict_queries(parsed.clone(), &app.sql).await?;
+        timings.stage("restrict");
+
+        let reader = ReaderKind::select(&queries);
+        let plans = RegexPlan::all(&queries);
+        timings.stage("plan");
+
+        let indexes = &app.indexes;
+        let compiled = match reader {
+            Some(ReaderKind::Commit) => Some(compile(&CommitReader, &indexes.commit, &queries)?),
+            Some(ReaderKind::Content) => Some(compile(&ContentReader, &indexes.file, &queries)?),
+            Some(ReaderKind::Repo) => Some(compile(&RepoReader, &indexes.repo, &queries)?),
+            Some(ReaderKind::File) => Some(compile(&FileReader, &indexes.file, &queries)?),
+            Some(ReaderKind::Open) => Some(compile(&OpenReader, &indexes.file, &queries)?),
+            None => None,
+        };
+        timings.stage("compile");
+
+        let index_hits = match reader {
+            Some(ReaderKind::Commit) => compiled.as_ref().map(|q| count(&indexes.commit, q)),
+            Some(ReaderKind::Repo) => compiled.as_ref().map(|q| count(&indexes.repo, q)),
+            Some(_) => compiled.as_ref().map(|q| count(&indexes.file, q)),
+            None => None,
+        }
+        .transpose()?;
+        timings.stage("count");
+
+        let response = match reader {
+            Some(_) => Some(
+                self.query_indexers(
+                    &indexes.repo,
+                    &indexes.file,
+                    &indexes.commit,
+                    queries.clone(),
+                )
+                .await?,
+            ),
+            None => None,
+        };
+        timings.stage("execute");
+
+        Ok(Explanation {
+            query: self.q.clone(),
+            parsed: serde_json::to_value(&parsed)?,
+            restricted: serde_json::to_value(&queries)?,
+            reader: reader.map(ReaderKind::name),
+            plans,
+            compiled: compiled.map(|q| format!("{q:?}")),
+            index_hits,
+            results: response.as_ref().map_or(0, |r| r.count),
+            total_count: response.as_ref().and_then(|r| r.metadata.total_count),
+            truncated: response.as_ref().map_or(false, |r| r.metadata.truncated),
+            collectors: response.map(|r| r.collectors).unwrap_or_default(),
+            timings: timings.stages,
+        })
     }
 
     fn limit(&self) -> usize {
@@ -399,6 +615,197 @@ impl ApiQuery {
     }
 }
 
+/// The readers that queries can be executed with, in order of precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReaderKind {
+    Commit,
+    Content,
+    Repo,
+    File,
+    Open,
+}
+
+impl ReaderKind {
+    /// Pick the reader of the first query that any reader can process.
+    fn select(queries: &[parser::Query<'_>]) -> Option<Self> {
+        queries.iter().find_map(|q| {
+            // Commit searches are checked first, as they can also carry a content target.
+            if CommitReader.query_matches(q) {
+                Some(Self::Commit)
+            } else if ContentReader.query_matches(q) {
+                Some(Self::Content)
+            } else if RepoReader.query_matches(q) {
+                Some(Self::Repo)
+            } else if FileReader.query_matches(q) {
+                Some(Self::File)
+            } else if OpenReader.query_matches(q) {
+                Some(Self::Open)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Commit => "commit",
+            Self::Content => "content",
+            Self::Repo => "repo",
+            Self::File => "file",
+            Self::Open => "open",
+        }
+    }
+}
+
+fn compile<R: DocumentRead>(
+    reader: &R,
+    indexer: &Indexer<R::Schema>,
+    queries: &[parser::Query<'_>],
+) -> Result<Box<dyn tantivy::query::Query>> {
+    let queries = queries.iter().filter(|q| reader.query_matches(q));
+    reader.compile(&indexer.source, queries, &indexer.index)
+}
+
+fn count<T>(indexer: &Indexer<T>, query: &dyn tantivy::query::Query) -> Result<usize> {
+    Ok(indexer
+        .reader
+        .searcher()
+        .search(query, &tantivy::collector::Count)?)
+}
+
+/// A breakdown of how a query is run, returned by `/q/explain`.
+#[derive(Serialize)]
+pub struct Explanation {
+    query: String,
+    /// Queries produced by the parser
+    parsed: serde_json::Value,
+    /// Queries after restricting them to the repos of the project
+    restricted: serde_json::Value,
+    /// The reader that executes the queries, if any reader accepts them
+    reader: Option<&'static str>,
+    /// Trigram plans of every regex in the restricted queries
+    plans: Vec<RegexPlan>,
+    /// The tantivy query compiled from the restricted queries
+    compiled: Option<String>,
+    /// Collectors filtering, ranking and counting the documents matched by the compiled query
+    collectors: Vec<String>,
+    /// Number of documents matched by the compiled query, before collectors filter them
+    index_hits: Option<usize>,
+    /// Number of results on the requested page
+    results: usize,
+    /// Number of results across all pages, if the reader calculates it
+    total_count: Option<usize>,
+    /// Whether `total_count` only counts the results among the first candidates
+    truncated: bool,
+    timings: Vec<Timing>,
+}
+
+impl crate::webserver::ApiResponse for Explanation {}
+
+#[derive(Serialize)]
+pub struct RegexPlan {
+    /// Index of the query in the restricted query list
+    query: usize,
+    field: &'static str,
+    /// Whether this regex comes from a negated term
+    excluded: bool,
+    regex: String,
+    plan: Option<String>,
+    optimized: Option<String>,
+    error: Option<String>,
+}
+
+impl RegexPlan {
+    fn all(queries: &[parser::Query<'_>]) -> Vec<Self> {
+        let mut plans = vec![];
+        for (i, q) in queries.iter().enumerate() {
+            Self::extend(&mut plans, i, q, false);
+            for excluded in &q.excluded {
+                Self::extend(&mut plans, i, excluded, true);
+            }
+        }
+
+        plans
+    }
+
+    fn extend(plans: &mut Vec<Self>, query: usize, q: &parser::Query<'_>, excluded: bool) {
+        // Structural patterns are compiled to a regex prefilter, which is what gets planned.
+        let target = q.target.as_ref().map(|t| match t {
+            parser::Target::Ast(pattern) => Pattern::parse(pattern)
+                .map(|p| p.prefilter())
+                .unwrap_or_else(|_| pattern.clone().into_owned()),
+            t => t.literal().clone().into_owned(),
+        });
+
+        let fields = [
+            ("org", q.org.as_ref()),
+            ("repo", q.repo.as_ref()),
+            ("path", q.path.as_ref()),
+            ("branch", q.branch.as_ref()),
+            ("commit", q.commit.as_ref()),
+            ("author", q.author.as_ref()),
+            ("target", target.as_ref()),
+        ];
+
+        for (field, literal) in fields {
+            let Some(parser::Literal::Regex(regex)) = literal else {
+                continue;
+            };
+
+            let (plan, optimized, error) = match planner::plan_unoptimized(regex)
+                .and_then(|p| Ok((p, planner::plan(regex)?)))
+            {
+                Ok((plan, optimized)) => {
+                    (Some(plan.to_string()), Some(optimized.to_string()), None)
+                }
+                Err(err) => (None, None, Some(err.to_string())),
+            };
+
+            plans.push(Self {
+                query,
+                field,
+                excluded,
+                regex: regex.to_string(),
+                plan,
+                optimized,
+                error,
+            });
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Timing {
+    stage: &'static str,
+    micros: u128,
+}
+
+struct Timings {
+    last: Instant,
+    stages: Vec<Timing>,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self {
+            last: Instant::now(),
+            stages: vec![],
+        }
+    }
+}
+
+impl Timings {
+    /// Record the time elapsed since the previous stage.
+    fn stage(&mut self, stage: &'static str) {
+        let now = Instant::now();
+        self.stages.push(Timing {
+            stage,
+            micros: (now - self.last).as_micros(),
+        });
+        self.last = now;
+    }
+}
+
 impl PagingMetadata {
     pub fn new(page: usize, page_size: usize, total_count: Option<usize>) -> Self {
         Self {
@@ -406,11 +813,28 @@ impl PagingMetadata {
             page_size,
             page_count: total_count.map(|t| div_ceil(t, page_size)),
             total_count,
+            truncated: false,
         }
     }
+
+    pub fn with_truncated(mut self, truncated: bool) -> Self {
+        self.truncated = truncated;
+        self
+    }
 }
 
 impl ResultStats {
+    /// Add the frequencies of `other` to these.
+    fn merge(&mut self, other: Self) {
+        for (lang, count) in other.lang {
+            *self.lang.entry(lang).or_default() += count;
+        }
+
+        for (repo, count) in other.repo {
+            *self.repo.entry(repo).or_default() += count;
+        }
+    }
+
     fn with_lang_freqs(mut self, mut lang_freqs: HashMap<Vec<u8>, usize>) -> Self {
         self.lang = lang_freqs
             .iter_mut()
@@ -454,14 +878,26 @@ impl ExecuteQuery for ContentReader {
         // - a symbol target: foo
         // - a content target: bar
         let targets = relevant_queries
-            .filter_map(|q| Some((q.target.as_ref()?, q.is_case_sensitive())))
+            .filter_map(|q| Some((q.target.as_ref()?, q.is_case_sensitive(), q.kind.as_deref())))
             .collect::<SmallVec<[_; 2]>>();
 
+        // structural patterns, keyed by their source text
+        let patterns = targets
+            .iter()
+            .filter_map(|(target, ..)| target.ast())
+            .map(|lit| Ok((lit.to_string(), Pattern::parse(lit)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+
         // a regex filter to get rid of docs that contain the trigrams but not the text
         let byte_regexes = targets
             .iter()
-            .filter_map(|(target, case)| {
-                ByteRegexBuilder::new(&target.literal().regex_str())
+            .filter_map(|(target, case, _)| {
+                let regex = match target {
+                    parser::Target::Ast(lit) => patterns[&**lit].prefilter().regex_str(),
+                    _ => target.literal().identifier_regex_str(),
+                };
+
+                ByteRegexBuilder::new(&regex)
                     .multi_line(true)
                     .case_insensitive(!case)
                     .build()
@@ -473,10 +909,32 @@ impl ExecuteQuery for ContentReader {
         let repo_field = indexer.source.raw_repo_name;
         let lang_field = indexer.source.lang;
 
+        // Structural matches and the kinds of matching symbols are only known after reading each
+        // document, so we fetch a bounded number of candidates and paginate over the documents
+        // that actually match.
+        let filtered = !patterns.is_empty() || targets.iter().any(|(.., kind)| kind.is_some());
+        let (limit, offset) = if filtered {
+            (MAX_FILTERED_CANDIDATES, 0)
+        } else {
+            (q.limit(), q.offset())
+        };
+
         // our results will consist of the top-k docs...
-        let top_k = TopDocs::with_limit(q.limit())
-            .and_offset(q.offset())
-            .tweak_score(DocumentTweaker(indexer.source.clone()));
+        let top_docs = TopDocs::with_limit(limit).and_offset(offset);
+        let collectors = vec![
+            describe(indexer, "BytesFilterCollector", raw_content),
+            format!(
+                "{top_docs:?}, tweaked by DocumentTweaker({})",
+                q.ranking.as_deref().unwrap_or("default")
+            ),
+            "Count".into(),
+            describe(indexer, "FrequencyCollector", lang_field),
+            describe(indexer, "FrequencyCollector", repo_field),
+        ];
+        let top_k = top_docs.tweak_score(DocumentTweaker(
+            indexer.source.clone(),
+            q.ranking_profile.clone(),
+        ));
 
         // ...plus some rich search metadata
         let total_count_collector = tantivy::collector::Count;
@@ -503,18 +961,24 @@ impl ExecuteQuery for ContentReader {
                 let snipper = Snipper::default().context(q.context_before, q.context_after);
                 let mut all_snippets = None::<SnippedFile>;
 
-                for (target, case_sensitive) in &targets {
-                    let (is_symbol, lit) = match target {
-                        parser::Target::Symbol(lit) => (true, lit),
-                        parser::Target::Content(lit) => (false, lit),
+                for (target, case_sensitive, kind) in &targets {
+                    let snippets = match target {
+                        parser::Target::Symbol(lit) | parser::Target::Content(lit) => snipper
+                            .find_symbols(matches!(target, parser::Target::Symbol(..)))
+                            .case_sensitive(*case_sensitive)
+                            .all_for_doc_of_kind(&lit.identifier_regex_str(), &doc, *kind)
+                            .unwrap(),
+                        parser::Target::Ast(lit) => doc
+                            .lang
+                            .as_deref()
+                            .and_then(|lang| {
+                                TreeSitterFile::try_build(doc.content.as_bytes(), lang).ok()
+                            })
+                            .and_then(|file| file.structural_matches(&patterns[&**lit]).ok())
+                            .and_then(|ranges| snipper.all_for_ranges(ranges, &doc)),
                     };
 
-                    if let Some(snippets) = snipper
-                        .find_symbols(is_symbol)
-                        .case_sensitive(*case_sensitive)
-                        .all_for_doc(&lit.regex_str(), &doc)
-                        .unwrap()
-                    {
+                    if let Some(snippets) = snippets {
                         all_snippets = if let Some(data) = all_snippets {
                             Some(data.merge(snippets))
                         } else {
@@ -527,13 +991,24 @@ impl ExecuteQuery for ContentReader {
             })
             .collect::<Vec<QueryResult>>();
 
-        let total_count = total_count_handle.extract(&mut results.metadata);
+        let mut total_count = total_count_handle.extract(&mut results.metadata);
+
+        // Only matches among the candidates are counted, so the count is truncated when there
+        // were more candidates to check.
+        let truncated = filtered && total_count > MAX_FILTERED_CANDIDATES;
+        let data = if filtered {
+            total_count = data.len();
+            data.into_iter().skip(q.offset()).take(q.limit()).collect()
+        } else {
+            data
+        };
 
         let stats = ResultStats::default()
             .with_lang_freqs(lang_stats_handle.extract(&mut results.metadata))
             .with_repo_freqs(repo_stats_handle.extract(&mut results.metadata));
 
-        let metadata = PagingMetadata::new(q.page, q.page_size, Some(total_count));
+        let metadata =
+            PagingMetadata::new(q.page, q.page_size, Some(total_count)).with_truncated(truncated);
 
         let count = data.len();
         let response = QueryResponse {
@@ -541,6 +1016,7 @@ impl ExecuteQuery for ContentReader {
             metadata,
             data,
             stats,
+            collectors,
         };
         Ok(response)
     }
@@ -556,6 +1032,12 @@ impl ExecuteQuery for FileReader {
         queries: &[parser::Query<'_>],
         q: &ApiQuery,
     ) -> Result<QueryResponse> {
+        // Paths only need filtering if every query has a path regex.
+        let any_path = queries
+            .iter()
+            .filter(|q| self.query_matches(q))
+            .any(|q| q.path.is_none());
+
         let (filter_regexes, byte_filter_regexes): (Vec<_>, Vec<_>) = queries
             .iter()
             .filter(|q| self.query_matches(q))
@@ -580,6 +1062,14 @@ impl ExecuteQuery for FileReader {
         let repo_field = indexer.source.raw_repo_name;
         let lang_field = indexer.source.lang;
 
+        let collectors = vec![
+            describe(indexer, "BytesFilterCollector", path_field),
+            format!("{top_k:?}"),
+            "Count".into(),
+            describe(indexer, "FrequencyCollector", lang_field),
+            describe(indexer, "FrequencyCollector", repo_field),
+        ];
+
         let total_count_collector = tantivy::collector::Count;
         let lang_stats_collector = FrequencyCollector(lang_field);
         let repo_stats_collector = FrequencyCollector(repo_field);
@@ -591,7 +1081,8 @@ impl ExecuteQuery for FileReader {
 
         let collector = BytesFilterCollector::new(
             path_field,
-            move |b| byte_filter_regexes.iter().any(|r| r.is_match(b)), // a doc is accepted if it contains at least 1 target
+            // a doc is accepted if it matches at least 1 path, or any path is allowed
+            move |b| any_path || byte_filter_regexes.iter().any(|r| r.is_match(b)),
             (top_k, metadata_collector),
         );
 
@@ -631,6 +1122,121 @@ impl ExecuteQuery for FileReader {
             data,
             metadata,
             stats,
+            collectors,
+        };
+
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl ExecuteQuery for CommitReader {
+    type Index = Commit;
+
+    async fn execute(
+        &self,
+        indexer: &Indexer<Self::Index>,
+        queries: &[parser::Query<'_>],
+        q: &ApiQuery,
+    ) -> Result<QueryResponse> {
+        let regexes =
+            |literal: for<'a> fn(&'a parser::Query<'a>) -> Option<&'a parser::Literal<'a>>| {
+                queries
+                    .iter()
+                    .filter(|q| self.query_matches(q))
+                    .filter_map(|q| {
+                        RegexBuilder::new(&literal(q)?.identifier_regex_str())
+                            .multi_line(true)
+                            .case_insensitive(!q.is_case_sensitive())
+                            .build()
+                            .ok()
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+        // regexes to highlight matches, and to get rid of docs that contain the trigrams but not
+        // the text
+        let content_regexes = regexes(|q| {
+            q.commit
+                .as_ref()
+                .or_else(|| q.target.as_ref().and_then(parser::Target::content))
+        });
+        let author_regexes = regexes(|q| q.author.as_ref());
+
+        // newest commits first
+        let top_docs = TopDocs::with_limit(q.limit()).and_offset(q.offset());
+        let collectors = vec![
+            format!("{top_docs:?}, tweaked by CommitTweaker"),
+            "Count".into(),
+            describe(indexer, "FrequencyCollector", indexer.source.raw_repo_name),
+        ];
+        let top_k = top_docs.tweak_score(CommitTweaker(indexer.source.clone()));
+
+        let total_count_collector = tantivy::collector::Count;
+        let repo_stats_collector = FrequencyCollector(indexer.source.raw_repo_name);
+
+        let mut metadata_collector = MultiCollector::new();
+        let total_count_handle = metadata_collector.add_collector(total_count_collector);
+        let repo_stats_handle = metadata_collector.add_collector(repo_stats_collector);
+
+        let mut results = indexer
+            .query(queries.iter(), self, (top_k, metadata_collector))
+            .await?;
+
+        let snipper = Snipper::default().context(q.context_before, q.context_after);
+        let data = results
+            .docs
+            .filter(|doc| {
+                let content_matches = content_regexes.is_empty()
+                    || content_regexes
+                        .iter()
+                        .any(|r| r.is_match(&doc.message) || r.is_match(&doc.diff));
+                let author_matches = author_regexes.is_empty()
+                    || author_regexes.iter().any(|r| r.is_match(&doc.author));
+
+                content_matches && author_matches
+            })
+            .map(|doc| {
+                let mut message = HighlightedString::new(doc.message);
+                let mut author = HighlightedString::new(doc.author);
+                let mut snippets = vec![];
+
+                for regex in &content_regexes {
+                    message.apply_regex(regex);
+                    snippets.extend(snipper.all_for_text(regex, &doc.diff));
+                }
+
+                for regex in &author_regexes {
+                    author.apply_regex(regex);
+                }
+
+                QueryResult::Commit(CommitResultData {
+                    repo_name: doc.repo_name,
+                    repo_ref: doc.repo_ref,
+                    commit_id: doc.commit_id,
+                    author,
+                    timestamp: doc.date,
+                    message,
+                    paths: doc.paths,
+                    snippets,
+                })
+            })
+            .collect::<Vec<QueryResult>>();
+
+        // The total is counted before filtering by regex, so it can be an overestimate.
+        let total_count = total_count_handle.extract(&mut results.metadata);
+
+        let stats = ResultStats::default()
+            .with_repo_freqs(repo_stats_handle.extract(&mut results.metadata));
+
+        let metadata = PagingMetadata::new(q.page, q.page_size, Some(total_count));
+
+        let response = QueryResponse {
+            count: data.len(),
+            data,
+            metadata,
+            stats,
+            collectors,
         };
 
         Ok(response)
@@ -647,6 +1253,12 @@ impl ExecuteQuery for RepoReader {
         queries: &[parser::Query<'_>],
         q: &ApiQuery,
     ) -> Result<QueryResponse> {
+        // Paths only need filtering if every query has a path regex.
+        let any_path = queries
+            .iter()
+            .filter(|q| self.query_matches(q))
+            .any(|q| q.path.is_none());
+
         let (filter_regexes, byte_filter_regexes): (Vec<_>, Vec<_>) = queries
             .iter()
             .filter(|q| self.query_matches(q))
@@ -668,6 +1280,13 @@ impl ExecuteQuery for RepoReader {
         let top_k = TopDocs::with_limit(q.limit()).and_offset(q.offset());
 
         let name_field = indexer.source.raw_name;
+        let collectors = vec![
+            describe(indexer, "BytesFilterCollector", name_field),
+            format!("{top_k:?}"),
+            describe(indexer, "FrequencyCollector", name_field),
+            "Count".into(),
+        ];
+
         let repo_stats_collector = FrequencyCollector(name_field);
         let total_count_collector = tantivy::collector::Count;
 
@@ -710,6 +1329,7 @@ impl ExecuteQuery for RepoReader {
             data,
             metadata,
             stats,
+            collectors,
         };
 
         Ok(response)
@@ -747,8 +1367,13 @@ impl ExecuteQuery for OpenReader {
             })
             .collect::<SmallVec<[_; 2]>>();
 
+        let path_field = indexer.source.raw_relative_path;
         let top_docs = TopDocs::with_limit(50000);
         let empty_collector = MultiCollector::new();
+        let collectors = vec![
+            describe(indexer, "BytesFilterCollector", path_field),
+            format!("{top_docs:?}"),
+        ];
 
         let relative_paths = open_directives
             .iter()
@@ -758,7 +1383,7 @@ impl ExecuteQuery for OpenReader {
         tracing::trace!(?relative_paths, "creating collector");
 
         let collector = BytesFilterCollector::new(
-            indexer.source.raw_relative_path,
+            path_field,
             move |b| {
                 let Ok(relative_path) = std::str::from_utf8(b) else {
                     return false;
@@ -891,6 +1516,7 @@ impl ExecuteQuery for OpenReader {
             data,
             metadata: PagingMetadata::default(),
             stats: ResultStats::default(),
+            collectors,
         };
 
         Ok(response)
@@ -936,7 +1562,8 @@ mod tests {
               "page": 0,
               "page_size": 100,
               "page_count": 6,
-              "total_count": 520
+              "total_count": 520,
+              "truncated": false
           },
           "stats": {
             "repo": {"local//bleep": 1},
@@ -967,12 +1594,203 @@ mod tests {
                 page: 0,
                 page_size: 100,
                 page_count: Some(6),
-                total_count: Some(520)
+                total_count: Some(520),
+                truncated: false,
             },
             stats: ResultStats { repo: repos, lang: langs },
+            collectors: vec![],
         })
         .unwrap();
 
         assert_eq!(expected, observed);
     }
+
+    #[test]
+    fn serialize_stats_event() {
+        let mut stats = ResultStats {
+            repo: HashMap::from([("bleep".into(), 2)]),
+            lang: HashMap::from([("Rust".into(), 2)]),
+        };
+        stats.merge(ResultStats {
+            repo: HashMap::from([("bloop".into(), 1)]),
+            lang: HashMap::from([("Rust".into(), 1)]),
+        });
+
+        let observed = serde_json::to_value(QueryEvent::Stats {
+            metadata: PagingMetadata::new(0, 2, Some(3)),
+            stats,
+        })
+        .unwrap();
+
+        let expected = serde_json::json!({
+            "kind": "stats",
+            "metadata": {
+                "page": 0,
+                "page_size": 2,
+                "page_count": 2,
+                "total_count": 3,
+                "truncated": false
+            },
+            "stats": {
+                "repo": {"bleep": 2, "bloop": 1},
+                "lang": {"Rust": 3}
+            }
+        });
+
+        assert_eq!(expected, observed);
+    }
+
+    #[test]
+    fn select_reader_for_exclusions() {
+        let select = |q: &str| ReaderKind::select(&parser::parse(q).unwrap());
+
+        assert_eq!(select("foo -bar"), Some(ReaderKind::Content));
+        assert_eq!(select("repo:bloop -repo:bleep"), Some(ReaderKind::Repo));
+
+        // Repos can't be filtered by their files, so these list files instead.
+        assert_eq!(select("repo:bloop -foo"), Some(ReaderKind::File));
+        assert_eq!(select("repo:bloop -path:test"), Some(ReaderKind::File));
+        assert_eq!(select("-foo"), Some(ReaderKind::File));
+    }
+
+    /// Index Rust files into a temporary file index.
+    fn index_files(files: &[(&str, &str)]) -> (tempdir::TempDir, Indexer<File>) {
+        let dir = tempdir::TempDir::new("file-index").unwrap();
+        let indexer = Indexer::create(File::new(), dir.path(), 50_000_000, 1).unwrap();
+
+        {
+            let schema = &indexer.source;
+            let mut writer = indexer.write_handle().unwrap();
+
+            for &(path, content) in files {
+                let symbol_locations = TreeSitterFile::try_build(content.as_bytes(), "rust")
+                    .and_then(TreeSitterFile::scope_graph)
+                    .map(crate::symbol::SymbolLocations::TreeSitter)
+                    .unwrap();
+                let symbols = symbol_locations
+                    .list()
+                    .iter()
+                    .map(|sym| &content[sym.range.start.byte..sym.range.end.byte])
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let line_end_indices = content
+                    .match_indices('\n')
+                    .flat_map(|(i, _)| u32::to_le_bytes(i as u32))
+                    .collect::<Vec<_>>();
+
+                let mut doc = tantivy::doc!(
+                    schema.unique_hash => path,
+                    schema.relative_path => path,
+                    schema.repo_ref => "github.com/BloopAI/bloop",
+                    schema.repo_name => "BloopAI/bloop",
+                    schema.content => content,
+                    schema.line_end_indices => line_end_indices,
+                    schema.symbols => symbols,
+                    schema.symbol_locations => bincode::serialize(&symbol_locations).unwrap(),
+                    schema.lang => "rust".as_bytes(),
+                    schema.branches => "main",
+                    schema.is_directory => false,
+                    schema.indexed => true,
+                    schema.size => content.len() as u64,
+                );
+                schema.restore_document(&mut doc, "/repo", 0);
+                writer.add_document(doc).unwrap();
+            }
+
+            writer.commit().unwrap();
+        }
+
+        (dir, indexer)
+    }
+
+    async fn search_content(indexer: &Indexer<File>, q: &str) -> QueryResponse {
+        let api_query: ApiQuery = serde_json::from_value(serde_json::json!({ "q": q })).unwrap();
+        let queries = parser::parse(q).unwrap();
+        ContentReader
+            .execute(indexer, &queries, &api_query)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn count_symbols_of_a_kind() {
+        let (_dir, indexer) = index_files(&[
+            ("src/parse.rs", "fn parse() {}\n"),
+            ("src/parser.rs", "struct parse;\n\nfn other() {}\n"),
+        ]);
+
+        let paths = |response: &QueryResponse| {
+            response
+                .data
+                .iter()
+                .map(|result| match result {
+                    QueryResult::Snippets(file) => file.relative_path.clone(),
+                    _ => panic!("expected snippets"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let all = search_content(&indexer, "symbol:parse").await;
+        assert_eq!(all.metadata.total_count, Some(2));
+
+        // Both files have a function, but only one of them is called `parse`.
+        let functions = search_content(&indexer, "symbol:parse kind:function").await;
+        assert_eq!(paths(&functions), ["src/parse.rs"]);
+        assert_eq!(functions.metadata.total_count, Some(1));
+    }
+
+    #[tokio::test]
+    async fn truncated_count_of_symbols() {
+        let paths = (0..=MAX_FILTERED_CANDIDATES)
+            .map(|i| format!("src/parse_{i}.rs"))
+            .collect::<Vec<_>>();
+        let files = paths
+            .iter()
+            .map(|path| (path.as_str(), "fn parse() {}\n"))
+            .collect::<Vec<_>>();
+        let (_dir, indexer) = index_files(&files);
+
+        let all = search_content(&indexer, "symbol:parse").await;
+        assert_eq!(all.metadata.total_count, Some(MAX_FILTERED_CANDIDATES + 1));
+        assert!(!all.metadata.truncated);
+
+        // Only the first candidates are checked for symbols of the requested kind.
+        let functions = search_content(&indexer, "symbol:parse kind:function").await;
+        assert_eq!(
+            functions.metadata.total_count,
+            Some(MAX_FILTERED_CANDIDATES)
+        );
+        assert!(functions.metadata.truncated);
+    }
+
+    #[tokio::test]
+    async fn explain_collectors_of_a_search() {
+        let (_dir, indexer) = index_files(&[("src/parse.rs", "fn parse() {}\n")]);
+
+        let collectors = |top_docs: &str| {
+            vec![
+                "BytesFilterCollector(raw_content)".to_owned(),
+                format!("{top_docs}, tweaked by DocumentTweaker(default)"),
+                "Count".to_owned(),
+                "FrequencyCollector(lang)".to_owned(),
+                "FrequencyCollector(raw_repo_name)".to_owned(),
+            ]
+        };
+
+        let plain = search_content(&indexer, "symbol:parse").await;
+        assert_eq!(
+            plain.collectors,
+            collectors(&format!("{:?}", TopDocs::with_limit(100)))
+        );
+
+        // Symbols of a kind are filtered after collecting, from a bounded number of candidates.
+        let filtered = search_content(&indexer, "symbol:parse kind:function").await;
+        assert_eq!(
+            filtered.collectors,
+            collectors(&format!(
+                "{:?}",
+                TopDocs::with_limit(MAX_FILTERED_CANDIDATES)
+            ))
+        );
+    }
 }
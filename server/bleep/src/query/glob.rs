@@ -0,0 +1,147 @@
+//! Path globs, such as `src/**/*.rs`, translated to regexes.
+//!
+//! - `*` matches any part of a file or directory name, and `?` a single character in one
+//! - `**` matches any number of directories, including none
+//! - `{rs,toml}` matches either alternative, and `[abc]` or `[!abc]` a character class
+//!
+//! As in `.gitignore` files, a glob without a `/` matches file names at any depth, while a glob
+//! with one is anchored to the repository root.
+
+use std::{iter::Peekable, str::Chars};
+
+/// Whether an unquoted path should be read as a glob, rather than as a plain substring.
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '{'])
+}
+
+/// Translate a glob into a regex matching whole paths.
+pub fn to_regex(glob: &str) -> String {
+    let anchored = glob.trim_end_matches('/').contains('/');
+
+    let mut glob = glob.trim_start_matches('/').to_owned();
+    if glob.ends_with('/') {
+        // A directory matches everything under it.
+        glob.push_str("**");
+    }
+
+    let mut regex = String::from("^");
+    if !anchored {
+        regex.push_str("(?:.*/)?");
+    }
+
+    let mut chars = glob.chars().peekable();
+    let mut in_braces = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.next_if_eq(&'/').is_some() {
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '{' if !in_braces => {
+                in_braces = true;
+                regex.push_str("(?:");
+            }
+            '}' if in_braces => {
+                in_braces = false;
+                regex.push(')');
+            }
+            ',' if in_braces => regex.push('|'),
+            '[' => match class(&mut chars) {
+                Some(class) => regex.push_str(&class),
+                None => regex.push_str(r"\["),
+            },
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    push_escaped(&mut regex, c);
+                }
+            }
+            c => push_escaped(&mut regex, c),
+        }
+    }
+
+    if in_braces {
+        regex.push(')');
+    }
+
+    regex.push('$');
+    regex
+}
+
+/// Read a character class after its opening `[`, leaving `chars` untouched if it isn't closed.
+fn class(chars: &mut Peekable<Chars<'_>>) -> Option<String> {
+    let mut lookahead = chars.clone();
+    let mut class = String::from("[");
+
+    if lookahead.next_if(|c| *c == '!' || *c == '^').is_some() {
+        class.push('^');
+    }
+
+    loop {
+        match lookahead.next()? {
+            ']' => break,
+            c if c.is_alphanumeric() || c == '-' => class.push(c),
+            c => {
+                class.push('\\');
+                class.push(c);
+            }
+        }
+    }
+
+    class.push(']');
+    *chars = lookahead;
+    Some(class)
+}
+
+fn push_escaped(regex: &mut String, c: char) {
+    let mut buf = [0; 4];
+    regex.push_str(&regex::escape(c.encode_utf8(&mut buf)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(glob: &str, path: &str) -> bool {
+        regex::Regex::new(&to_regex(glob)).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn translate() {
+        assert_eq!(to_regex("src/**/*.rs"), r"^src/(?:.*/)?[^/]*\.rs$");
+        assert_eq!(to_regex("*.rs"), r"^(?:.*/)?[^/]*\.rs$");
+        assert_eq!(to_regex("**/vendor/**"), r"^(?:.*/)?vendor/.*$");
+        assert_eq!(to_regex("*.{rs,toml}"), r"^(?:.*/)?[^/]*\.(?:rs|toml)$");
+        assert_eq!(to_regex("file[!0-9].txt"), r"^(?:.*/)?file[^0-9]\.txt$");
+        assert_eq!(to_regex("a[b"), r"^(?:.*/)?a\[b$");
+    }
+
+    #[test]
+    fn matches() {
+        assert!(is_match("src/**/*.rs", "src/main.rs"));
+        assert!(is_match("src/**/*.rs", "src/query/glob.rs"));
+        assert!(!is_match("src/**/*.rs", "server/src/main.rs"));
+        assert!(!is_match("src/**/*.rs", "src/main.rsx"));
+
+        assert!(is_match("*.rs", "main.rs"));
+        assert!(is_match("*.rs", "server/src/main.rs"));
+
+        assert!(is_match("**/vendor/**", "vendor/lib.js"));
+        assert!(is_match("**/vendor/**", "client/vendor/lib/a.js"));
+        assert!(!is_match("**/vendor/**", "client/vendored.js"));
+
+        assert!(is_match("vendor/", "client/vendor/lib.js"));
+        assert!(!is_match("vendor/", "vendored/lib.js"));
+        assert!(is_match("/Cargo.toml", "Cargo.toml"));
+        assert!(!is_match("/Cargo.toml", "server/Cargo.toml"));
+
+        assert!(is_match("test_?.py", "tests/test_a.py"));
+        assert!(!is_match("test_?.py", "tests/test_ab.py"));
+    }
+}
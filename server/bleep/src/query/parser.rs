@@ -1,9 +1,14 @@
+use crate::indexes::tokenizer::split_identifier;
 use pest::{iterators::Pair, Parser};
 use regex::Regex;
 use smallvec::{smallvec, SmallVec};
-use std::{borrow::Cow, mem, ops::Deref};
+use std::{
+    borrow::Cow,
+    mem,
+    ops::{Bound, Deref},
+};
 
-#[derive(Default, Clone, Debug, PartialEq, Eq)]
+#[derive(Default, Clone, Debug, PartialEq, Eq, serde::Serialize)]
 pub struct Query<'a> {
     pub open: Option<bool>,
     pub case_sensitive: Option<bool>,
@@ -14,13 +19,36 @@ pub struct Query<'a> {
     pub path: Option<Literal<'a>>,
     pub lang: Option<Literal<'a>>,
     pub branch: Option<Literal<'a>>,
+    /// Kind of the searched symbols, e.g. `function` or `struct`
+    pub kind: Option<Literal<'a>>,
+    /// Only match files last changed before this time, in seconds since the unix epoch
+    pub modified_before: Option<u64>,
+    /// Only match files last changed at or after this time, in seconds since the unix epoch
+    pub modified_after: Option<u64>,
     pub target: Option<Target<'a>>,
+
+    /// Text in the message or diff of the searched commits
+    pub commit: Option<Literal<'a>>,
+    /// Name or email of the author of the searched commits
+    pub author: Option<Literal<'a>>,
+    /// Only match commits authored before this time, in seconds since the unix epoch
+    pub before: Option<u64>,
+    /// Only match commits authored at or after this time, in seconds since the unix epoch
+    pub after: Option<u64>,
+
+    /// Negated terms, such as `-path:test`. Documents matching any of these are excluded.
+    ///
+    /// Each excluded query holds a single term, so that it can be compiled with the same
+    /// extractors as the positive terms.
+    pub excluded: Vec<Query<'a>>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize)]
 pub enum Target<'a> {
     Symbol(Literal<'a>),
     Content(Literal<'a>),
+    /// A structural pattern, see [`super::structural::Pattern`].
+    Ast(Literal<'a>),
 }
 
 #[derive(Default, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -100,10 +128,20 @@ impl<'a> Query<'a> {
             path: rhs.path.or(self.path),
             lang: rhs.lang.or(self.lang),
             branch: rhs.branch.or(self.branch),
+            kind: rhs.kind.or(self.kind),
+            modified_before: rhs.modified_before.or(self.modified_before),
+            modified_after: rhs.modified_after.or(self.modified_after),
+
+            commit: rhs.commit.or(self.commit),
+            author: rhs.author.or(self.author),
+            before: rhs.before.or(self.before),
+            after: rhs.after.or(self.after),
+
+            excluded: self.excluded.into_iter().chain(rhs.excluded).collect(),
 
             target: match (self.target, rhs.target) {
                 (Some(Target::Content(lhs)), Some(Target::Content(rhs))) => {
-                    Some(Target::Content(lhs.join_as_regex(rhs)))
+                    Some(Target::Content(lhs.join(rhs)))
                 }
 
                 // TODO: Do we want to return an error here?
@@ -157,6 +195,31 @@ impl<'a> Query<'a> {
         self.case_sensitive.unwrap_or_default()
     }
 
+    /// Whether this query searches the commit history, rather than files.
+    pub fn is_commit_search(&self) -> bool {
+        self.commit.is_some()
+            || self.author.is_some()
+            || self.before.is_some()
+            || self.after.is_some()
+            || self.excluded.iter().any(Query::is_commit_search)
+    }
+
+    /// The range of last commit times of the files this query is restricted to, if any.
+    pub fn modified_range(&self) -> Option<(Bound<u64>, Bound<u64>)> {
+        if self.modified_before.is_none() && self.modified_after.is_none() {
+            return None;
+        }
+
+        Some((
+            self.modified_after
+                .map(Bound::Included)
+                .unwrap_or(Bound::Unbounded),
+            self.modified_before
+                .map(Bound::Excluded)
+                .unwrap_or(Bound::Unbounded),
+        ))
+    }
+
     fn set_global_regex(&mut self, value: Option<bool>) {
         self.global_regex = value;
         if let Some(true) = value {
@@ -164,6 +227,12 @@ impl<'a> Query<'a> {
             self.repo.as_mut().map(Literal::make_regex);
             self.path.as_mut().map(Literal::make_regex);
             self.target.as_mut().map(Target::make_regex);
+            self.commit.as_mut().map(Literal::make_regex);
+            self.author.as_mut().map(Literal::make_regex);
+        }
+
+        for q in self.excluded.iter_mut() {
+            q.set_global_regex(value);
         }
     }
 }
@@ -192,6 +261,7 @@ impl<'a> Target<'a> {
         match self {
             Self::Symbol(lit) => lit,
             Self::Content(lit) => lit,
+            Self::Ast(lit) => lit,
         }
     }
 
@@ -200,6 +270,7 @@ impl<'a> Target<'a> {
         match self {
             Self::Symbol(lit) => lit,
             Self::Content(lit) => lit,
+            Self::Ast(lit) => lit,
         }
     }
 
@@ -207,15 +278,23 @@ impl<'a> Target<'a> {
     pub fn symbol(&self) -> Option<&Literal<'_>> {
         match self {
             Self::Symbol(lit) => Some(lit),
-            Self::Content(_) => None,
+            Self::Content(_) | Self::Ast(_) => None,
         }
     }
 
     /// Get the content literal, if present
     pub fn content(&self) -> Option<&Literal<'_>> {
         match self {
-            Self::Symbol(_) => None,
             Self::Content(lit) => Some(lit),
+            Self::Symbol(_) | Self::Ast(_) => None,
+        }
+    }
+
+    /// Get the structural pattern, if present
+    pub fn ast(&self) -> Option<&Literal<'_>> {
+        match self {
+            Self::Ast(lit) => Some(lit),
+            Self::Symbol(_) | Self::Content(_) => None,
         }
     }
 
@@ -223,6 +302,8 @@ impl<'a> Target<'a> {
         match self {
             Self::Symbol(lit) => lit.make_regex(),
             Self::Content(lit) => lit.make_regex(),
+            // Patterns have their own syntax, and are never treated as regexes.
+            Self::Ast(_) => {}
         }
     }
 }
@@ -239,6 +320,8 @@ pub enum ParseError {
     UnparsedToken(String),
     #[error("multiple mode designators")]
     MultiMode,
+    #[error("`kind:` can only be used with `symbol:`")]
+    KindWithoutSymbol,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
@@ -325,6 +408,25 @@ impl<'a> Default for Literal<'a> {
 }
 
 impl<'a> Literal<'a> {
+    /// Join two content literals.
+    ///
+    /// Plain identifiers are joined with a space, so that `retry policy` can still be matched as
+    /// an identifier. Anything else is joined as a regex.
+    fn join(self, rhs: Self) -> Literal<'static> {
+        match (self, rhs) {
+            (Self::Plain(lhs), Self::Plain(rhs))
+                if is_identifiers(&lhs.content) && is_identifiers(&rhs.content) =>
+            {
+                Literal::Plain(LiteralInner::new(
+                    lhs.start,
+                    rhs.end,
+                    format!("{} {}", lhs.content, rhs.content),
+                ))
+            }
+            (lhs, rhs) => lhs.join_as_regex(rhs),
+        }
+    }
+
     /// This drops position information, as it's not intelligible after the merge
     fn join_as_regex(self, rhs: Self) -> Literal<'static> {
         let lhs = self.regex_str();
@@ -347,6 +449,57 @@ impl<'a> Literal<'a> {
         Regex::new(&self.regex_str())
     }
 
+    /// The words of a plain literal made of identifiers, e.g. `RetryPolicy` or `retry policy`.
+    ///
+    /// This is `None` for regexes, for other plain text, and for literals of a single word.
+    pub fn identifier_words(&self) -> Option<Vec<&str>> {
+        let Self::Plain(inner) = self else {
+            return None;
+        };
+
+        if !is_identifiers(&inner.content) {
+            return None;
+        }
+
+        let words = inner
+            .content
+            .split_whitespace()
+            .flat_map(split_identifier)
+            .collect::<Vec<_>>();
+
+        (words.len() > 1).then_some(words)
+    }
+
+    /// Convert this literal into a regex string, like `regex_str`.
+    ///
+    /// Identifiers match however their words are cased and joined, so that `RetryPolicy` also
+    /// matches `retry_policy`, `retry-policy` and `retry policy` when searching case insensitively.
+    /// Words must still be separated, or start with a capital, so `foo-bar` doesn't match `foobar`.
+    pub fn identifier_regex_str(&self) -> Cow<'a, str> {
+        let Some(words) = self.identifier_words() else {
+            return self.regex_str();
+        };
+
+        let mut regex = regex::escape(words[0]);
+        for word in &words[1..] {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap_or_default();
+            let upper = first.to_uppercase().collect::<String>();
+
+            regex.push_str(r"(?:[\s_-]+");
+            regex.push_str(&regex::escape(word));
+            if upper != first.to_lowercase().collect::<String>() {
+                regex.push_str("|(?-i:");
+                regex.push_str(&regex::escape(&upper));
+                regex.push(')');
+                regex.push_str(&regex::escape(chars.as_str()));
+            }
+            regex.push(')');
+        }
+
+        regex.into()
+    }
+
     pub fn as_plain(&self) -> Option<Cow<'a, str>> {
         match self {
             Self::Plain(p) => Some(p.content.clone()),
@@ -474,10 +627,20 @@ enum Expr<'a> {
     Org(Literal<'a>),
     Repo(Literal<'a>),
     Symbol(Literal<'a>),
+    Ast(Literal<'a>),
     Path(Literal<'a>),
     Lang(Literal<'a>),
     Content(Literal<'a>),
     Branch(Literal<'a>),
+    Kind(Literal<'a>),
+    ModifiedBefore(u64),
+    ModifiedAfter(u64),
+    Commit(Literal<'a>),
+    Author(Literal<'a>),
+    Before(u64),
+    After(u64),
+
+    Not(Box<Expr<'a>>),
 
     CaseSensitive(bool),
     Open(bool),
@@ -495,12 +658,55 @@ impl<'a> Expr<'a> {
             | Rule::regex_quoted_literal => Content(Literal::from(pair)),
 
             Rule::content => Content(Literal::from(pair.into_inner().next().unwrap())),
-            Rule::path => Path(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::path => parse_path(pair.into_inner().next().unwrap()),
             Rule::repo => Repo(Literal::from(pair.into_inner().next().unwrap())),
             Rule::symbol => Symbol(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::ast => Ast(Literal::from(pair.into_inner().next().unwrap())),
             Rule::org => Org(Literal::from(pair.into_inner().next().unwrap())),
             Rule::branch => Branch(Literal::from(pair.into_inner().next().unwrap())),
             Rule::lang => Lang(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::kind => Kind(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::commit => Commit(Literal::from(pair.into_inner().next().unwrap())),
+            Rule::author => Author(Literal::from(pair.into_inner().next().unwrap())),
+
+            Rule::modified => {
+                let mut inner = pair.clone().into_inner();
+                let newer = inner.next().unwrap().as_str() == ">";
+                let value = inner.next().unwrap().as_str();
+
+                // Ages count backwards, so `<30d` is newer than 30 days ago.
+                let (time, newer) = match parse_date(value) {
+                    Some(date) => (date, newer),
+                    None => match parse_age(value) {
+                        Some(age) => (now().saturating_sub(age), !newer),
+                        None => return Err(pair),
+                    },
+                };
+
+                if newer {
+                    ModifiedAfter(time)
+                } else {
+                    ModifiedBefore(time)
+                }
+            }
+
+            Rule::before | Rule::after => {
+                let Some(date) = parse_date(pair.clone().into_inner().next().unwrap().as_str())
+                else {
+                    return Err(pair);
+                };
+
+                if pair.as_rule() == Rule::before {
+                    Before(date)
+                } else {
+                    After(date)
+                }
+            }
+
+            Rule::negation => Not(Box::new(Self::parse(
+                pair.into_inner().next().unwrap(),
+                top_level,
+            )?)),
 
             Rule::open => {
                 let inner = pair.into_inner().next().unwrap();
@@ -594,6 +800,15 @@ pub fn parse(query: &str) -> Result<Vec<Query<'_>>, ParseError> {
         q.case_sensitive = case_sensitive;
     }
 
+    // Kinds filter the symbols matched by a symbol target, and nothing else.
+    let has_kind = |q: &Query<'_>| q.kind.is_some() || q.excluded.iter().any(|e| e.kind.is_some());
+    if qs
+        .iter()
+        .any(|q| has_kind(q) && !matches!(q.target, Some(Target::Symbol(..))))
+    {
+        return Err(ParseError::KindWithoutSymbol);
+    }
+
     Ok(qs.into_vec())
 }
 
@@ -663,6 +878,76 @@ pub fn parse_nl(query: &str) -> Result<SemanticQuery<'_>, ParseError> {
     })
 }
 
+/// Parse a `path:` value, reading unquoted paths as globs if they look like one.
+///
+/// Globs are translated to regexes, and a leading `!` negates them, as in `path:!**/vendor/**`.
+fn parse_path(pair: Pair<'_, Rule>) -> Expr<'_> {
+    let start = pair.as_span().start();
+    let end = pair.as_span().end();
+    let literal = Literal::from(pair.clone());
+
+    if pair.as_rule() != Rule::unquoted_literal {
+        return Expr::Path(literal);
+    }
+
+    let (negated, glob) = match literal.strip_prefix('!') {
+        Some(glob) => (true, glob),
+        None => (false, &*literal),
+    };
+
+    if !super::glob::is_glob(glob) {
+        return Expr::Path(literal);
+    }
+
+    let path = Expr::Path(Literal::Regex(LiteralInner::new(
+        start,
+        end,
+        super::glob::to_regex(glob),
+    )));
+
+    if negated {
+        Expr::Not(Box::new(path))
+    } else {
+        path
+    }
+}
+
+/// Parse a date of the form `YYYY-MM-DD`, or an RFC 3339 timestamp, into seconds since the unix
+/// epoch. Dates without a time are taken to be midnight UTC.
+fn parse_date(date: &str) -> Option<u64> {
+    let seconds = match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        Ok(date) => date.and_hms_opt(0, 0, 0)?.timestamp(),
+        Err(_) => chrono::DateTime::parse_from_rfc3339(date).ok()?.timestamp(),
+    };
+
+    u64::try_from(seconds).ok()
+}
+
+/// Parse an age like `12h`, `30d`, `2w` or `1y` into seconds.
+fn parse_age(age: &str) -> Option<u64> {
+    let unit = match age.chars().last()? {
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        'y' => 365 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    age[..age.len() - 1].parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn now() -> u64 {
+    u64::try_from(chrono::Utc::now().timestamp()).unwrap_or_default()
+}
+
+/// Whether a piece of text is made only of identifiers, separated by whitespace.
+fn is_identifiers(text: &str) -> bool {
+    !text.trim().is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c.is_whitespace())
+}
+
 fn flatten(root: Expr<'_>) -> SmallVec<[Query<'_>; 1]> {
     match root {
         Expr::Repo(repo) => smallvec![Query {
@@ -686,6 +971,10 @@ fn flatten(root: Expr<'_>) -> SmallVec<[Query<'_>; 1]> {
             target: Some(Target::Symbol(sym)),
             ..Default::default()
         }],
+        Expr::Ast(pattern) => smallvec![Query {
+            target: Some(Target::Ast(pattern)),
+            ..Default::default()
+        }],
         Expr::Lang(lang) => smallvec![Query {
             lang: Some(super::languages::parse_alias(&lang).into()),
             ..Default::default()
@@ -694,6 +983,41 @@ fn flatten(root: Expr<'_>) -> SmallVec<[Query<'_>; 1]> {
             target: Some(Target::Content(lit)),
             ..Default::default()
         }],
+        Expr::Kind(kind) => smallvec![Query {
+            kind: Some(Literal::from(&kind.to_ascii_lowercase())),
+            ..Default::default()
+        }],
+
+        Expr::ModifiedBefore(time) => smallvec![Query {
+            modified_before: Some(time),
+            ..Default::default()
+        }],
+        Expr::ModifiedAfter(time) => smallvec![Query {
+            modified_after: Some(time),
+            ..Default::default()
+        }],
+
+        Expr::Commit(commit) => smallvec![Query {
+            commit: Some(commit),
+            ..Default::default()
+        }],
+        Expr::Author(author) => smallvec![Query {
+            author: Some(author),
+            ..Default::default()
+        }],
+        Expr::Before(before) => smallvec![Query {
+            before: Some(before),
+            ..Default::default()
+        }],
+        Expr::After(after) => smallvec![Query {
+            after: Some(after),
+            ..Default::default()
+        }],
+
+        Expr::Not(expr) => smallvec![Query {
+            excluded: flatten(*expr).into_vec(),
+            ..Default::default()
+        }],
 
         Expr::CaseSensitive(case_sensitive) => smallvec![Query {
             case_sensitive: Some(case_sensitive),
@@ -1117,6 +1441,54 @@ mod tests {
         assert_eq!(out, Literal::Regex("f\\(oo\\)\\s+\\(bar\\|quux\\)".into()));
     }
 
+    #[test]
+    fn identifiers() {
+        let q = parse("retry policy").unwrap();
+        let content = q[0].target.as_ref().unwrap().content().unwrap();
+        assert_eq!(
+            content,
+            &Literal::Plain(LiteralInner::new(0, 12, "retry policy"))
+        );
+        assert_eq!(content.identifier_words(), Some(vec!["retry", "policy"]));
+        assert_eq!(
+            content.identifier_regex_str(),
+            r"retry(?:[\s_-]+policy|(?-i:P)olicy)"
+        );
+
+        let lit = Literal::from("HTTPServer");
+        assert_eq!(
+            lit.identifier_regex_str(),
+            r"HTTP(?:[\s_-]+Server|(?-i:S)erver)"
+        );
+
+        // Words are matched however they're cased and joined, as long as they're still apart.
+        let regex = regex::RegexBuilder::new(&Literal::from("foo-bar").identifier_regex_str())
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        for text in [
+            "foo-bar", "foo_bar", "FOO_BAR", "foo bar", "fooBar", "Foo Bar",
+        ] {
+            assert!(regex.is_match(text), "{text}");
+        }
+        assert!(!regex.is_match("foobar"));
+
+        // Words starting with a digit have no capital, and must be separated.
+        assert_eq!(
+            Literal::from("retry 2fa").identifier_regex_str(),
+            r"retry(?:[\s_-]+2fa)"
+        );
+
+        // Single words, other text and regexes are matched as they are.
+        assert_eq!(Literal::from("retry").identifier_words(), None);
+        assert_eq!(Literal::from("retry_").identifier_regex_str(), "retry_");
+        assert_eq!(Literal::from("a.b(c)").identifier_regex_str(), r"a\.b\(c\)");
+
+        let q = parse("retry /pol+icy/").unwrap();
+        let content = q[0].target.as_ref().unwrap().content().unwrap();
+        assert_eq!(content, &Literal::Regex(r"retry\s+pol+icy".into()));
+    }
+
     #[test]
     fn lang_path_filter() {
         assert_eq!(
@@ -1614,4 +1986,251 @@ mod tests {
             }],
         );
     }
+
+    #[test]
+    fn symbol_kind() {
+        assert_eq!(
+            parse("symbol:parse kind:Function -kind:method").unwrap(),
+            vec![Query {
+                target: Some(Target::Symbol(Literal::Plain(LiteralInner {
+                    start: 7,
+                    end: 12,
+                    content: "parse".into()
+                }))),
+                kind: Some(Literal::Plain("function".into())),
+                excluded: vec![Query {
+                    kind: Some(Literal::Plain("method".into())),
+                    ..Query::default()
+                }],
+                ..Query::default()
+            }],
+        );
+
+        assert!(matches!(
+            parse("kind:function lang:rust"),
+            Err(ParseError::KindWithoutSymbol)
+        ));
+        assert!(matches!(
+            parse("parse -kind:method"),
+            Err(ParseError::KindWithoutSymbol)
+        ));
+        assert!(matches!(
+            parse("symbol:parse or kind:function"),
+            Err(ParseError::KindWithoutSymbol)
+        ));
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(
+            parse("content:foo -content:bar NOT lang:markdown -path:test").unwrap(),
+            vec![Query {
+                target: Some(Target::Content(Literal::Plain(LiteralInner {
+                    start: 8,
+                    end: 11,
+                    content: "foo".into()
+                }))),
+                excluded: vec![
+                    Query {
+                        target: Some(Target::Content(Literal::Plain(LiteralInner {
+                            start: 21,
+                            end: 24,
+                            content: "bar".into()
+                        }))),
+                        ..Query::default()
+                    },
+                    Query {
+                        lang: Some(Literal::Plain("markdown".into())),
+                        ..Query::default()
+                    },
+                    Query {
+                        path: Some(Literal::Plain(LiteralInner {
+                            start: 49,
+                            end: 53,
+                            content: "test".into()
+                        })),
+                        ..Query::default()
+                    },
+                ],
+                ..Query::default()
+            }],
+        );
+
+        assert_eq!(
+            parse("-foo").unwrap(),
+            vec![Query {
+                excluded: vec![Query {
+                    target: Some(Target::Content(Literal::Plain(LiteralInner {
+                        start: 1,
+                        end: 4,
+                        content: "foo".into()
+                    }))),
+                    ..Query::default()
+                }],
+                ..Query::default()
+            }],
+        );
+
+        // Negations bind tighter than `or`, so they only apply to their side.
+        assert_eq!(
+            parse("foo or bar -path:test")
+                .unwrap()
+                .into_iter()
+                .map(|q| q.excluded.len())
+                .collect::<Vec<_>>(),
+            vec![0, 1],
+        );
+
+        // Double dashes are not negations.
+        assert_eq!(
+            parse("--release").unwrap(),
+            vec![Query {
+                target: Some(Target::Content(Literal::Plain(LiteralInner {
+                    start: 0,
+                    end: 9,
+                    content: "--release".into()
+                }))),
+                ..Query::default()
+            }],
+        );
+    }
+
+    #[test]
+    fn commit_history() {
+        assert_eq!(
+            parse("commit:RetryPolicy author:alice after:2023-01-01 before:2024-01-01T12:00:00Z")
+                .unwrap(),
+            vec![Query {
+                commit: Some(Literal::Plain(LiteralInner {
+                    start: 7,
+                    end: 18,
+                    content: "RetryPolicy".into()
+                })),
+                author: Some(Literal::Plain(LiteralInner {
+                    start: 26,
+                    end: 31,
+                    content: "alice".into()
+                })),
+                after: Some(1672531200),
+                before: Some(1704110400),
+                ..Query::default()
+            }],
+        );
+
+        assert!(parse("commit:foo").unwrap()[0].is_commit_search());
+        assert!(parse("foo -author:bot").unwrap()[0].is_commit_search());
+        assert!(!parse("foo -path:test").unwrap()[0].is_commit_search());
+
+        assert!(parse("before:yesterday").is_err());
+    }
+
+    #[test]
+    fn path_globs() {
+        assert_eq!(
+            parse("foo path:src/**/*.rs").unwrap()[0].path,
+            Some(Literal::Regex(LiteralInner {
+                start: 9,
+                end: 20,
+                content: r"^src/(?:.*/)?[^/]*\.rs$".into()
+            })),
+        );
+
+        let q = &parse("foo path:!**/vendor/**").unwrap()[0];
+        assert_eq!(q.path, None);
+        assert_eq!(
+            q.excluded[0].path.as_ref().map(|p| p.regex_str()),
+            Some(r"^(?:.*/)?vendor/.*$".into()),
+        );
+
+        // Quoted paths and paths without wildcards are kept as they are.
+        assert_eq!(
+            parse("path:\"a*b\"").unwrap()[0]
+                .path
+                .as_ref()
+                .unwrap()
+                .as_plain(),
+            Some("a*b".into()),
+        );
+        assert_eq!(
+            parse("path:!important").unwrap()[0]
+                .path
+                .as_ref()
+                .unwrap()
+                .as_plain(),
+            Some("!important".into()),
+        );
+    }
+
+    #[test]
+    fn modified() {
+        let q = &parse("modified:>2024-01-01 modified:<2024-06-01 foo").unwrap()[0];
+        assert_eq!(q.modified_after, Some(1704067200));
+        assert_eq!(q.modified_before, Some(1717200000));
+        assert_eq!(
+            q.modified_range(),
+            Some((Bound::Included(1704067200), Bound::Excluded(1717200000)))
+        );
+        assert!(!q.is_commit_search());
+
+        // Ages count back from now, so `<30d` is anything changed in the last 30 days.
+        let month = 30 * 24 * 60 * 60;
+        let q = &parse("modified:<30d").unwrap()[0];
+        assert!(q.modified_before.is_none());
+        assert!(q.modified_after.unwrap().abs_diff(now() - month) < 60);
+
+        let q = &parse("modified:>1y").unwrap()[0];
+        assert!(q.modified_after.is_none());
+        assert!(
+            q.modified_before
+                .unwrap()
+                .abs_diff(now() - 365 * 24 * 60 * 60)
+                < 60
+        );
+
+        let q = &parse("foo -modified:<2w").unwrap()[0];
+        assert!(q.modified_range().is_none());
+        assert!(q.excluded[0].modified_after.is_some());
+
+        assert!(parse("modified:<30m").is_err());
+        assert!(parse("modified:>soon").is_err());
+    }
+
+    #[test]
+    fn negation_global_regex() {
+        let q = parse("global_regex:true foo -path:test").unwrap();
+        assert_eq!(
+            q[0].excluded[0].path,
+            Some(Literal::Regex(LiteralInner {
+                start: 28,
+                end: 32,
+                content: "test".into()
+            }))
+        );
+    }
+
+    #[test]
+    fn ast_pattern() {
+        assert_eq!(
+            parse(r#"ast:"fn $NAME($$$)""#).unwrap(),
+            vec![Query {
+                target: Some(Target::Ast(Literal::Plain(LiteralInner {
+                    start: 5,
+                    end: 18,
+                    content: "fn $NAME($$$)".into()
+                }))),
+                ..Query::default()
+            }],
+        );
+
+        // Patterns are never turned into regexes.
+        let q = parse(r#"global_regex:true ast:'(function_item)'"#).unwrap();
+        assert_eq!(
+            q[0].target,
+            Some(Target::Ast(Literal::Plain(LiteralInner {
+                start: 23,
+                end: 38,
+                content: "(function_item)".into()
+            })))
+        );
+    }
 }
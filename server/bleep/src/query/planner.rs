@@ -16,12 +16,18 @@ pub enum Error {
 }
 
 pub fn plan(regex: &str) -> Result<Fragment, Error> {
-    let hir = regex_syntax::Parser::new().parse(regex).map_err(Box::new)?;
-
     // TODO: Optimizations are run twice in order to capture new available passes when possible.
     // Instead, the optimizer should be smart enough to re-run relevant passes recursively only
     // when applicable.
-    step(hir).map(optimize::run).map(optimize::run)
+    plan_unoptimized(regex)
+        .map(optimize::run)
+        .map(optimize::run)
+}
+
+/// Plan a regex without running any optimization passes, to help debugging the optimizer.
+pub fn plan_unoptimized(regex: &str) -> Result<Fragment, Error> {
+    let hir = regex_syntax::Parser::new().parse(regex).map_err(Box::new)?;
+    step(hir)
 }
 
 fn step(hir: Hir) -> Result<Fragment, Error> {
@@ -182,6 +188,18 @@ impl Fragment {
         }
     }
 
+    /// Whether the query compiled from this fragment may match any document.
+    ///
+    /// Such fragments cannot be used to exclude documents, as they would exclude everything.
+    pub fn is_unbounded(&self) -> bool {
+        match self {
+            Self::Break => true,
+            Self::Literal(s) => s.is_empty(),
+            Self::Dense(Op::Or, children) => children.iter().any(Self::is_unbounded),
+            Self::Dense(Op::And, children) => children.iter().all(Self::is_unbounded),
+        }
+    }
+
     fn as_literal(&self) -> Option<&String> {
         if let Self::Literal(s) = self {
             Some(s)
@@ -310,6 +328,27 @@ mod tests {
         assert_eq!(fragment, expected);
     }
 
+    #[test]
+    fn unoptimized_inline() {
+        // ab(de|fg) is left as is, without inlining
+        let fragment = plan_unoptimized("ab(de|fg)").unwrap();
+        let expected = Fragment::Dense(
+            Op::And,
+            vec![
+                Fragment::Literal("ab".into()),
+                Fragment::Dense(
+                    Op::Or,
+                    vec![
+                        Fragment::Literal("de".into()),
+                        Fragment::Literal("fg".into()),
+                    ],
+                ),
+            ],
+        );
+
+        assert_eq!(fragment, expected);
+    }
+
     #[test]
     fn small_literal_alt() {
         let frag = plan("ab|cd").unwrap();
@@ -399,6 +438,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn unbounded() {
+        assert!(plan(".*").unwrap().is_unbounded());
+        assert!(plan("a|.+").unwrap().is_unbounded());
+        assert!(!plan("abc.*def").unwrap().is_unbounded());
+        assert!(!plan("(abc|def)").unwrap().is_unbounded());
+    }
+
     #[test]
     fn simple_range() {
         let frag = plan("abc[d-f]g").unwrap();
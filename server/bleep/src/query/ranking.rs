@@ -1,5 +1,6 @@
-use std::time::SystemTime;
+use std::{collections::HashMap, time::SystemTime};
 
+use serde::{Deserialize, Serialize};
 use tantivy::{
     collector::{ScoreSegmentTweaker, ScoreTweaker},
     fastfield::Column,
@@ -7,32 +8,100 @@ use tantivy::{
 };
 use tantivy_columnar::{column_values::ColumnValues, BytesColumn};
 
-use crate::indexes::file::File;
+use crate::indexes::{commit::Commit, file::File};
 
-pub struct DocumentTweaker(pub File);
+/// Weights used to rank content search results.
+///
+/// Profiles are named in the configuration's `ranking_profiles`, and selected per query. The
+/// defaults reproduce the built-in ranking.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RankingProfile {
+    /// Multiplier for files in a language we understand
+    pub language: f32,
+
+    /// Exponent of the penalty for long lines, by the average line length of a file
+    pub line_length: f32,
+
+    /// Exponent of the penalty for files that have not been changed in a while
+    pub recency: f32,
+
+    /// Exponent of the penalty for deeply nested files, by the number of directories in their path
+    pub path_depth: f32,
+
+    /// Multiplier for test files, e.g. `tests/api.rs` or `api.test.ts`
+    pub test_file: f32,
+
+    /// Multipliers for files in specific repos, keyed by repo name
+    pub repos: HashMap<String, f32>,
+}
+
+impl Default for RankingProfile {
+    fn default() -> Self {
+        Self {
+            language: 1000.0,
+            line_length: 1.0,
+            recency: 1.0,
+            path_depth: 0.0,
+            test_file: 1.0,
+            repos: HashMap::new(),
+        }
+    }
+}
+
+impl RankingProfile {
+    fn uses_path(&self) -> bool {
+        self.path_depth != 0.0 || self.test_file != 1.0
+    }
+}
+
+pub struct DocumentTweaker(pub File, pub RankingProfile);
 pub struct SegmentScorer {
     line_length: Column<f64>,
     lang: BytesColumn,
     last_commit: Column<u64>,
+    relative_path: BytesColumn,
+    repo_name: BytesColumn,
+    profile: RankingProfile,
+    now: u64,
 }
 
 impl ScoreSegmentTweaker<Score> for SegmentScorer {
     fn score(&mut self, doc: DocId, mut score: Score) -> Score {
-        // * 1000 if it's a language we understand
-        let mut bytes = Vec::new();
-        self.lang.ords().values_for_doc(doc).for_each(|ord| {
-            self.lang.ord_to_bytes(ord, &mut bytes).unwrap();
-        });
-        score *= 1.0 + bytes.len().min(1) as f32 * 999.0;
+        let profile = &self.profile;
+
+        // Boost languages we understand
+        if !read_bytes(&self.lang, doc).is_empty() {
+            score *= profile.language;
+        }
 
         // Penalty for lines that are too long
-        score /= self.line_length.values.get_val(doc).clamp(20.0, 1000.0) as f32;
-        score /= SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
+        let line_length = self.line_length.values.get_val(doc).clamp(20.0, 1000.0) as f32;
+        score /= line_length.powf(profile.line_length);
+
+        // Penalty for stale files
+        let age = self
+            .now
             .saturating_sub(self.last_commit.values.get_val(doc))
             .min(5_000_000) as f32;
+        score /= age.powf(profile.recency);
+
+        if profile.uses_path() {
+            let path = read_bytes(&self.relative_path, doc);
+            let depth = path.iter().filter(|&&b| b == b'/').count() as f32;
+            score /= (1.0 + depth).powf(profile.path_depth);
+
+            if is_test_path(&String::from_utf8_lossy(&path)) {
+                score *= profile.test_file;
+            }
+        }
+
+        if !profile.repos.is_empty() {
+            let repo_name = read_bytes(&self.repo_name, doc);
+            if let Some(weight) = profile.repos.get(&*String::from_utf8_lossy(&repo_name)) {
+                score *= weight;
+            }
+        }
 
         score
     }
@@ -45,17 +114,124 @@ impl ScoreTweaker<Score> for DocumentTweaker {
         &self,
         segment_reader: &tantivy::SegmentReader,
     ) -> tantivy::Result<Self::Child> {
-        let Self(file) = self;
+        let Self(file, profile) = self;
         let schema = file.schema();
         let avg_line_length_field = schema.get_field_name(file.avg_line_length);
         let lang_field = schema.get_field_name(file.lang);
         let last_commit_unix_seconds_field = schema.get_field_name(file.last_commit_unix_seconds);
+        let relative_path_field = schema.get_field_name(file.raw_relative_path);
+        let repo_name_field = schema.get_field_name(file.raw_repo_name);
         Ok(SegmentScorer {
             line_length: segment_reader.fast_fields().f64(avg_line_length_field)?,
             lang: segment_reader.fast_fields().bytes(lang_field)?.unwrap(),
             last_commit: segment_reader
                 .fast_fields()
                 .u64(last_commit_unix_seconds_field)?,
+            relative_path: segment_reader
+                .fast_fields()
+                .bytes(relative_path_field)?
+                .unwrap(),
+            repo_name: segment_reader
+                .fast_fields()
+                .bytes(repo_name_field)?
+                .unwrap(),
+            profile: profile.clone(),
+            now: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
         })
     }
 }
+
+fn read_bytes(column: &BytesColumn, doc: DocId) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    column.ords().values_for_doc(doc).for_each(|ord| {
+        column.ord_to_bytes(ord, &mut bytes).unwrap();
+    });
+    bytes
+}
+
+/// Whether a path looks like a test, either by its directory or by its file name.
+pub fn is_test_path(path: &str) -> bool {
+    let mut components = path.split('/').rev();
+    let file_name = components.next().unwrap_or_default();
+
+    let in_test_dir = components.any(|dir| {
+        matches!(
+            dir,
+            "test" | "tests" | "__tests__" | "spec" | "specs" | "testdata" | "test_data"
+        )
+    });
+
+    let stem = file_name.split('.').next().unwrap_or_default();
+    in_test_dir
+        || stem.starts_with("test_")
+        || stem.ends_with("_test")
+        || stem.ends_with("_spec")
+        || stem.ends_with("Test")
+        || stem.ends_with("Tests")
+        || file_name.contains(".test.")
+        || file_name.contains(".spec.")
+}
+
+/// Ranks commits by their author date, so that the newest commits come first.
+pub struct CommitTweaker(pub Commit);
+pub struct CommitScorer {
+    date: Column<u64>,
+}
+
+impl ScoreSegmentTweaker<Score> for CommitScorer {
+    fn score(&mut self, doc: DocId, _score: Score) -> Score {
+        self.date.values.get_val(doc) as Score
+    }
+}
+
+impl ScoreTweaker<Score> for CommitTweaker {
+    type Child = CommitScorer;
+
+    fn segment_tweaker(
+        &self,
+        segment_reader: &tantivy::SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let Self(commit) = self;
+        let schema = commit.schema();
+        let date_field = schema.get_field_name(commit.date);
+        Ok(CommitScorer {
+            date: segment_reader.fast_fields().u64(date_field)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        assert!(is_test_path("server/bleep/tests/desktop.rs"));
+        assert!(is_test_path("src/__tests__/App.tsx"));
+        assert!(is_test_path("src/query/parser_test.go"));
+        assert!(is_test_path("test_parser.py"));
+        assert!(is_test_path("client/src/api.test.ts"));
+        assert!(is_test_path("src/main/java/ParserTest.java"));
+
+        assert!(!is_test_path("src/query/parser.rs"));
+        assert!(!is_test_path("src/testing.rs"));
+        assert!(!is_test_path("src/attestation/mod.rs"));
+    }
+
+    #[test]
+    fn default_profile() {
+        let profile = serde_json::from_str::<RankingProfile>(r#"{"test_file": 0.1}"#).unwrap();
+        assert_eq!(
+            profile,
+            RankingProfile {
+                test_file: 0.1,
+                ..Default::default()
+            }
+        );
+        assert!(profile.uses_path());
+        assert!(!RankingProfile::default().uses_path());
+    }
+}
@@ -0,0 +1,337 @@
+//! Structural search over tree-sitter syntax trees.
+//!
+//! An `ast:` target holds either a raw tree-sitter S-expression, such as
+//! `(function_item name: (identifier) @name)`, or a code template with metavariables, such as
+//! `fn $NAME($$$) -> Result<$T>`:
+//!
+//! - `$NAME` matches any non-empty piece of code. When a metavariable is used more than once,
+//!   all its occurrences must match the same text, so `$A == $A` finds self-comparisons.
+//! - `$$$` matches any sequence of code, including none at all.
+//!
+//! Templates are matched textually against the innermost syntax nodes that start with the
+//! template, so a function signature template matches the whole function, body included.
+
+use std::{collections::HashMap, ops::Range};
+
+use regex::bytes::{Regex, RegexBuilder};
+use tree_sitter::{Node, QueryCursor, QueryError};
+
+use super::parser::Literal;
+
+/// Fragments shorter than this don't produce any useful trigrams for the prefilter.
+const MIN_FRAGMENT_LEN: usize = 3;
+
+#[derive(Debug)]
+pub enum Pattern {
+    /// A tree-sitter query, compiled separately for the grammar of each searched file.
+    Query(String),
+    /// A code template with metavariables.
+    Template(Template),
+}
+
+#[derive(Debug)]
+pub struct Template {
+    /// Anchored regex matching the start of a node's text.
+    regex: Regex,
+    /// Capture group names that must match the same text, one entry per repeated metavariable.
+    repeated: Vec<Vec<String>>,
+    /// Identifiers and keywords that appear literally in the template.
+    fragments: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Punct(char),
+    Var(&'a str),
+    Ellipsis,
+}
+
+impl Pattern {
+    /// Parse a pattern, treating anything that starts with a parenthesis as a tree-sitter query.
+    pub fn parse(pattern: &str) -> Result<Self, regex::Error> {
+        let pattern = pattern.trim();
+
+        if pattern.starts_with('(') {
+            Ok(Self::Query(pattern.to_owned()))
+        } else {
+            Template::new(pattern).map(Self::Template)
+        }
+    }
+
+    /// A content literal that every file matching this pattern must contain.
+    ///
+    /// Tree-sitter queries can make any string optional or negated, so they don't narrow down
+    /// the candidate files at all; combine them with `lang:` or `path:` to keep searches fast.
+    pub fn prefilter(&self) -> Literal<'static> {
+        let fragments = match self {
+            Self::Query(_) => &[][..],
+            Self::Template(template) => &template.fragments,
+        };
+
+        if fragments.is_empty() {
+            return Literal::Regex(".*".into());
+        }
+
+        let regex = fragments
+            .iter()
+            .map(|f| regex::escape(f))
+            .collect::<Vec<_>>()
+            .join("(?s:.*)");
+
+        Literal::Regex(regex.into())
+    }
+
+    /// Find the byte ranges of all nodes under `root` that match this pattern.
+    pub fn find(
+        &self,
+        root: Node<'_>,
+        src: &[u8],
+        grammar: tree_sitter::Language,
+    ) -> Result<Vec<Range<usize>>, QueryError> {
+        let mut ranges = match self {
+            Self::Query(query) => find_query(query, root, src, grammar)?,
+            Self::Template(template) => {
+                let mut ranges = Vec::new();
+                template.find(root, src, &mut ranges);
+                ranges
+            }
+        };
+
+        ranges.sort_by_key(|r| (r.start, r.end));
+        ranges.dedup();
+        Ok(ranges)
+    }
+}
+
+impl Template {
+    fn new(pattern: &str) -> Result<Self, regex::Error> {
+        let tokens = tokenize(pattern);
+
+        let mut regex = String::from("^");
+        let mut groups = HashMap::<&str, Vec<String>>::new();
+        let mut fragments = Vec::new();
+
+        for (i, token) in tokens.iter().enumerate() {
+            if i > 0 {
+                // Whitespace is only required between two words, elsewhere it is optional.
+                let sep = match (&tokens[i - 1], token) {
+                    (Token::Word(_), Token::Word(_)) => r"\s+",
+                    _ => r"\s*",
+                };
+                regex.push_str(sep);
+            }
+
+            match token {
+                Token::Word(word) => {
+                    regex.push_str(&regex::escape(word));
+                    if word.len() >= MIN_FRAGMENT_LEN {
+                        fragments.push(word.to_string());
+                    }
+                }
+                Token::Punct(c) => regex.push_str(&regex::escape(&c.to_string())),
+                Token::Ellipsis => regex.push_str("(?s:.*?)"),
+                Token::Var(name) => {
+                    let names = groups.entry(name).or_default();
+                    let group = format!("{name}_{}", names.len());
+                    regex.push_str(&format!("(?P<{group}>(?s:.+?))"));
+                    names.push(group);
+                }
+            }
+        }
+
+        // Don't let a trailing word match a prefix of a longer identifier.
+        if let Some(Token::Word(_)) = tokens.last() {
+            regex.push_str(r"\b");
+        }
+
+        Ok(Self {
+            regex: RegexBuilder::new(&regex).build()?,
+            repeated: groups.into_values().filter(|g| g.len() > 1).collect(),
+            fragments,
+        })
+    }
+
+    fn is_match(&self, text: &[u8]) -> bool {
+        let Some(captures) = self.regex.captures(text) else {
+            return false;
+        };
+
+        self.repeated.iter().all(|group| {
+            let mut values = group
+                .iter()
+                .map(|name| captures.name(name).map(|m| m.as_bytes()));
+            let first = values.next().flatten();
+            values.all(|v| v == first)
+        })
+    }
+
+    /// Walk the tree in post-order, collecting the innermost matching nodes.
+    ///
+    /// Ancestors of a match are skipped, as their text could otherwise match by stretching a
+    /// metavariable over several sibling nodes.
+    fn find(&self, node: Node<'_>, src: &[u8], ranges: &mut Vec<Range<usize>>) {
+        let first = ranges.len();
+
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            self.find(child, src, ranges);
+        }
+
+        if ranges.len() == first && self.is_match(&src[node.byte_range()]) {
+            ranges.push(node.byte_range());
+        }
+    }
+}
+
+fn find_query(
+    query: &str,
+    root: Node<'_>,
+    src: &[u8],
+    grammar: tree_sitter::Language,
+) -> Result<Vec<Range<usize>>, QueryError> {
+    let mut compiled = tree_sitter::Query::new(grammar, query)?;
+
+    // Without any captures there is no node to report, so capture the whole pattern.
+    if compiled.capture_names().is_empty() {
+        compiled = tree_sitter::Query::new(grammar, &format!("{query} @match"))?;
+    }
+
+    let mut cursor = QueryCursor::new();
+    Ok(cursor
+        .matches(&compiled, root, src)
+        .filter_map(|m| {
+            m.captures
+                .iter()
+                .map(|c| c.node.byte_range())
+                .max_by_key(|r| r.len())
+        })
+        .collect())
+}
+
+fn tokenize(pattern: &str) -> Vec<Token<'_>> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let is_var = |c: char| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_';
+
+    let mut tokens = Vec::new();
+    let mut rest = pattern;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if let Some(after) = rest.strip_prefix("$$$") {
+            // Names on ellipses, as in `$$$ARGS`, are accepted but not checked.
+            rest = after.trim_start_matches(is_var);
+            tokens.push(Token::Ellipsis);
+        } else if c == '$' && rest[1..].starts_with(|c: char| c.is_ascii_uppercase() || c == '_') {
+            let end = rest[1..].find(|c| !is_var(c)).map_or(rest.len(), |i| i + 1);
+            tokens.push(Token::Var(&rest[1..end]));
+            rest = &rest[end..];
+        } else if is_word(c) {
+            let end = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+            tokens.push(Token::Word(&rest[..end]));
+            rest = &rest[end..];
+        } else {
+            tokens.push(Token::Punct(c));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, src: &str) -> Vec<String> {
+        let grammar = tree_sitter_rust::language();
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(grammar).unwrap();
+        let tree = parser.parse(src, None).unwrap();
+
+        Pattern::parse(pattern)
+            .unwrap()
+            .find(tree.root_node(), src.as_bytes(), grammar)
+            .unwrap()
+            .into_iter()
+            .map(|r| src[r].to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_template() {
+        assert_eq!(
+            tokenize("fn $NAME($$$ARGS) -> Result<$T>"),
+            vec![
+                Token::Word("fn"),
+                Token::Var("NAME"),
+                Token::Punct('('),
+                Token::Ellipsis,
+                Token::Punct(')'),
+                Token::Punct('-'),
+                Token::Punct('>'),
+                Token::Word("Result"),
+                Token::Punct('<'),
+                Token::Var("T"),
+                Token::Punct('>'),
+            ]
+        );
+
+        // Lowercase names and lone dollars are kept as code.
+        assert_eq!(
+            tokenize("$x $"),
+            vec![Token::Punct('$'), Token::Word("x"), Token::Punct('$')]
+        );
+    }
+
+    #[test]
+    fn template_matches() {
+        let src = r#"
+fn plain(a: u32) -> u32 {
+    a
+}
+
+fn fallible(path: &str, n: usize) -> Result<String> {
+    let same = n == n;
+    let other = n == path.len();
+    Ok(path.to_owned())
+}
+"#;
+
+        let found = find("fn $NAME($$$) -> Result<$T>", src);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].starts_with("fn fallible(path: &str, n: usize)"));
+        assert!(found[0].ends_with("Ok(path.to_owned())\n}"));
+
+        assert_eq!(find("$A == $A", src), vec!["n == n"]);
+        assert_eq!(find("fn $NAME() -> Result<$T>", src), Vec::<String>::new());
+        assert_eq!(find("path.to_owned()", src), vec!["path.to_owned()"]);
+    }
+
+    #[test]
+    fn query_matches() {
+        let src = "fn one() {}\nstruct Two;\nfn three() {}\n";
+
+        assert_eq!(
+            find("(function_item name: (identifier) @name)", src),
+            vec!["one", "three"]
+        );
+        assert_eq!(find("(struct_item)", src), vec!["struct Two;"]);
+    }
+
+    #[test]
+    fn prefilter() {
+        let pattern = Pattern::parse("fn $NAME($$$) -> Result<$T>").unwrap();
+        assert_eq!(pattern.prefilter(), Literal::Regex("Result".into()));
+
+        let pattern = Pattern::parse("impl $T for Vec<$T>").unwrap();
+        assert_eq!(
+            pattern.prefilter(),
+            Literal::Regex("impl(?s:.*)for(?s:.*)Vec".into())
+        );
+
+        let pattern = Pattern::parse("(function_item)").unwrap();
+        assert_eq!(pattern.prefilter(), Literal::Regex(".*".into()));
+    }
+}
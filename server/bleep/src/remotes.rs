@@ -12,10 +12,13 @@ use tracing::error;
 
 use crate::{
     background::{SyncHandle, SyncPipes},
-    repo::{Backend, RepoError, RepoRef, Repository, SyncStatus},
+    repo::{Backend, GitRemote, RepoError, RepoRef, RepoRemote, Repository, SyncStatus},
 };
 
+mod forge;
+pub mod gitea;
 pub mod github;
+pub mod gitlab;
 
 type GitCreds = Account;
 
@@ -40,6 +43,9 @@ pub(crate) enum RemoteError {
     #[error("github access error: {0}")]
     GitHub(#[from] octocrab::Error),
 
+    #[error("forge api error: {0}")]
+    Http(#[from] reqwest::Error),
+
     #[error("anyhow: {0:?}")]
     Anyhow(#[from] anyhow::Error),
 
@@ -106,6 +112,27 @@ macro_rules! creds_callback(($auth:ident) => {{
     }
 }});
 
+fn plain_creds(repo: &Repository) -> Option<GitCreds> {
+    use secrecy::ExposeSecret;
+
+    let RepoRemote::Git(GitRemote {
+        credentials: Some(ref creds),
+        ..
+    }) = repo.remote
+    else {
+        return None;
+    };
+
+    Some(GitCreds {
+        username: creds.username.clone(),
+        password: creds
+            .password
+            .as_ref()
+            .map(|p| p.expose_secret().to_owned())
+            .unwrap_or_default(),
+    })
+}
+
 async fn git_clone(
     auth: &Option<GitCreds>,
     url: &str,
@@ -292,14 +319,38 @@ impl From<HashMap<Backend, BackendCredential>> for Backends {
 
 impl Backends {
     pub(crate) fn for_repo(&self, repo: &RepoRef) -> Option<BackendCredential> {
+        if repo.backend() == Backend::Git {
+            return Some(BackendCredential::Git);
+        }
+
         self.backends.read(&repo.backend(), |_, v| v.inner.clone())
     }
 
     pub(crate) fn github(&self) -> Option<github::State> {
-        self.backends.read(&Backend::Github, |_, v| {
-            let BackendCredential::Github(ref github) = v.inner;
-            github.clone()
-        })
+        self.backends
+            .read(&Backend::Github, |_, v| match v.inner {
+                BackendCredential::Github(ref github) => Some(github.clone()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    pub(crate) fn gitlab(&self) -> Option<gitlab::State> {
+        self.backends
+            .read(&Backend::Gitlab, |_, v| match v.inner {
+                BackendCredential::Gitlab(ref gitlab) => Some(gitlab.clone()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    pub(crate) fn gitea(&self) -> Option<gitea::State> {
+        self.backends
+            .read(&Backend::Gitea, |_, v| match v.inner {
+                BackendCredential::Gitea(ref gitea) => Some(gitea.clone()),
+                _ => None,
+            })
+            .flatten()
     }
 
     pub(crate) fn set_github(&self, gh: impl Into<github::State>) {
@@ -311,11 +362,54 @@ impl Backends {
             })
             .or_insert_with(|| BackendCredential::Github(gh).into());
     }
+
+    pub(crate) fn set_gitlab(&self, gl: impl Into<gitlab::State>) {
+        let gl = gl.into();
+        if let Some(host) = gl.auth.host() {
+            crate::repo::register_forge_host(&host, Backend::Gitlab);
+        }
+
+        self.backends
+            .entry(Backend::Gitlab)
+            .and_modify(|existing| {
+                existing.inner = BackendCredential::Gitlab(gl.clone());
+            })
+            .or_insert_with(|| BackendCredential::Gitlab(gl).into());
+    }
+
+    pub(crate) fn set_gitea(&self, gt: impl Into<gitea::State>) {
+        let gt = gt.into();
+        if let Some(host) = gt.auth.host() {
+            crate::repo::register_forge_host(&host, Backend::Gitea);
+        }
+
+        self.backends
+            .entry(Backend::Gitea)
+            .and_modify(|existing| {
+                existing.inner = BackendCredential::Gitea(gt.clone());
+            })
+            .or_insert_with(|| BackendCredential::Gitea(gt).into());
+    }
+
+    /// Make the hosts of stored GitLab & Gitea credentials known to `RepoRef` parsing.
+    pub(crate) fn register_forge_hosts(&self) {
+        if let Some(host) = self.gitlab().and_then(|gl| gl.auth.host()) {
+            crate::repo::register_forge_host(&host, Backend::Gitlab);
+        }
+
+        if let Some(host) = self.gitea().and_then(|gt| gt.auth.host()) {
+            crate::repo::register_forge_host(&host, Backend::Gitea);
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) enum BackendCredential {
     Github(github::State),
+    Gitlab(gitlab::State),
+    Gitea(gitea::State),
+    /// Plain git remotes carry their own credentials in [`GitRemote`].
+    Git,
 }
 
 impl BackendCredential {
@@ -326,14 +420,19 @@ impl BackendCredential {
         repo: Repository,
     ) -> Result<SyncStatus> {
         use BackendCredential::*;
-        let Github(gh) = self;
 
-        let creds = gh.auth.creds(&repo).await?;
+        let (creds, url) = match self {
+            Github(gh) => (gh.auth.creds(&repo).await?, repo.remote.to_string()),
+            Gitlab(gl) => (gl.auth.creds(&repo), gl.auth.clone_url(&repo)),
+            Gitea(gt) => (gt.auth.creds(&repo), gt.auth.clone_url(&repo)),
+            Git => (plain_creds(&repo), repo.remote.to_string()),
+        };
+
         let clone = || async {
             handle.set_status(|_| SyncStatus::Syncing);
             git_clone(
                 &creds,
-                &repo.remote.to_string(),
+                &url,
                 &repo.disk_path,
                 &handle.pipes,
                 handle.shallow_config.clone(),
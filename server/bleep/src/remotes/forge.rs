@@ -0,0 +1,200 @@
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
+
+use crate::repo::{GitRemote, RepoRemote, Repository};
+
+use super::*;
+
+/// Upper bound on the number of pages we fetch from a paginated list endpoint.
+const MAX_PAGES: usize = 100;
+
+/// A self-hosted (or SaaS) GitLab or Gitea instance, and the token we use to talk to it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Instance {
+    /// Base URL of the instance, e.g. `https://gitlab.example.com`
+    url: String,
+    #[serde(serialize_with = "crate::config::serialize_secret_str")]
+    token: SecretString,
+}
+
+impl Instance {
+    pub(crate) fn new(url: &str, token: SecretString) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            token,
+        }
+    }
+
+    pub(crate) fn token(&self) -> &str {
+        self.token.expose_secret()
+    }
+
+    /// The host part of a `RepoRef` pointing to this instance, including a non-default port.
+    pub(crate) fn host(&self) -> Option<String> {
+        let url = url::Url::parse(&self.url).ok()?;
+        let host = url.host_str()?;
+
+        Some(match url.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_owned(),
+        })
+    }
+
+    /// Whether the repository is hosted on this instance.
+    ///
+    /// We never hand out the token to other hosts.
+    pub(crate) fn hosts(&self, repo: &Repository) -> bool {
+        match repo.remote {
+            RepoRemote::Git(GitRemote { ref host, .. }) => {
+                self.host().is_some_and(|h| h.eq_ignore_ascii_case(host))
+            }
+            RepoRemote::None => false,
+        }
+    }
+
+    /// The URL to clone the repository from.
+    ///
+    /// For repositories on this instance, this respects the scheme and path prefix of the
+    /// configured URL, so instances served over plain HTTP or from a sub-path work, too.
+    pub(crate) fn clone_url(&self, repo: &Repository) -> String {
+        match repo.remote {
+            RepoRemote::Git(GitRemote { ref address, .. }) if self.hosts(repo) => {
+                format!("{}/{address}.git", self.url)
+            }
+            _ => repo.remote.to_string(),
+        }
+    }
+
+    /// Collect all pages of a paginated JSON list endpoint, up to `MAX_PAGES`.
+    pub(crate) async fn list<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        authorization: &str,
+        params: &[(&str, &str)],
+        per_page: (&str, usize),
+    ) -> Result<Vec<T>> {
+        let client = reqwest::Client::new();
+        let url = format!("{}/{}", self.url, path.trim_start_matches('/'));
+
+        let mut results = vec![];
+        let mut previous = None;
+        for page in 1.. {
+            if page > MAX_PAGES {
+                warn!(%url, "stopped listing after {MAX_PAGES} pages");
+                break;
+            }
+
+            let items: Vec<serde_json::Value> = client
+                .get(&url)
+                .header(reqwest::header::AUTHORIZATION, authorization)
+                .query(params)
+                .query(&[("page", page), (per_page.0, per_page.1)])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            // Instances may cap the page size below what we ask for, so only an empty page
+            // reliably marks the end. Some ignore `page` instead, and repeat the first page.
+            if items.is_empty() || previous.as_ref() == Some(&items) {
+                break;
+            }
+
+            for item in &items {
+                results.push(T::deserialize(item).context("unexpected item in list response")?);
+            }
+
+            previous = Some(items);
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum::{extract::Query, http::HeaderMap, routing::get, Json, Router};
+
+    use super::*;
+
+    /// Serve `router` on a random local port, returning an instance pointing at it.
+    fn serve(router: Router) -> Instance {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+
+        Instance::new(
+            &format!("http://{addr}/"),
+            SecretString::new("secret".into()),
+        )
+    }
+
+    async fn list(instance: &Instance) -> Vec<u64> {
+        instance
+            .list(
+                "/api/projects",
+                "Bearer secret",
+                &[("archived", "false")],
+                ("per_page", 2),
+            )
+            .await
+            .unwrap()
+    }
+
+    fn page(params: &HashMap<String, String>) -> usize {
+        params["page"].parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn lists_until_an_empty_page() {
+        let instance = serve(Router::new().route(
+            "/api/projects",
+            get(
+                |headers: HeaderMap, Query(params): Query<HashMap<String, String>>| async move {
+                    assert_eq!(headers["authorization"], "Bearer secret");
+                    assert_eq!(params["archived"], "false");
+                    assert_eq!(params["per_page"], "2");
+
+                    // Pages are shorter than requested, like on a capped instance.
+                    Json(match page(&params) {
+                        1 => vec![1],
+                        2 => vec![2],
+                        _ => vec![],
+                    })
+                },
+            ),
+        ));
+
+        assert_eq!(list(&instance).await, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn stops_at_repeated_pages() {
+        let instance =
+            serve(Router::new().route("/api/projects", get(|| async { Json(vec![1, 2]) })));
+
+        assert_eq!(list(&instance).await, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn stops_after_max_pages() {
+        let instance = serve(Router::new().route(
+            "/api/projects",
+            get(|Query(params): Query<HashMap<String, String>>| async move {
+                Json(vec![page(&params)])
+            }),
+        ));
+
+        assert_eq!(list(&instance).await.len(), MAX_PAGES);
+    }
+}
@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::repo::Repository;
+
+use super::{forge::Instance, *};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct State {
+    pub auth: Auth,
+    #[serde(skip)]
+    pub repositories: Arc<Vec<Repo>>,
+}
+
+impl State {
+    pub(crate) fn with_auth(auth: Auth) -> Self {
+        Self {
+            auth,
+            repositories: Arc::default(),
+        }
+    }
+
+    /// Get a representative list of repositories currently accessible
+    pub async fn current_repo_list(&self) -> Result<Vec<Repo>> {
+        self.auth.list_repos().await
+    }
+
+    /// Create a new object with the updated repositories list
+    pub fn update_repositories(self, repos: Vec<Repo>) -> Self {
+        Self {
+            auth: self.auth,
+            repositories: repos.into(),
+        }
+    }
+}
+
+/// The fields of a Gitea repository we use for listing.
+///
+/// Forgejo serves the same API.
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct Repo {
+    pub full_name: String,
+    pub clone_url: String,
+    pub ssh_url: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Auth {
+    instance: Instance,
+}
+
+impl From<Auth> for State {
+    fn from(value: Auth) -> Self {
+        State::with_auth(value)
+    }
+}
+
+impl Auth {
+    pub(crate) fn new(url: &str, token: SecretString) -> Self {
+        Self {
+            instance: Instance::new(url, token),
+        }
+    }
+
+    pub(crate) fn host(&self) -> Option<String> {
+        self.instance.host()
+    }
+
+    pub(crate) fn clone_url(&self, repo: &Repository) -> String {
+        self.instance.clone_url(repo)
+    }
+
+    /// Return credentials for repositories on the configured instance.
+    ///
+    /// Gitea accepts an access token in place of the password, and doesn't check the
+    /// username in that case.
+    pub(crate) fn creds(&self, repo: &Repository) -> Option<GitCreds> {
+        self.instance.hosts(repo).then(|| GitCreds {
+            username: "oauth2".into(),
+            password: self.instance.token().into(),
+        })
+    }
+
+    async fn list_repos(&self) -> Result<Vec<Repo>> {
+        self.instance
+            .list(
+                "api/v1/user/repos",
+                &format!("token {}", self.instance.token()),
+                &[],
+                ("limit", 50),
+            )
+            .await
+    }
+}
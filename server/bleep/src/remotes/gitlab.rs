@@ -0,0 +1,95 @@
+use chrono::{DateTime, Utc};
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::repo::Repository;
+
+use super::{forge::Instance, *};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct State {
+    pub auth: Auth,
+    #[serde(skip)]
+    pub repositories: Arc<Vec<Project>>,
+}
+
+impl State {
+    pub(crate) fn with_auth(auth: Auth) -> Self {
+        Self {
+            auth,
+            repositories: Arc::default(),
+        }
+    }
+
+    /// Get a representative list of repositories currently accessible
+    pub async fn current_repo_list(&self) -> Result<Vec<Project>> {
+        self.auth.list_repos().await
+    }
+
+    /// Create a new object with the updated repositories list
+    pub fn update_repositories(self, repos: Vec<Project>) -> Self {
+        Self {
+            auth: self.auth,
+            repositories: repos.into(),
+        }
+    }
+}
+
+/// The fields of a GitLab project we use for listing.
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct Project {
+    pub path_with_namespace: String,
+    pub http_url_to_repo: String,
+    pub ssh_url_to_repo: String,
+    pub last_activity_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct Auth {
+    instance: Instance,
+}
+
+impl From<Auth> for State {
+    fn from(value: Auth) -> Self {
+        State::with_auth(value)
+    }
+}
+
+impl Auth {
+    pub(crate) fn new(url: &str, token: SecretString) -> Self {
+        Self {
+            instance: Instance::new(url, token),
+        }
+    }
+
+    pub(crate) fn host(&self) -> Option<String> {
+        self.instance.host()
+    }
+
+    pub(crate) fn clone_url(&self, repo: &Repository) -> String {
+        self.instance.clone_url(repo)
+    }
+
+    /// Return credentials for repositories on the configured instance.
+    ///
+    /// Unlike GitHub, there's no cheap way to tell whether a project is public, but sending
+    /// the token along for public projects is harmless.
+    pub(crate) fn creds(&self, repo: &Repository) -> Option<GitCreds> {
+        self.instance.hosts(repo).then(|| GitCreds {
+            username: "oauth2".into(),
+            password: self.instance.token().into(),
+        })
+    }
+
+    async fn list_repos(&self) -> Result<Vec<Project>> {
+        self.instance
+            .list(
+                "api/v4/projects",
+                &format!("Bearer {}", self.instance.token()),
+                &[("membership", "true"), ("archived", "false")],
+                ("per_page", 100),
+            )
+            .await
+    }
+}
@@ -5,14 +5,20 @@ use regex::Regex;
 use smallvec::SmallVec;
 use tracing::warn;
 
+mod config;
 mod filters;
 mod fs;
 mod git;
 pub(super) mod language;
 
+#[cfg(test)]
+pub(crate) mod test_utils;
+
+pub use config::{RepoConfig, CONFIG_FILE, IGNORE_FILE};
 pub use filters::*;
 pub use fs::FileWalker;
 pub use git::GitWalker;
+pub(crate) use git::branch_tips;
 
 use crate::background::SyncPipes;
 
@@ -103,7 +109,18 @@ pub struct RepoFile {
 
 impl RepoFile {
     pub fn should_index(&self) -> bool {
-        should_index_path(&self.path) && self.len < MAX_FILE_LEN
+        self.skip_reason().is_none()
+    }
+
+    /// Why the file isn't indexed, judging by its path and length.
+    pub fn skip_reason(&self) -> Option<SkipReason> {
+        if !should_index_path(&self.path) {
+            Some(SkipReason::Blacklisted)
+        } else if self.len >= MAX_FILE_LEN {
+            Some(SkipReason::TooLarge)
+        } else {
+            None
+        }
     }
 
     pub fn buffer(&self) -> std::io::Result<String> {
@@ -115,6 +132,30 @@ impl RepoFile {
     }
 }
 
+/// Why the contents of a file aren't indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The file isn't valid UTF-8
+    Binary,
+    /// The file has a blacklisted extension, or is in a vendored directory
+    Blacklisted,
+    /// The file is longer than `MAX_FILE_LEN`, or has more than `MAX_LINE_COUNT` lines
+    TooLarge,
+    /// The file is excluded by the repository's file filter
+    Excluded,
+}
+
+impl SkipReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Binary => "binary",
+            Self::Blacklisted => "blacklisted",
+            Self::TooLarge => "too_large",
+            Self::Excluded => "excluded",
+        }
+    }
+}
+
 #[derive(Hash, Eq, PartialEq)]
 pub enum FileType {
     File,
@@ -219,4 +260,24 @@ mod test {
             assert_eq!(should_index_path(&Path::new(path)), index);
         }
     }
+
+    #[test]
+    fn test_skip_reason() {
+        let file = |path: &str, len| RepoFile {
+            path: path.to_owned(),
+            branches: vec![],
+            len,
+            buffer: Box::new(|| Ok(String::new())),
+        };
+
+        assert_eq!(file("src/main.rs", 100).skip_reason(), None);
+        assert_eq!(
+            file("logo.png", 100).skip_reason(),
+            Some(SkipReason::Blacklisted)
+        );
+        assert_eq!(
+            file("src/main.rs", MAX_FILE_LEN).skip_reason(),
+            Some(SkipReason::TooLarge)
+        );
+    }
 }
@@ -0,0 +1,309 @@
+//! Indexing rules checked into a repository.
+//!
+//! A `.bloopignore` file at the root of a repository lists files not to index, in `.gitignore`
+//! syntax. A `.bloop.toml` file next to it can declare globs of files to include or exclude,
+//! language overrides, and the branches to index:
+//!
+//! ```toml
+//! include = ["docs/**/*.svg"]
+//! exclude = ["**/generated/**", "*.min.js"]
+//! branches = { select = ["release/.*"] }
+//!
+//! [languages]
+//! "*.h" = "C++"
+//! ```
+//!
+//! These are merged with the filters configured through the API, which take priority. Local
+//! repositories are indexed from their working tree, where the rules are read from. Remote
+//! repositories are bare clones, so the rules are read from the commit that is indexed.
+
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use regex::Regex;
+use serde::Deserialize;
+use tracing::warn;
+
+use super::{branch_tips, BranchFilter, BranchFilterConfig, FileFilterConfig, FileFilterRule};
+use crate::{query::glob, repo::RepoRef};
+
+/// Name of the file with `.gitignore` rules of files not to index.
+pub const IGNORE_FILE: &str = ".bloopignore";
+
+/// Name of the file with include and exclude rules, language overrides and branch filters.
+pub const CONFIG_FILE: &str = ".bloop.toml";
+
+/// The indexing rules found at the root of a repository.
+#[derive(Debug, Default)]
+pub struct RepoConfig {
+    /// The `include` and `exclude` globs, as API file filter rules
+    pub(super) file_filter: FileFilterConfig,
+    pub(super) ignore: Option<Gitignore>,
+    languages: Vec<(Regex, String)>,
+    branches: Option<BranchFilterConfig>,
+}
+
+/// The contents of `.bloop.toml`.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    /// Globs of files to index, even if they'd be skipped otherwise
+    include: Vec<String>,
+    /// Globs of files not to index
+    exclude: Vec<String>,
+    /// Languages of the files matching each glob, which override the detected ones
+    languages: BTreeMap<String, String>,
+    /// Branches to index, unless a branch filter is configured through the API
+    branches: Option<BranchFilterConfig>,
+}
+
+impl RepoConfig {
+    /// Read the rules at the root of a repository's working tree.
+    ///
+    /// Invalid rules are logged and ignored, so that they don't stop the repository from being
+    /// indexed.
+    pub fn load(root: &Path) -> Self {
+        let read = |name| match fs::read_to_string(root.join(name)) {
+            Ok(text) => Some(text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                warn!(%err, ?root, "failed to read {name}; ignoring it");
+                None
+            }
+        };
+
+        Self::parse(
+            root,
+            read(IGNORE_FILE).as_deref(),
+            read(CONFIG_FILE).as_deref(),
+        )
+    }
+
+    /// Read the rules from the commit of a remote repository that is indexed.
+    ///
+    /// The branches to index are read from HEAD. The other rules are read from the tip of the
+    /// only indexed branch, and from HEAD if more than one branch is indexed.
+    pub fn load_from_git(
+        reporef: &RepoRef,
+        root: &Path,
+        git: &gix::Repository,
+        api: Option<&BranchFilterConfig>,
+    ) -> Self {
+        let load = |branches: &BranchFilter| -> Option<Self> {
+            let tips = branch_tips(reporef, git, branches)
+                .map_err(|err| warn!(%err, ?root, "failed to resolve branches"))
+                .ok()?;
+            let [(_, _, id)] = &tips[..] else {
+                return None;
+            };
+
+            let tree = git.find_object(*id).ok()?.peel_to_tree().ok()?;
+            let entries = tree.decode().ok()?.entries;
+            let read = |name: &str| {
+                let entry = entries
+                    .iter()
+                    .find(|e| e.filename == name && e.mode.is_blob())?;
+                let blob = git.find_object(entry.oid.to_owned()).ok()?;
+                Some(String::from_utf8_lossy(&blob.data).into_owned())
+            };
+
+            Some(Self::parse(
+                root,
+                read(IGNORE_FILE).as_deref(),
+                read(CONFIG_FILE).as_deref(),
+            ))
+        };
+
+        let head = load(&BranchFilter::Head).unwrap_or_default();
+        let branches: BranchFilter = head.branch_filter(api).map(Into::into).unwrap_or_default();
+        if matches!(branches, BranchFilter::Head) {
+            return head;
+        }
+
+        match load(&branches) {
+            Some(indexed) => Self {
+                branches: head.branches,
+                ..indexed
+            },
+            None => head,
+        }
+    }
+
+    fn parse(root: &Path, ignore: Option<&str>, config: Option<&str>) -> Self {
+        let mut repo_config = Self::default();
+
+        if let Some(text) = ignore {
+            let mut builder = GitignoreBuilder::new(root);
+            for line in text.lines() {
+                if let Err(err) = builder.add_line(Some(root.join(IGNORE_FILE)), line) {
+                    warn!(%err, ?root, "invalid rule in {IGNORE_FILE}; skipping");
+                }
+            }
+
+            match builder.build() {
+                Ok(ignore) => repo_config.ignore = Some(ignore),
+                Err(err) => warn!(%err, ?root, "invalid {IGNORE_FILE}; ignoring it"),
+            }
+        }
+
+        let Some(text) = config else {
+            return repo_config;
+        };
+
+        let file = match toml::from_str::<ConfigFile>(text) {
+            Ok(file) => file,
+            Err(err) => {
+                warn!(%err, ?root, "invalid {CONFIG_FILE}; ignoring it");
+                return repo_config;
+            }
+        };
+
+        let includes = file.include.into_iter().map(FileFilterRule::Glob);
+        let excludes = file
+            .exclude
+            .into_iter()
+            .map(|glob| FileFilterRule::Glob(format!("!{glob}")));
+        repo_config.file_filter = FileFilterConfig {
+            rules: includes.chain(excludes).collect(),
+        };
+
+        // More specific globs, which are assumed to be the longer ones, are tried first.
+        let mut languages = file.languages.into_iter().collect::<Vec<_>>();
+        languages.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+
+        repo_config.languages = languages
+            .into_iter()
+            .filter_map(
+                |(pattern, lang)| match Regex::new(&glob::to_regex(&pattern)) {
+                    Ok(regex) => Some((regex, lang)),
+                    Err(err) => {
+                        warn!(%err, %pattern, "invalid glob in {CONFIG_FILE}; skipping");
+                        None
+                    }
+                },
+            )
+            .collect();
+
+        repo_config.branches = file.branches;
+        repo_config
+    }
+
+    /// The language of a file, if it's overridden.
+    pub fn language(&self, relative_path: &Path) -> Option<&str> {
+        let path = relative_path.to_string_lossy();
+        self.languages
+            .iter()
+            .find(|(regex, _)| regex.is_match(&path))
+            .map(|(_, lang)| lang.as_str())
+    }
+
+    /// The branches to index, unless the API configures some.
+    pub fn branch_filter<'a>(
+        &'a self,
+        api: Option<&'a BranchFilterConfig>,
+    ) -> Option<&'a BranchFilterConfig> {
+        api.or(self.branches.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::iterator::{test_utils::TestRepo, FileFilter};
+
+    const CONFIG: &str = r#"
+        include = ["vendor/ours/**"]
+        exclude = ["vendor/", "*.min.js"]
+        branches = { select = ["release/.*"] }
+
+        [languages]
+        "*.h" = "C++"
+        "include/c/*.h" = "C"
+    "#;
+
+    fn filter(api: &[FileFilterRule], ignore: &str) -> FileFilter {
+        let repo_config = RepoConfig::parse(Path::new("/repo"), Some(ignore), Some(CONFIG));
+        let api = FileFilterConfig {
+            rules: api.to_vec(),
+        };
+
+        FileFilter::compile(&api)
+            .unwrap()
+            .with_repo_config(&repo_config)
+            .unwrap()
+    }
+
+    #[test]
+    fn merged_filters() {
+        let filter = filter(&[], "build/\n*.log\n");
+
+        assert_eq!(filter.is_allowed("src/main.rs"), None);
+        assert_eq!(filter.is_allowed("vendor/lib.js"), Some(false));
+        assert_eq!(filter.is_allowed("vendor/ours/lib.js"), Some(true));
+        assert_eq!(filter.is_allowed("web/app.min.js"), Some(false));
+        assert_eq!(filter.is_allowed("build/out.rs"), Some(false));
+        assert_eq!(filter.is_allowed("logs/server.log"), Some(false));
+    }
+
+    #[test]
+    fn api_rules_take_priority() {
+        let api = [
+            FileFilterRule::IncludeFile("build/keep.rs".into()),
+            FileFilterRule::Glob("!vendor/ours/**".into()),
+        ];
+        let filter = filter(&api, "build/\n");
+
+        assert_eq!(filter.is_allowed("build/keep.rs"), Some(true));
+        assert_eq!(filter.is_allowed("vendor/ours/lib.js"), Some(false));
+    }
+
+    #[test]
+    fn languages_and_branches() {
+        let config = RepoConfig::parse(Path::new("/repo"), None, Some(CONFIG));
+
+        assert_eq!(config.language(Path::new("src/lib.h")), Some("C++"));
+        assert_eq!(config.language(Path::new("include/c/lib.h")), Some("C"));
+        assert_eq!(config.language(Path::new("src/lib.c")), None);
+
+        let select = BranchFilterConfig::Select(vec!["release/.*".into()]);
+        assert_eq!(config.branch_filter(None), Some(&select));
+        assert_eq!(
+            config.branch_filter(Some(&BranchFilterConfig::All)),
+            Some(&BranchFilterConfig::All)
+        );
+    }
+
+    #[test]
+    fn invalid_config_is_ignored() {
+        let config = RepoConfig::parse(Path::new("/repo"), None, Some("exclude = 1"));
+        assert!(config.file_filter.rules.is_empty());
+        assert_eq!(config.branch_filter(None), None);
+    }
+
+    #[test]
+    fn load_from_git() {
+        let repo = TestRepo::new();
+        repo.write(IGNORE_FILE, "build/\n");
+        repo.write(
+            CONFIG_FILE,
+            "exclude = [\"*.min.js\"]\n[languages]\n\"*.h\" = \"C++\"\n",
+        );
+        repo.write("src/lib.h", "int f();\n");
+        repo.commit(1_700_000_000);
+
+        // Remote repos are bare, so changes to the working tree don't count.
+        repo.write(CONFIG_FILE, "exclude = [\"src/**\"]\n");
+
+        let config = RepoConfig::load_from_git(&repo.reporef, repo.path(), &repo.open(), None);
+        let filter = FileFilter::compile(&FileFilterConfig::default())
+            .unwrap()
+            .with_repo_config(&config)
+            .unwrap();
+
+        assert_eq!(filter.is_allowed("web/app.min.js"), Some(false));
+        assert_eq!(filter.is_allowed("build/out.rs"), Some(false));
+        assert_eq!(filter.is_allowed("src/lib.h"), None);
+        assert_eq!(config.language(Path::new("src/lib.h")), Some("C++"));
+        assert_eq!(config.branch_filter(None), None);
+    }
+}
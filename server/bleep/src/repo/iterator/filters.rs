@@ -3,9 +3,13 @@ use std::{
     path::Path,
 };
 
+use ignore::gitignore::Gitignore;
 use regex::RegexSet;
 use serde::{Deserialize, Serialize};
 
+use super::RepoConfig;
+use crate::query::glob;
+
 /// Update filter configs for a repository
 #[derive(serde::Deserialize, Clone, Debug, Default)]
 pub struct FilterUpdate {
@@ -103,6 +107,10 @@ pub enum FileFilterRule {
 
     /// Exclude files matchin the regex pattern
     ExcludeRegex(String),
+
+    /// Include files matching the glob, such as `src/**/*.rs`, or exclude them if it starts
+    /// with `!`, as in `!**/vendor/**`
+    Glob(String),
 }
 
 impl FileFilterConfig {
@@ -131,6 +139,14 @@ impl FileFilterConfig {
                     rules.remove(&FileFilterRule::IncludeRegex(x.to_string()));
                     rules.insert(r.clone());
                 }
+                r @ FileFilterRule::Glob(g) => {
+                    let opposite = match g.strip_prefix('!') {
+                        Some(g) => g.to_string(),
+                        None => format!("!{g}"),
+                    };
+                    rules.remove(&FileFilterRule::Glob(opposite));
+                    rules.insert(r.clone());
+                }
             }
         }
 
@@ -146,6 +162,11 @@ pub struct FileFilter {
     include_list: HashSet<String>,
     exclude_patterns: RegexSet,
     include_patterns: RegexSet,
+
+    /// The rules of the repository's `.bloop.toml`, for files the rules above don't mention
+    repo_rules: Option<Box<FileFilter>>,
+    /// The repository's `.bloopignore`
+    ignore: Option<Gitignore>,
 }
 
 impl FileFilter {
@@ -158,9 +179,13 @@ impl FileFilter {
         for rule in &config.rules {
             match rule {
                 FileFilterRule::IncludeFile(name) => include_list.insert(name.to_string()),
-                FileFilterRule::IncludeRegex(pattern) => include_patterns.insert(pattern),
+                FileFilterRule::IncludeRegex(pattern) => include_patterns.insert(pattern.clone()),
                 FileFilterRule::ExcludeFile(name) => exclude_list.insert(name.to_string()),
-                FileFilterRule::ExcludeRegex(pattern) => exclude_patterns.insert(pattern),
+                FileFilterRule::ExcludeRegex(pattern) => exclude_patterns.insert(pattern.clone()),
+                FileFilterRule::Glob(glob) => match glob.strip_prefix('!') {
+                    Some(glob) => exclude_patterns.insert(glob::to_regex(glob)),
+                    None => include_patterns.insert(glob::to_regex(glob)),
+                },
             };
         }
 
@@ -169,9 +194,19 @@ impl FileFilter {
             exclude_list,
             include_patterns: RegexSet::new(include_patterns)?,
             exclude_patterns: RegexSet::new(exclude_patterns)?,
+            repo_rules: None,
+            ignore: None,
         })
     }
 
+    /// Merge with the rules checked into a repository, which only apply to the files that
+    /// aren't mentioned by this filter.
+    pub fn with_repo_config(mut self, repo_config: &RepoConfig) -> anyhow::Result<Self> {
+        self.repo_rules = Some(Box::new(Self::compile(&repo_config.file_filter)?));
+        self.ignore = repo_config.ignore.clone();
+        Ok(self)
+    }
+
     /// Returns:
     ///  * `Some(true)` if the file is allowed
     ///  * `Some(false)` if rejected
@@ -186,6 +221,14 @@ impl FileFilter {
             Some(true)
         } else if self.exclude_list.contains(name) || self.exclude_patterns.is_match(name) {
             Some(false)
+        } else if let Some(allowed) = self.repo_rules.as_ref().and_then(|r| r.is_allowed(path)) {
+            Some(allowed)
+        } else if self.ignore.as_ref().is_some_and(|ignore| {
+            ignore
+                .matched_path_or_any_parents(path.as_ref(), false)
+                .is_ignore()
+        }) {
+            Some(false)
         } else {
             None
         }
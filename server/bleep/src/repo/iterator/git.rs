@@ -1,13 +1,16 @@
-use crate::{background, repo::RepoRef};
+use crate::{
+    background,
+    repo::{IndexedTip, RepoRef},
+};
 
 use super::{filters::BranchFilter, *};
 
-use anyhow::Result;
-use gix::ThreadSafeRepository;
+use anyhow::{bail, Context, Result};
+use gix::{objs::tree::EntryMode, ThreadSafeRepository};
 use tracing::trace;
 
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeSet, HashMap, HashSet},
     path::Path,
 };
 
@@ -16,6 +19,76 @@ fn human_readable_branch_name(r: &gix::Reference<'_>) -> String {
     r.name().shorten().to_str_lossy().to_string()
 }
 
+/// Resolve the branches of a repository that pass the `BranchFilter` to the
+/// commits they point to.
+///
+/// Returns `(is_head, branch_name, commit_id)` for every matching branch.
+pub(crate) fn branch_tips(
+    reporef: &RepoRef,
+    git: &gix::Repository,
+    branches: &BranchFilter,
+) -> Result<Vec<(bool, String, gix::ObjectId)>> {
+    let mut head = git.head()?;
+
+    // HEAD name needs to be pinned to the remote pointer
+    //
+    // Otherwise the local branch will never advance to the
+    // remote's branch ref
+    //
+    // The easiest here is to check by name, and assume the
+    // default remote is `origin`, since we don't configure it
+    // otherwise.
+    let head_name = head.clone().try_into_referent().map(|r| {
+        if reporef.is_local() {
+            human_readable_branch_name(&r)
+        } else {
+            format!("origin/{}", human_readable_branch_name(&r))
+        }
+    });
+
+    let refs = git.references()?;
+    let tips = if head_name.is_none() && matches!(branches, BranchFilter::Head) {
+        // the current checkout is not a branch, so HEAD will not
+        // point to a real reference.
+        vec![(true, "HEAD".to_string(), head.peel_to_commit_in_place()?.id)]
+    } else {
+        refs.all()?
+            .filter_map(Result::ok)
+            // Check if it's HEAD
+            // Normalize the name of the branch for further steps
+            //
+            .map(|r| {
+                let name = human_readable_branch_name(&r);
+                (
+                    head_name
+                        .as_ref()
+                        .map(|head| head == &name)
+                        .unwrap_or_default(),
+                    name,
+                    r,
+                )
+            })
+            .filter(|(_, name, _)| {
+                if reporef.is_local() {
+                    true
+                } else {
+                    // Only consider remote branches
+                    //
+                    name.starts_with("origin/")
+                }
+            })
+            // Apply branch filters, along whether it's HEAD
+            //
+            .filter(|(is_head, name, _)| branches.filter(*is_head, name))
+            .filter_map(|(is_head, branch, r)| -> Option<_> {
+                Some((is_head, branch, r.into_fully_peeled_id().ok()?.detach()))
+            })
+            .collect()
+    };
+
+    Ok(tips)
+}
+
 pub struct GitWalker {
     git: ThreadSafeRepository,
     entries: HashMap<(String, FileType, gix::ObjectId), BTreeSet<String>>,
@@ -35,76 +108,16 @@ impl GitWalker {
             .open(dir.as_ref())?;
 
         let local_git = git.to_thread_local();
-        let mut head = local_git.head()?;
-
-        // HEAD name needs to be pinned to the remote pointer
-        //
-        // Otherwise the local branch will never advance to the
-        // remote's branch ref
-        //
-        // The easiest here is to check by name, and assume the
-        // default remote is `origin`, since we don't configure it
-        // otherwise.
-        let head_name = head.clone().try_into_referent().map(|r| {
-            if reporef.is_local() {
-                human_readable_branch_name(&r)
-            } else {
-                format!("origin/{}", human_readable_branch_name(&r))
-            }
-        });
-
-        let refs = local_git.references()?;
-        let trees = if head_name.is_none() && matches!(branches, BranchFilter::Head) {
-            // the current checkout is not a branch, so HEAD will not
-            // point to a real reference.
-            vec![(
-                true,
-                "HEAD".to_string(),
-                head.peel_to_commit_in_place()?.tree()?,
-            )]
-        } else {
-            refs.all()?
-                .filter_map(Result::ok)
-                // Check if it's HEAD
-                // Normalize the name of the branch for further steps
-                //
-                .map(|r| {
-                    let name = human_readable_branch_name(&r);
-                    (
-                        head_name
-                            .as_ref()
-                            .map(|head| head == &name)
-                            .unwrap_or_default(),
-                        name,
-                        r,
-                    )
-                })
-                .filter(|(_, name, _)| {
-                    if reporef.is_local() {
-                        true
-                    } else {
-                        // Only consider remote branches
-                        //
-                        name.starts_with("origin/")
-                    }
-                })
-                // Apply branch filters, along whether it's HEAD
-                //
-                .filter(|(is_head, name, _)| branches.filter(*is_head, name))
-                .filter_map(|(is_head, branch, r)| -> Option<_> {
-                    Some((
-                        is_head,
-                        branch,
-                        r.into_fully_peeled_id()
-                            .ok()?
-                            .object()
-                            .ok()?
-                            .peel_to_tree()
-                            .ok()?,
-                    ))
-                })
-                .collect()
-        };
+        let trees = branch_tips(reporef, &local_git, &branches)?
+            .into_iter()
+            .filter_map(|(is_head, branch, id)| -> Option<_> {
+                Some((
+                    is_head,
+                    branch,
+                    local_git.find_object(id).ok()?.peel_to_tree().ok()?,
+                ))
+            })
+            .collect::<Vec<_>>();
 
         let entries = trees
             .into_iter()
@@ -127,14 +140,7 @@ impl GitWalker {
             .fold(
                 HashMap::new(),
                 |mut acc, (is_head, branch, file, mode, oid)| {
-                    let kind = if mode.is_tree() {
-                        FileType::Dir
-                    } else if mode.is_blob() {
-                        FileType::File
-                    } else {
-                        FileType::Other
-                    };
-
+                    let kind = file_type(mode);
                     let branches: &mut BTreeSet<String> = acc.entry((file, kind, oid)).or_default();
                     if is_head {
                         branches.insert("HEAD".to_string());
@@ -147,6 +153,146 @@ impl GitWalker {
 
         Ok(Self { git, entries })
     }
+
+    /// Walk the entries that changed on the only indexed branch since it was at `since`.
+    ///
+    /// Returns a walker over the added and modified entries, and the relative paths of the
+    /// modified and removed entries, as they're stored in the file index: directories end with
+    /// a `/`. Fails if more than one branch is indexed, if the indexed branch is not the one at
+    /// `since`, if `since` is not in the history, or if the repository's `.bloopignore` or
+    /// `.bloop.toml` changed, as these can affect any file.
+    pub fn open_diff(
+        reporef: &RepoRef,
+        dir: impl AsRef<Path>,
+        branch_filter: impl Into<Option<BranchFilter>>,
+        since: &IndexedTip,
+    ) -> Result<(Self, HashSet<String>)> {
+        use gix::object::tree::diff::{change::Event, Action};
+
+        let root_dir = dir.as_ref();
+
+        let branches = branch_filter.into().unwrap_or_default();
+        let git = gix::open::Options::isolated()
+            .filter_config_section(|_| false)
+            .open(root_dir)?;
+
+        let local_git = git.to_thread_local();
+        let tips = branch_tips(reporef, &local_git, &branches)?;
+        let [(is_head, branch, id)] = &tips[..] else {
+            bail!("more than one branch is indexed");
+        };
+
+        if *is_head != since.is_head || *branch != since.branch {
+            bail!("indexed branch changed from `{}`", since.branch);
+        }
+
+        let old_tree = local_git
+            .find_object(gix::ObjectId::from_hex(since.commit.as_bytes())?)
+            .context("previously indexed commit is not in the history")?
+            .peel_to_tree()?;
+        let new_tree = local_git.find_object(*id)?.peel_to_tree()?;
+
+        let mut branch_set = BTreeSet::from([branch.clone()]);
+        if *is_head {
+            branch_set.insert("HEAD".to_string());
+        }
+
+        let mut changed = HashMap::new();
+        let mut removed = HashSet::new();
+
+        old_tree
+            .changes()?
+            .track_path()
+            .for_each_to_obtain_tree(&new_tree, |change| {
+                let mut change_entry =
+                    |location: &gix::bstr::BStr, mode: EntryMode, id: gix::Id<'_>| {
+                        let strpath = String::from_utf8_lossy(location.as_ref());
+                        let path = root_dir
+                            .join(strpath.as_ref())
+                            .to_string_lossy()
+                            .to_string();
+                        changed.insert((path, file_type(mode), id.detach()), branch_set.clone());
+                    };
+
+                let mut remove_entry = |location: &gix::bstr::BStr, mode: EntryMode| {
+                    let mut path = String::from_utf8_lossy(location.as_ref()).into_owned();
+                    if mode.is_tree() {
+                        path.push('/');
+                    }
+
+                    removed.insert(path);
+                };
+
+                match change.event {
+                    Event::Addition { entry_mode, id } => {
+                        change_entry(change.location, entry_mode, id);
+                    }
+                    Event::Deletion { entry_mode, .. } => {
+                        remove_entry(change.location, entry_mode);
+                    }
+                    // Directories change with their contents, as does their last commit.
+                    Event::Modification {
+                        previous_entry_mode,
+                        entry_mode,
+                        id,
+                        ..
+                    } => {
+                        remove_entry(change.location, previous_entry_mode);
+                        change_entry(change.location, entry_mode, id);
+                    }
+                    Event::Rewrite {
+                        source_location,
+                        source_entry_mode,
+                        entry_mode,
+                        id,
+                        copy,
+                        ..
+                    } => {
+                        if !copy {
+                            remove_entry(source_location, source_entry_mode);
+                        }
+
+                        change_entry(change.location, entry_mode, id);
+                    }
+                }
+
+                Ok::<_, std::convert::Infallible>(Action::Continue)
+            })?;
+
+        let config_paths = [IGNORE_FILE, CONFIG_FILE]
+            .map(|name| root_dir.join(name).to_string_lossy().to_string());
+        if changed.keys().any(|(path, ..)| config_paths.contains(path))
+            || [IGNORE_FILE, CONFIG_FILE]
+                .iter()
+                .any(|name| removed.contains(*name))
+        {
+            bail!("the indexing rules of the repository changed");
+        }
+
+        trace!(
+            changed = changed.len(),
+            removed = removed.len(),
+            "diffed trees"
+        );
+
+        Ok((
+            Self {
+                git,
+                entries: changed,
+            },
+            removed,
+        ))
+    }
+}
+
+fn file_type(mode: EntryMode) -> FileType {
+    if mode.is_tree() {
+        FileType::Dir
+    } else if mode.is_blob() {
+        FileType::File
+    } else {
+        FileType::Other
+    }
 }
 
 impl FileSource for GitWalker {
@@ -191,3 +337,88 @@ impl FileSource for GitWalker {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::iterator::test_utils::TestRepo;
+
+    fn tip(commit: &str) -> IndexedTip {
+        IndexedTip {
+            branch: "origin/main".into(),
+            is_head: true,
+            commit: commit.into(),
+        }
+    }
+
+    fn changed_files(repo: &TestRepo, walker: &GitWalker) -> BTreeSet<String> {
+        walker
+            .entries
+            .keys()
+            .filter(|(_, kind, _)| matches!(kind, FileType::File))
+            .map(|(path, ..)| {
+                let path = Path::new(path).strip_prefix(repo.path()).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_since_indexed_tip() {
+        let repo = TestRepo::new();
+        let moved = "fn moved() {\n    println!(\"long enough to be detected as a rename\");\n}\n";
+        repo.write("src/keep.rs", "fn keep() {}\n");
+        repo.write("src/modify.rs", "fn before() {}\n");
+        repo.write("src/delete.rs", "fn delete() {}\n");
+        repo.write("old/moved.rs", moved);
+        let since = repo.commit(1_700_000_000);
+
+        repo.write("src/add.rs", "fn add() {}\n");
+        repo.write("src/modify.rs", "fn after() {}\n");
+        repo.remove("src/delete.rs");
+        repo.remove("old/moved.rs");
+        repo.write("new/moved.rs", moved);
+        repo.commit(1_700_000_100);
+
+        let (changed, removed) =
+            GitWalker::open_diff(&repo.reporef, repo.path(), BranchFilter::Head, &tip(&since))
+                .unwrap();
+
+        assert_eq!(
+            changed_files(&repo, &changed),
+            BTreeSet::from(["new/moved.rs", "src/add.rs", "src/modify.rs"].map(String::from))
+        );
+        assert_eq!(
+            removed
+                .iter()
+                .filter(|path| !path.ends_with('/'))
+                .cloned()
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from(["old/moved.rs", "src/delete.rs", "src/modify.rs"].map(String::from))
+        );
+        assert!(removed.contains("old/"));
+    }
+
+    #[test]
+    fn diff_needs_full_walk() {
+        let repo = TestRepo::new();
+        repo.write("src/lib.rs", "fn lib() {}\n");
+        let since = repo.commit(1_700_000_000);
+
+        let unknown = "0".repeat(40);
+        assert!(GitWalker::open_diff(
+            &repo.reporef,
+            repo.path(),
+            BranchFilter::Head,
+            &tip(&unknown)
+        )
+        .is_err());
+
+        repo.write(CONFIG_FILE, "exclude = [\"src/**\"]\n");
+        repo.commit(1_700_000_100);
+        assert!(
+            GitWalker::open_diff(&repo.reporef, repo.path(), BranchFilter::Head, &tip(&since))
+                .is_err()
+        );
+    }
+}
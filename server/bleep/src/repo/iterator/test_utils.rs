@@ -0,0 +1,82 @@
+use std::{fs, path::Path, process::Command};
+
+use tempdir::TempDir;
+
+use crate::repo::{Backend, RepoRef};
+
+/// A git repository laid out like a clone of a remote, with `main` tracked as `origin/main`.
+pub struct TestRepo {
+    dir: TempDir,
+    pub reporef: RepoRef,
+}
+
+impl TestRepo {
+    pub fn new() -> Self {
+        let repo = Self {
+            dir: TempDir::new("test-repo").unwrap(),
+            reporef: RepoRef::new(Backend::Git, "git.example.com/team/repo").unwrap(),
+        };
+
+        repo.git(&["init", "--quiet", "--initial-branch=main"]);
+        repo.git(&["config", "user.name", "Test"]);
+        repo.git(&["config", "user.email", "test@example.com"]);
+        repo
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn remove(&self, path: &str) {
+        fs::remove_file(self.path().join(path)).unwrap();
+    }
+
+    /// Commit every change at `time` seconds since the epoch, and return the commit's id.
+    pub fn commit(&self, time: i64) -> String {
+        let date = format!("{time} +0000");
+        self.git(&["add", "--all"]);
+        let status = Command::new("git")
+            .args(["commit", "--quiet", "--allow-empty", "--message", "commit"])
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
+            .current_dir(self.path())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git commit failed");
+        self.git(&["update-ref", "refs/remotes/origin/main", "HEAD"]);
+        self.git(&["rev-parse", "HEAD"]).trim().to_owned()
+    }
+
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(self.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Clone this repository with only its last `depth` commits, like remote repos are cloned.
+    pub fn shallow_clone(&self, depth: usize) -> Self {
+        let clone = Self {
+            dir: TempDir::new("test-repo").unwrap(),
+            reporef: self.reporef.clone(),
+        };
+
+        let source = format!("file://{}", self.path().display());
+        let depth = depth.to_string();
+        clone.git(&["clone", "--quiet", "--depth", &depth, &source, "."]);
+        clone
+    }
+
+    pub fn open(&self) -> gix::Repository {
+        gix::open(self.path()).unwrap()
+    }
+}
@@ -0,0 +1,451 @@
+//! Saved searches, which are re-run after every successful sync of a repository in their project.
+//!
+//! Each run compares the matches in the synced repository against the previous ones, and any
+//! difference is stored in `saved_search_changes` and posted to the search's webhook, if it has
+//! one.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, error, warn};
+
+use crate::{
+    query::execute::{ApiQuery, QueryResponse, QueryResult},
+    repo::RepoRef,
+    Application,
+};
+
+/// The maximum number of files a saved search run collects matches from.
+const MAX_FILES_PER_RUN: usize = 1000;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A lock per saved search, so that runs triggered by syncs of different repositories compare
+/// against each other's matches, rather than both against the same ones.
+static RUN_LOCKS: Lazy<scc::HashMap<i64, Arc<Mutex<()>>>> = Lazy::new(Default::default);
+
+/// A single match of a saved search.
+///
+/// Matches are compared by their repository, path and line text, so that lines which only moved
+/// around in a file are not reported as changed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub repo_ref: String,
+    pub relative_path: String,
+    /// 1-based line number, absent for path-only results
+    pub line: Option<usize>,
+    pub text: String,
+}
+
+impl Match {
+    fn key(&self) -> (&str, &str, &str) {
+        (&self.repo_ref, &self.relative_path, &self.text)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub added: Vec<Match>,
+    pub removed: Vec<Match>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    saved_search_id: i64,
+    project_id: i64,
+    name: &'a str,
+    query: &'a str,
+    repo_ref: &'a str,
+    #[serde(flatten)]
+    changes: &'a Changes,
+}
+
+/// The matches of a saved search run.
+pub struct Run {
+    pub matches: Vec<Match>,
+    /// Whether more than `MAX_FILES_PER_RUN` files matched, and only the first ones were kept
+    pub truncated: bool,
+}
+
+impl Run {
+    /// The matches to compare the next run against, unless this run is truncated.
+    ///
+    /// Files beyond `MAX_FILES_PER_RUN` are picked by rank, so comparing truncated runs would
+    /// report files moving in and out of the limit as changes.
+    pub fn baseline(&self) -> Result<Option<String>> {
+        if self.truncated {
+            return Ok(None);
+        }
+
+        Ok(Some(serde_json::to_string(&self.matches)?))
+    }
+
+    /// Compare the matches of this run in `repo_ref` against the previous ones, and keep the
+    /// previous matches of the other repositories.
+    ///
+    /// Only the synced repository is compared, so that changes are attributed to it. The other
+    /// repositories are compared when they are synced themselves.
+    fn rebase(&mut self, previous: Vec<Match>, repo_ref: &str) -> Changes {
+        let (previous, others): (Vec<_>, Vec<_>) =
+            previous.into_iter().partition(|m| m.repo_ref == repo_ref);
+
+        self.matches.retain(|m| m.repo_ref == repo_ref);
+        let changes = diff(&previous, &self.matches);
+        self.matches.extend(others);
+        changes
+    }
+}
+
+/// Run a saved search query against the current state of its project's repositories.
+pub async fn run(app: &Application, project_id: i64, query: &str) -> Result<Run> {
+    let api_query = ApiQuery {
+        q: query.to_owned(),
+        project_id,
+        page: 0,
+        // One more file than we keep, to know if there are more.
+        page_size: MAX_FILES_PER_RUN + 1,
+        calculate_totals: false,
+        context_before: 0,
+        context_after: 0,
+        ranking: None,
+        ranking_profile: app.config.ranking_profile(None)?,
+    };
+
+    let mut response = Arc::new(api_query).query(app).await?;
+    let truncated = response.data.len() > MAX_FILES_PER_RUN;
+    response.data.truncate(MAX_FILES_PER_RUN);
+
+    Ok(Run {
+        matches: matches(response),
+        truncated,
+    })
+}
+
+/// Re-run all saved searches of the projects which contain `reporef`.
+///
+/// This is called after each successful sync, so errors are only logged.
+pub async fn rerun_for_repo(app: Application, reporef: RepoRef) {
+    if let Err(err) = try_rerun_for_repo(&app, &reporef).await {
+        error!(?err, %reporef, "failed to re-run saved searches");
+    }
+}
+
+async fn try_rerun_for_repo(app: &Application, reporef: &RepoRef) -> Result<()> {
+    let repo_str = reporef.to_string();
+    let searches = sqlx::query! {
+        "SELECT s.id, s.project_id, s.name, s.query, s.webhook_url \
+         FROM saved_searches s \
+         JOIN project_repos pr ON pr.project_id = s.project_id \
+         WHERE pr.repo_ref = ?",
+        repo_str,
+    }
+    .fetch_all(&*app.sql)
+    .await?;
+
+    for search in searches {
+        let lock = RUN_LOCKS
+            .entry_async(search.id)
+            .await
+            .or_insert_with(Default::default)
+            .get()
+            .clone();
+        let _guard = lock.lock().await;
+
+        let mut run = match run(app, search.project_id, &search.query).await {
+            Ok(run) => run,
+            Err(err) => {
+                warn!(?err, search.id, "saved search failed");
+                continue;
+            }
+        };
+
+        let mut tx = app.sql.begin().await?;
+
+        let Some(row) = sqlx::query! {
+            "SELECT last_matches FROM saved_searches WHERE id = ?",
+            search.id,
+        }
+        .fetch_optional(&mut tx)
+        .await?
+        else {
+            // The search was deleted while it ran.
+            continue;
+        };
+
+        let previous = match row
+            .last_matches
+            .as_deref()
+            .map(serde_json::from_str::<Vec<Match>>)
+            .transpose()
+        {
+            Ok(previous) => previous,
+            Err(err) => {
+                warn!(?err, search.id, "ignoring unreadable saved search matches");
+                None
+            }
+        };
+
+        // Without matches to compare against, this run only becomes the baseline of the next.
+        let changes = match previous {
+            Some(previous) if !run.truncated => run.rebase(previous, &repo_str),
+            _ => Changes::default(),
+        };
+
+        if run.truncated {
+            warn!(
+                search.id,
+                "saved search matched more than {MAX_FILES_PER_RUN} files, skipping comparison"
+            );
+        }
+
+        debug!(
+            search.id,
+            added = changes.added.len(),
+            removed = changes.removed.len(),
+            "re-ran saved search"
+        );
+
+        let last_matches = run.baseline()?;
+        sqlx::query! {
+            "UPDATE saved_searches SET last_matches = ?, last_run_at = datetime('now') WHERE id = ?",
+            last_matches,
+            search.id,
+        }
+        .execute(&mut tx)
+        .await?;
+
+        if !changes.is_empty() {
+            let added = serde_json::to_string(&changes.added)?;
+            let removed = serde_json::to_string(&changes.removed)?;
+            sqlx::query! {
+                "INSERT INTO saved_search_changes (saved_search_id, repo_ref, added, removed) \
+                 VALUES (?, ?, ?, ?)",
+                search.id,
+                repo_str,
+                added,
+                removed,
+            }
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        if let (Some(url), false) = (search.webhook_url, changes.is_empty()) {
+            let payload = WebhookPayload {
+                saved_search_id: search.id,
+                project_id: search.project_id,
+                name: &search.name,
+                query: &search.query,
+                repo_ref: &repo_str,
+                changes: &changes,
+            };
+
+            if let Err(err) = notify(&url, &payload).await {
+                warn!(?err, search.id, "failed to call saved search webhook");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn notify(url: &str, payload: &WebhookPayload<'_>) -> reqwest::Result<()> {
+    reqwest::Client::new()
+        .post(url)
+        .timeout(WEBHOOK_TIMEOUT)
+        .json(payload)
+        .send()
+        .await?
+        .error_for_status()
+        .map(|_| ())
+}
+
+/// Flatten a query response into a list of matched lines.
+///
+/// Only content and path results are kept, as repository and commit results don't describe the
+/// state of the code.
+fn matches(response: QueryResponse) -> Vec<Match> {
+    let mut matches = Vec::new();
+
+    for result in response.data {
+        match result {
+            QueryResult::Snippets(file) => {
+                for snippet in &file.snippets {
+                    let mut lines = snippet
+                        .highlight_positions()
+                        .into_iter()
+                        .map(|(line, _)| line)
+                        .collect::<Vec<_>>();
+                    lines.dedup();
+
+                    matches.extend(lines.into_iter().map(|line| Match {
+                        repo_ref: file.repo_ref.clone(),
+                        relative_path: file.relative_path.clone(),
+                        line: Some(line),
+                        text: snippet.line(line).unwrap_or_default().trim().to_owned(),
+                    }));
+                }
+            }
+            QueryResult::FileResult(file) => matches.push(Match {
+                repo_ref: file.repo_ref.to_string(),
+                relative_path: file.relative_path.text,
+                line: None,
+                text: String::new(),
+            }),
+            _ => {}
+        }
+    }
+
+    matches
+}
+
+/// Compare two runs of a saved search.
+///
+/// Identical lines are counted, so that a second copy of an already matching line is reported
+/// as added.
+fn diff(previous: &[Match], current: &[Match]) -> Changes {
+    let mut counts = HashMap::<_, isize>::new();
+    for m in previous {
+        *counts.entry(m.key()).or_default() -= 1;
+    }
+    for m in current {
+        *counts.entry(m.key()).or_default() += 1;
+    }
+
+    let mut changes = Changes::default();
+
+    for m in current.iter().rev() {
+        if let Some(n) = counts.get_mut(&m.key()).filter(|n| **n > 0) {
+            *n -= 1;
+            changes.added.push(m.clone());
+        }
+    }
+
+    for m in previous.iter().rev() {
+        if let Some(n) = counts.get_mut(&m.key()).filter(|n| **n < 0) {
+            *n += 1;
+            changes.removed.push(m.clone());
+        }
+    }
+
+    changes.added.reverse();
+    changes.removed.reverse();
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(path: &str, line: usize, text: &str) -> Match {
+        Match {
+            repo_ref: "github.com/BloopAI/bloop".into(),
+            relative_path: path.into(),
+            line: Some(line),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn diff_matches() {
+        let previous = vec![
+            line("src/lib.rs", 10, "unsafe { a() }"),
+            line("src/lib.rs", 20, "unsafe { b() }"),
+            line("src/main.rs", 5, "unsafe { c() }"),
+        ];
+
+        // Lines moved, one was removed, and a duplicate was added.
+        let current = vec![
+            line("src/lib.rs", 12, "unsafe { a() }"),
+            line("src/lib.rs", 22, "unsafe { b() }"),
+            line("src/lib.rs", 30, "unsafe { b() }"),
+        ];
+
+        assert_eq!(
+            diff(&previous, &current),
+            Changes {
+                added: vec![line("src/lib.rs", 30, "unsafe { b() }")],
+                removed: vec![line("src/main.rs", 5, "unsafe { c() }")],
+            }
+        );
+
+        assert!(diff(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn truncated_runs_have_no_baseline() {
+        let matches = vec![line("src/lib.rs", 10, "unsafe { a() }")];
+
+        let run = Run {
+            matches: matches.clone(),
+            truncated: false,
+        };
+        let baseline = run.baseline().unwrap().unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<Match>>(&baseline).unwrap(),
+            matches
+        );
+
+        let run = Run {
+            matches,
+            truncated: true,
+        };
+        assert_eq!(run.baseline().unwrap(), None);
+    }
+
+    #[test]
+    fn changes_are_attributed_to_the_synced_repo() {
+        let other = |path: &str, n: usize, text: &str| Match {
+            repo_ref: "github.com/BloopAI/other".into(),
+            ..line(path, n, text)
+        };
+
+        let previous = vec![
+            line("src/lib.rs", 10, "unsafe { a() }"),
+            other("src/lib.rs", 10, "unsafe { b() }"),
+        ];
+
+        // Both repos changed, but only one of them was synced.
+        let current = vec![
+            line("src/lib.rs", 10, "unsafe { a() }"),
+            line("src/lib.rs", 20, "unsafe { c() }"),
+            other("src/main.rs", 5, "unsafe { d() }"),
+        ];
+
+        let mut run = Run {
+            matches: current.clone(),
+            truncated: false,
+        };
+        assert_eq!(
+            run.rebase(previous, "github.com/BloopAI/bloop"),
+            Changes {
+                added: vec![line("src/lib.rs", 20, "unsafe { c() }")],
+                removed: vec![],
+            }
+        );
+
+        // The changes of the other repo are reported once it is synced.
+        let previous = run.matches;
+        let mut run = Run {
+            matches: current,
+            truncated: false,
+        };
+        assert_eq!(
+            run.rebase(previous, "github.com/BloopAI/other"),
+            Changes {
+                added: vec![other("src/main.rs", 5, "unsafe { d() }")],
+                removed: vec![other("src/lib.rs", 10, "unsafe { b() }")],
+            }
+        );
+    }
+}
@@ -6,10 +6,12 @@ use anyhow::bail;
 use qdrant_client::{
     prelude::{QdrantClient, QdrantClientConfig},
     qdrant::{
-        point_id::PointIdOptions, r#match::MatchValue, vectors::VectorsOptions,
-        with_payload_selector, with_vectors_selector, CollectionOperationResponse, FieldCondition,
-        FieldType, Filter, Match, PointId, PointsOperationResponse, RetrievedPoint, ScoredPoint,
-        SearchParams, SearchPoints, Value, Vectors, WithPayloadSelector, WithVectorsSelector,
+        point_id::PointIdOptions, points_selector::PointsSelectorOneOf, r#match::MatchValue,
+        vectors::VectorsOptions, with_payload_selector, with_vectors_selector,
+        CollectionOperationResponse, FieldCondition, FieldType, Filter, Match, PointId,
+        PointStruct, PointsIdsList, PointsOperationResponse, PointsSelector, RetrievedPoint,
+        ScoredPoint, ScrollPoints, SearchParams, SearchPoints, Value, Vectors, WithPayloadSelector,
+        WithVectorsSelector,
     },
 };
 
@@ -21,6 +23,7 @@ use tracing::{debug, error, info, trace, warn};
 pub mod chunk;
 pub mod embedder;
 pub mod execute;
+pub mod hybrid;
 mod schema;
 
 pub use embedder::Embedder;
@@ -696,6 +699,103 @@ impl Semantic {
             .delete_points(&self.config.collection_name, &selector, None)
             .await;
     }
+
+    /// Call `f` with every point of a repository, and its embedding if `with_vectors` is set.
+    pub async fn for_each_point(
+        &self,
+        repo_ref: &str,
+        with_vectors: bool,
+        mut f: impl FnMut(Payload) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        const PAGE_SIZE: u32 = 1000;
+
+        let filter = Filter {
+            must: vec![make_kv_keyword_filter("repo_ref", repo_ref).into()],
+            ..Default::default()
+        };
+
+        let mut offset = None;
+        loop {
+            let response = self
+                .qdrant
+                .scroll(&ScrollPoints {
+                    collection_name: self.config.collection_name.to_string(),
+                    filter: Some(filter.clone()),
+                    offset,
+                    limit: Some(PAGE_SIZE),
+                    with_payload: Some(true.into()),
+                    with_vectors: Some(with_vectors.into()),
+                    ..Default::default()
+                })
+                .await?;
+
+            for point in response.result {
+                f(Payload::from_scroll(point))?;
+            }
+
+            match response.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Delete points by their ids, waiting for the deletion to be applied.
+    pub async fn delete_points(&self, ids: Vec<String>) -> anyhow::Result<()> {
+        let selector = PointsSelector {
+            points_selector_one_of: Some(PointsSelectorOneOf::Points(PointsIdsList {
+                ids: ids.into_iter().map(PointId::from).collect(),
+            })),
+        };
+
+        self.qdrant
+            .delete_points_blocking(&self.config.collection_name, &selector, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Delete every point of a repository, waiting for the deletion to be applied.
+    pub async fn delete_repo_points(&self, repo_ref: &str) -> anyhow::Result<()> {
+        let selector = Filter {
+            must: vec![make_kv_keyword_filter("repo_ref", repo_ref).into()],
+            ..Default::default()
+        }
+        .into();
+
+        self.qdrant
+            .delete_points_blocking(&self.config.collection_name, &selector, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Insert points which were already embedded, e.g. by another installation.
+    ///
+    /// Every payload must have an `id` and an `embedding`.
+    pub async fn insert_points(&self, points: Vec<Payload>) -> anyhow::Result<()> {
+        let points = points
+            .into_iter()
+            .map(|mut payload| {
+                let (Some(id), Some(embedding)) = (payload.id.take(), payload.embedding.take())
+                else {
+                    bail!("point is missing its id or embedding");
+                };
+
+                Ok(PointStruct {
+                    id: Some(PointId::from(id)),
+                    vectors: Some(embedding.into()),
+                    payload: payload.into_qdrant(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        self.qdrant
+            .upsert_points_blocking(&self.config.collection_name, points, None)
+            .await?;
+
+        Ok(())
+    }
 }
 
 /// Initialize the `ORT_DYLIB_PATH` variable, consumed by the `ort` crate.
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use crate::{
+    indexes::Indexes,
     query::{
         execute::{ApiQuery, PagingMetadata, QueryResponse, QueryResult, ResultStats},
         parser::SemanticQuery,
@@ -9,7 +10,7 @@ use crate::{
     snippet::Snippet,
 };
 
-use super::Semantic;
+use super::{Payload, Semantic};
 
 use anyhow::Result;
 
@@ -18,18 +19,31 @@ pub async fn execute(
     query: SemanticQuery<'_>,
     params: ApiQuery,
 ) -> Result<QueryResponse> {
-    let results = semantic
-        .search(
-            &query,
-            SemanticSearchParams {
-                limit: params.page_size as u64,
-                offset: ((params.page + 1) * params.page_size) as u64,
-                threshold: 0.0,
-                exact_match: false,
-            },
-        )
-        .await?;
+    let results = semantic.search(&query, search_params(&params)).await?;
+    Ok(into_response(results, params))
+}
+
+/// Execute a query with both the content index and Qdrant, see [`super::hybrid`].
+pub async fn execute_hybrid(
+    indexes: &Indexes,
+    semantic: Semantic,
+    query: SemanticQuery<'_>,
+    params: ApiQuery,
+) -> Result<QueryResponse> {
+    let results = super::hybrid::search(indexes, &semantic, &query, search_params(&params)).await?;
+    Ok(into_response(results, params))
+}
+
+fn search_params(params: &ApiQuery) -> SemanticSearchParams {
+    SemanticSearchParams {
+        limit: params.page_size as u64,
+        offset: ((params.page + 1) * params.page_size) as u64,
+        threshold: 0.0,
+        exact_match: false,
+    }
+}
 
+fn into_response(results: Vec<Payload>, params: ApiQuery) -> QueryResponse {
     let data = results
         .into_iter()
         .fold(HashMap::<_, Vec<_>>::new(), |mut acc, payload| {
@@ -60,10 +74,11 @@ pub async fn execute(
             })
         })
         .collect::<Vec<_>>();
-    Ok(QueryResponse {
+    QueryResponse {
         count: data.len(),
         metadata: PagingMetadata::new(params.page, params.page_size, None),
         stats: ResultStats::default(),
         data,
-    })
+        collectors: vec![],
+    }
 }
@@ -0,0 +1,336 @@
+//! Hybrid retrieval, combining the tantivy content index with Qdrant.
+//!
+//! Both searches run in parallel. Lexical results are split into the same chunks that are
+//! embedded in Qdrant, keeping the best matching chunk of each file, so that a chunk found by both
+//! searches can be recognized. The two rankings are then fused with reciprocal rank fusion, and
+//! the best fused candidates are deduplicated with MMR.
+
+use std::{cmp::Reverse, collections::HashMap};
+
+use anyhow::Result;
+use tantivy::collector::{MultiCollector, TopDocs};
+use tracing::{debug, trace};
+
+use super::{deduplicate_snippets, Payload, Semantic, SemanticSearchParams};
+use crate::{
+    indexes::{
+        reader::{ContentDocument, ContentReader},
+        Indexes,
+    },
+    query::{
+        parser::{Literal, Query, SemanticQuery, Target},
+        ranking::DocumentTweaker,
+        stopwords::remove_stopwords,
+    },
+};
+
+/// Weight of lower ranks in reciprocal rank fusion, see `Semantic::merge_rrf`.
+const RRF_K: f32 = 60.0;
+
+/// Shorter keywords don't have any trigrams to search for.
+const MIN_KEYWORD_LEN: usize = 3;
+
+/// Search code with both the content index and Qdrant, returning at most `params.limit` chunks.
+pub async fn search(
+    indexes: &Indexes,
+    semantic: &Semantic,
+    query: &SemanticQuery<'_>,
+    params: SemanticSearchParams,
+) -> Result<Vec<Payload>> {
+    let Some(target) = query.target() else {
+        anyhow::bail!("no search target for query");
+    };
+
+    let SemanticSearchParams {
+        limit,
+        offset,
+        threshold,
+        exact_match: exact,
+    } = params;
+
+    // Retrieve double `limit` from each side, to leave room for fusion and deduplication.
+    let candidates = limit * 2;
+
+    let vector_search = async {
+        let vector = semantic.embedder().embed(&target).await?;
+        let results = semantic
+            .search_with(query, vector.clone(), candidates, offset, threshold, exact)
+            .await?
+            .into_iter()
+            .map(Payload::from_qdrant)
+            .collect::<Vec<_>>();
+
+        anyhow::Ok((vector, results))
+    };
+
+    let lexical_search = search_lexical(indexes, semantic, query, &target, candidates, offset);
+
+    let ((vector, semantic_results), lexical_results) =
+        futures::try_join!(vector_search, lexical_search)?;
+
+    debug!(
+        semantic = semantic_results.len(),
+        lexical = lexical_results.len(),
+        "running hybrid search"
+    );
+
+    let mut fused = fuse([semantic_results, lexical_results]);
+    fused.truncate(candidates as usize);
+
+    embed_missing(semantic, &mut fused).await?;
+    fused.retain(|p| p.embedding.is_some());
+
+    Ok(deduplicate_snippets(fused, vector, limit))
+}
+
+/// Search the content index for any of the keywords in `target`, and pick the best chunk of each
+/// matching file.
+async fn search_lexical(
+    indexes: &Indexes,
+    semantic: &Semantic,
+    query: &SemanticQuery<'_>,
+    target: &str,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<Payload>> {
+    let keywords = remove_stopwords(target)
+        .split_whitespace()
+        .filter(|k| k.len() >= MIN_KEYWORD_LEN)
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+
+    if keywords.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let queries = lexical_queries(query, &keywords);
+    trace!(?queries, "performing lexical search");
+
+    let profile = semantic.config.ranking_profile(None)?;
+    let top_k = TopDocs::with_limit(limit as usize)
+        .and_offset(offset as usize)
+        .tweak_score(DocumentTweaker(indexes.file.source.clone(), profile));
+
+    let docs = indexes
+        .file
+        .query(
+            queries.iter(),
+            &ContentReader,
+            (top_k, MultiCollector::new()),
+        )
+        .await?
+        .docs
+        .collect::<Vec<_>>();
+
+    // Chunking tokenizes every document, which is too slow to run on the async runtime.
+    let semantic = semantic.clone();
+    let payloads = tokio::task::spawn_blocking(move || {
+        docs.into_iter()
+            .filter_map(|doc| best_chunk(&semantic, &doc, &keywords))
+            .collect::<Vec<_>>()
+    })
+    .await?;
+
+    Ok(payloads)
+}
+
+/// Pick the chunk of a document that contains the most keywords, if any.
+fn best_chunk(semantic: &Semantic, doc: &ContentDocument, keywords: &[String]) -> Option<Payload> {
+    let branches = doc
+        .branches
+        .as_deref()
+        .unwrap_or_default()
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+
+    semantic
+        .chunks_for_buffer(
+            String::new(),
+            &doc.repo_name,
+            &doc.repo_ref,
+            &doc.relative_path,
+            &doc.content,
+            doc.lang.as_deref().unwrap_or_default(),
+            &branches,
+        )
+        .map(|(_, payload)| (keyword_score(&payload.text, keywords), payload))
+        .filter(|(score, _)| *score > 0)
+        .max_by_key(|(score, payload)| (*score, Reverse(payload.start_byte)))
+        .map(|(_, payload)| payload)
+}
+
+/// Build content queries matching any keyword, for every combination of the query's filters.
+fn lexical_queries<'a>(query: &'a SemanticQuery<'a>, keywords: &[String]) -> Vec<Query<'a>> {
+    let pattern = keywords
+        .iter()
+        .map(|k| regex::escape(k))
+        .collect::<Vec<_>>()
+        .join("|");
+
+    let base = Query {
+        target: Some(Target::Content(Literal::Regex(pattern.into()))),
+        branch: query.branch.first().cloned(),
+        ..Default::default()
+    };
+
+    let or_all = |literals: &'a [Literal<'a>]| -> Vec<Option<Literal<'a>>> {
+        if literals.is_empty() {
+            vec![None]
+        } else {
+            literals.iter().cloned().map(Some).collect()
+        }
+    };
+
+    let mut queries = Vec::new();
+    for repo in or_all(&query.repos) {
+        for path in or_all(&query.paths) {
+            for lang in or_all(&query.langs) {
+                queries.push(Query {
+                    repo: repo.clone(),
+                    path: path.clone(),
+                    lang,
+                    ..base.clone()
+                });
+            }
+        }
+    }
+
+    queries
+}
+
+/// Score a chunk by the number of distinct keywords it contains, then their total occurrences.
+fn keyword_score(text: &str, keywords: &[String]) -> usize {
+    let text = text.to_lowercase();
+    let counts = keywords
+        .iter()
+        .map(|k| text.matches(k.as_str()).count())
+        .collect::<Vec<_>>();
+
+    10 * counts.iter().filter(|&&c| c > 0).count() + counts.iter().sum::<usize>()
+}
+
+/// Fuse rankings of chunks with reciprocal rank fusion.
+///
+/// Chunks are identified by their location, as lexical results are not Qdrant points. The first
+/// ranking a chunk appears in provides its payload, and its embedding if it has one. The fused
+/// score of each chunk is stored in its `score`.
+fn fuse(rankings: impl IntoIterator<Item = Vec<Payload>>) -> Vec<Payload> {
+    let key = |p: &Payload| {
+        (
+            p.repo_ref.to_string(),
+            p.relative_path.clone(),
+            p.start_byte,
+            p.end_byte,
+        )
+    };
+
+    let mut scores = HashMap::<_, f32>::new();
+    let mut fused = Vec::new();
+
+    for ranking in rankings {
+        for (rank, payload) in ranking.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f32 + 1.0);
+            match scores.get_mut(&key(&payload)) {
+                Some(total) => *total += score,
+                None => {
+                    scores.insert(key(&payload), score);
+                    fused.push(payload);
+                }
+            }
+        }
+    }
+
+    for payload in fused.iter_mut() {
+        payload.score = scores.get(&key(payload)).copied();
+    }
+
+    fused.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    fused
+}
+
+/// Embed the chunks that were only found by the lexical search, so that MMR can compare them.
+async fn embed_missing(semantic: &Semantic, chunks: &mut [Payload]) -> Result<()> {
+    // This is the same text `Semantic::chunks_for_buffer` embeds when indexing.
+    let missing = chunks
+        .iter()
+        .filter(|p| p.embedding.is_none())
+        .map(|p| format!("{}\t{}\n{}", p.repo_name, p.relative_path, p.text))
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut embeddings = semantic
+        .embedder()
+        .batch_embed(missing.iter().map(String::as_str).collect())
+        .await?
+        .into_iter();
+
+    for payload in chunks.iter_mut().filter(|p| p.embedding.is_none()) {
+        payload.embedding = embeddings.next();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(path: &str, start_byte: u64) -> Payload {
+        Payload {
+            lang: "rust".into(),
+            repo_name: "bloop".into(),
+            repo_ref: "github.com/BloopAI/bloop".parse().unwrap(),
+            relative_path: path.into(),
+            content_hash: String::new(),
+            text: String::new(),
+            start_line: 0,
+            end_line: 0,
+            start_byte,
+            end_byte: start_byte + 100,
+            branches: vec![],
+            id: None,
+            embedding: None,
+            score: None,
+        }
+    }
+
+    #[test]
+    fn fuse_rankings() {
+        let semantic = vec![
+            Payload {
+                embedding: Some(vec![1.0]),
+                ..chunk("a.rs", 0)
+            },
+            chunk("b.rs", 0),
+            chunk("c.rs", 0),
+        ];
+        let lexical = vec![chunk("c.rs", 0), chunk("a.rs", 0), chunk("d.rs", 100)];
+
+        let fused = fuse([semantic, lexical]);
+        let order = fused
+            .iter()
+            .map(|p| (p.relative_path.as_str(), p.start_byte))
+            .collect::<Vec<_>>();
+
+        // Chunks found by both searches come first, best combined ranks first.
+        assert_eq!(
+            order,
+            vec![("a.rs", 0), ("c.rs", 0), ("b.rs", 0), ("d.rs", 100)]
+        );
+
+        // The payload of the first ranking is kept, along with its embedding.
+        assert_eq!(fused[0].embedding, Some(vec![1.0]));
+        assert_eq!(fused[0].score, Some(1.0 / 61.0 + 1.0 / 62.0));
+    }
+
+    #[test]
+    fn score_keywords() {
+        let keywords = ["retry".to_owned(), "policy".to_owned()];
+        assert_eq!(keyword_score("RetryPolicy::new(retry)", &keywords), 23);
+        assert_eq!(keyword_score("let x = 1;", &keywords), 0);
+    }
+}
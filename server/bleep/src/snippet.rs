@@ -94,6 +94,36 @@ impl Location {
     }
 }
 
+impl Snippet {
+    /// The 1-based line and column of the start of each highlight, in the original text.
+    pub fn highlight_positions(&self) -> Vec<(usize, usize)> {
+        let data = self.data.as_bytes();
+
+        self.highlights
+            .iter()
+            .filter(|h| h.start <= data.len())
+            .map(|h| {
+                let before = &data[..h.start];
+                let line = before.iter().filter(|&&b| b == b'\n').count();
+                let line_start = before
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+
+                (self.line_range.start + line + 1, h.start - line_start + 1)
+            })
+            .collect()
+    }
+
+    /// The text of a line of this snippet, given its 1-based line number in the original text.
+    pub fn line(&self, line: usize) -> Option<&str> {
+        self.data
+            .lines()
+            .nth(line.checked_sub(self.line_range.start + 1)?)
+    }
+}
+
 impl SnippedFile {
     pub fn merge(mut self, rhs: Self) -> Self {
         self.snippets.extend(rhs.snippets);
@@ -144,6 +174,17 @@ impl Snipper {
         &self,
         regex: &str,
         doc: &indexes::reader::ContentDocument,
+    ) -> Result<Option<SnippedFile>> {
+        self.all_for_doc_of_kind(regex, doc, None)
+    }
+
+    /// Like `all_for_doc`, but when finding symbols, only consider symbols of the given kind,
+    /// such as `function` or `struct`.
+    pub fn all_for_doc_of_kind(
+        &self,
+        regex: &str,
+        doc: &indexes::reader::ContentDocument,
+        symbol_kind: Option<&str>,
     ) -> Result<Option<SnippedFile>> {
         let query = RegexBuilder::new(regex)
             .multi_line(true)
@@ -155,6 +196,10 @@ impl Snipper {
             // search results with the symbol list present in a document.
             //
             let mut symbols = doc.symbol_locations.list();
+            if let Some(kind) = symbol_kind {
+                symbols.retain(|sym| sym.kind.eq_ignore_ascii_case(kind));
+            }
+
             let symbol_ranges = symbols
                 .iter()
                 .map(|sym| sym.range.into())
@@ -210,17 +255,36 @@ impl Snipper {
                 .collect::<Vec<_>>()
         };
 
-        Ok(if snippets.is_empty() {
-            None
-        } else {
-            Some(SnippedFile {
-                relative_path: doc.relative_path.clone(),
-                repo_name: doc.repo_name.clone(),
-                repo_ref: doc.repo_ref.clone(),
-                lang: doc.lang.clone(),
-                snippets,
-            })
-        })
+        Ok(snipped_file(doc, snippets))
+    }
+
+    /// Snip a list of already matched byte ranges in a document, such as structural search
+    /// matches.
+    pub fn all_for_ranges(
+        &self,
+        ranges: Vec<Range<usize>>,
+        doc: &indexes::reader::ContentDocument,
+    ) -> Option<SnippedFile> {
+        let snippets = self
+            .expand_many(ranges.into_iter(), &doc.content, &doc.line_end_indices)
+            .map(|loc| loc.reify(&doc.content, &[]))
+            .collect::<Vec<_>>();
+
+        snipped_file(doc, snippets)
+    }
+
+    /// Find all snippets matching `regex` in a piece of plain text, which isn't backed by an
+    /// indexed file.
+    pub fn all_for_text(&self, regex: &Regex, text: &str) -> Vec<Snippet> {
+        let line_ends = text
+            .match_indices('\n')
+            .map(|(i, _)| i as u32)
+            .collect::<Vec<_>>();
+
+        let highlights = regex.find_iter(text).map(|m| m.range());
+        self.expand_many(highlights, text, &line_ends)
+            .map(|loc| loc.reify(text, &[]))
+            .collect()
     }
 
     fn expand_many<'a>(
@@ -293,6 +357,23 @@ impl Snipper {
     }
 }
 
+fn snipped_file(
+    doc: &indexes::reader::ContentDocument,
+    snippets: Vec<Snippet>,
+) -> Option<SnippedFile> {
+    if snippets.is_empty() {
+        None
+    } else {
+        Some(SnippedFile {
+            relative_path: doc.relative_path.clone(),
+            repo_name: doc.repo_name.clone(),
+            repo_ref: doc.repo_ref.clone(),
+            lang: doc.lang.clone(),
+            snippets,
+        })
+    }
+}
+
 #[derive(Serialize)]
 pub struct HighlightedString {
     pub text: String,
@@ -609,6 +690,31 @@ mod tests {
             .is_some());
     }
 
+    #[test]
+    fn snip_plain_text() {
+        let snipper = Snipper::default().context(0, 0);
+        let snippets =
+            snipper.all_for_text(&Regex::new("retry").unwrap(), "retry\n\n\nold\nretry\n");
+
+        assert_eq!(
+            snippets,
+            vec![
+                Snippet {
+                    data: "retry".into(),
+                    line_range: 0..0,
+                    highlights: vec![0..5],
+                    symbols: vec![],
+                },
+                Snippet {
+                    data: "retry".into(),
+                    line_range: 4..4,
+                    highlights: vec![0..5],
+                    symbols: vec![],
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_highlighted_string() {
         let mut s = HighlightedString::new("foo bar quux");
@@ -620,4 +726,19 @@ mod tests {
         assert_eq!(s.text, "foo bar quux");
         assert_eq!(s.highlights.to_vec(), &[0..3, 4..8, 10..12]);
     }
+
+    #[test]
+    fn highlight_positions() {
+        let snippet = Snippet {
+            data: "fn main() {\n    let foo = bar();\n}\n".to_owned(),
+            highlights: vec![3..7, 20..23, 26..29],
+            symbols: vec![],
+            line_range: 9..12,
+        };
+
+        assert_eq!(
+            snippet.highlight_positions(),
+            vec![(10, 4), (11, 9), (11, 15)]
+        );
+    }
 }
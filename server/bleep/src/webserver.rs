@@ -81,6 +81,7 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
         // misc
         .route("/search/code", get(search::semantic_code))
         .route("/file", get(file::handle))
+        .route("/file/blame", get(file::blame))
         .route("/folder", get(file::folder))
         .route("/projects", get(project::list).post(project::create))
         .route(
@@ -105,6 +106,18 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
             "/projects/:project_id/docs/:doc_id",
             delete(project::doc::delete),
         )
+        .route(
+            "/projects/:project_id/saved-searches",
+            get(project::saved_search::list).post(project::saved_search::create),
+        )
+        .route(
+            "/projects/:project_id/saved-searches/:saved_search_id",
+            get(project::saved_search::get).delete(project::saved_search::delete),
+        )
+        .route(
+            "/projects/:project_id/saved-searches/:saved_search_id/changes",
+            get(project::saved_search::changes),
+        )
         .route(
             "/projects/:project_id/conversations",
             get(conversation::list),
@@ -114,6 +127,8 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
             get(conversation::get).delete(conversation::delete),
         )
         .route("/projects/:project_id/q", get(query::handle))
+        .route("/projects/:project_id/q/explain", get(query::explain))
+        .route("/projects/:project_id/q/stream", get(query::stream))
         .route(
             "/projects/:project_id/autocomplete",
             get(autocomplete::handle),
@@ -128,6 +143,14 @@ pub async fn start(app: Application) -> anyhow::Result<()> {
             get(studio::get).patch(studio::patch).delete(studio::delete),
         )
         .route("/projects/:project_id/studios/import", post(studio::import))
+        .route(
+            "/projects/:project_id/studios/import-replace",
+            post(studio::replace::import),
+        )
+        .route(
+            "/projects/:project_id/replace",
+            get(studio::replace::preview),
+        )
         .route(
             "/projects/:project_id/studios/:studio_id/generate",
             get(studio::generate),
@@ -45,6 +45,10 @@ pub(super) async fn handle(
     api_params.page = 0;
 
     api_params.project_id = project_id;
+    api_params.ranking_profile = app
+        .config
+        .ranking_profile(api_params.ranking.as_deref())
+        .map_err(Error::user)?;
 
     let mut partial_lang = None;
     let mut has_target = false;
@@ -128,7 +132,7 @@ pub(super) async fn handle(
         .restrict_repo_queries(queries.clone(), &app)
         .await?;
 
-    let restricted_queries = api_params.restrict_queries(queries.clone(), &app).await?;
+    let restricted_queries = api_params.restrict_queries(queries.clone(), &app.sql).await?;
 
     let mut engines = vec![];
     if ac_params.content {
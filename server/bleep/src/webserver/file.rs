@@ -1,16 +1,22 @@
 use std::{path::PathBuf, sync::Arc};
 
 use anyhow::Context;
-use axum::{extract::Query, Extension, Json};
+use axum::{
+    extract::{Query, State},
+    Extension, Json,
+};
 use tracing::warn;
 
 use crate::{
+    cache::BlameCache,
+    commits::{self, BlameLine},
     indexes::reader::OpenReader,
     query::{
         execute::{ApiQuery, DirectoryData, ExecuteQuery, QueryResult},
         parser,
     },
-    repo::RepoRef,
+    repo::{iterator::BranchFilter, RepoRef},
+    Application,
 };
 
 use super::prelude::*;
@@ -57,6 +63,99 @@ pub(super) async fn handle<'a>(
     }))
 }
 
+#[derive(serde::Serialize)]
+pub(super) struct BlameResponse {
+    /// The commit that the requested branch points to
+    commit_id: String,
+    /// 1-indexed line number of the first entry in `lines`
+    line_start: usize,
+    lines: Vec<BlameLine>,
+}
+
+impl super::ApiResponse for BlameResponse {}
+
+/// Return the commit that last changed each line of a file, on any of the indexed branches.
+pub(super) async fn blame<'a>(
+    Query(params): Query<FileParams>,
+    State(app): State<Application>,
+) -> Result<Json<super::Response<'a>>, Error> {
+    let (disk_path, branch_filter) = app
+        .repo_pool
+        .read_async(&params.repo_ref, |_, repo| {
+            (repo.disk_path.clone(), repo.branch_filter.clone())
+        })
+        .await
+        .ok_or_else(|| Error::user("repo not found").with_status(StatusCode::NOT_FOUND))?;
+
+    let path = params
+        .path
+        .to_str()
+        .context("invalid file path")?
+        .to_owned();
+
+    let repo_ref = params.repo_ref.clone();
+    let branch = params.branch.clone();
+    let commit_id = tokio::task::spawn_blocking(move || {
+        let branch_filter: BranchFilter =
+            branch_filter.as_ref().map(Into::into).unwrap_or_default();
+        commits::branch_commit(&repo_ref, &disk_path, &branch_filter, branch.as_deref())
+            .map(|id| id.map(|id| (id, disk_path)))
+    })
+    .await
+    .map_err(Error::internal)?
+    .map_err(Error::internal)?;
+
+    let Some((commit_id, disk_path)) = commit_id else {
+        return Err(Error::user("branch not indexed").with_status(StatusCode::NOT_FOUND));
+    };
+
+    let cache = BlameCache::new(app.sql.clone());
+    let cached = cache
+        .get(&params.repo_ref, &commit_id.to_string(), &path)
+        .await
+        .map_err(Error::internal)?;
+
+    let lines = match cached {
+        Some(lines) => lines,
+        None => {
+            let blame_path = path.clone();
+            let lines = tokio::task::spawn_blocking(move || {
+                commits::blame(&disk_path, commit_id, &blame_path)
+            })
+            .await
+            .map_err(Error::internal)?
+            .map_err(Error::internal)?
+            .ok_or_else(|| Error::user("file not found").with_status(StatusCode::NOT_FOUND))?;
+
+            if let Err(err) = cache
+                .insert(&params.repo_ref, &commit_id.to_string(), &path, &lines)
+                .await
+            {
+                warn!(?err, "failed to cache blame");
+            }
+
+            lines
+        }
+    };
+
+    let line_start = match params.line_start {
+        None => 1,
+        Some(line_start) if line_start >= 1 => line_start as usize,
+        Some(_) => return Err(Error::user("line numbers are 1-indexed!")),
+    };
+    let line_end = params.line_end.unwrap_or(lines.len()).min(lines.len());
+
+    Ok(json(BlameResponse {
+        commit_id: commit_id.to_string(),
+        line_start,
+        lines: lines
+            .into_iter()
+            .take(line_end)
+            .skip(line_start - 1)
+            .collect(),
+    }))
+}
+
 fn split_by_lines<'a>(
     text: &'a str,
     indices: &[u32],
@@ -92,6 +191,7 @@ pub(super) struct FolderParams {
 pub(super) async fn folder(
     Query(params): Query<FolderParams>,
     Extension(indexes): Extension<Arc<Indexes>>,
+    State(app): State<Application>,
 ) -> Result<Json<DirectoryData>, Error> {
     let reader = OpenReader;
 
@@ -119,6 +219,8 @@ pub(super) async fn folder(
         calculate_totals: false,
         context_before: 0,
         context_after: 0,
+        ranking: None,
+        ranking_profile: app.config.ranking_profile(None).map_err(Error::internal)?,
     };
 
     let mut results = reader
@@ -8,6 +8,7 @@ use super::{middleware::User, repos::Repo, Error};
 
 pub mod doc;
 pub mod repo;
+pub mod saved_search;
 
 fn default_name() -> String {
     "New Project".into()
@@ -0,0 +1,250 @@
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+use chrono::NaiveDateTime;
+
+use crate::{
+    query::parser,
+    saved_search::{self, Match},
+    webserver::{self, middleware::User, Error},
+    Application,
+};
+
+#[derive(serde::Serialize)]
+pub struct ListItem {
+    id: i64,
+    name: String,
+    query: String,
+    webhook_url: Option<String>,
+    created_at: NaiveDateTime,
+    last_run_at: Option<NaiveDateTime>,
+}
+
+pub async fn list(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(project_id): Path<i64>,
+) -> webserver::Result<Json<Vec<ListItem>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(webserver::no_user_id)?
+        .to_string();
+
+    let searches = sqlx::query_as! {
+        ListItem,
+        "SELECT s.id, s.name, s.query, s.webhook_url, s.created_at, s.last_run_at \
+         FROM saved_searches s \
+         JOIN projects p ON p.id = s.project_id AND p.user_id = ? \
+         WHERE s.project_id = ?",
+        user_id,
+        project_id,
+    }
+    .fetch_all(&*app.sql)
+    .await?;
+
+    Ok(Json(searches))
+}
+
+#[derive(serde::Deserialize)]
+pub struct Create {
+    name: String,
+    query: String,
+    webhook_url: Option<String>,
+}
+
+/// Save a search, and run it once to record the matches later runs are compared against.
+pub async fn create(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(project_id): Path<i64>,
+    Json(params): Json<Create>,
+) -> webserver::Result<String> {
+    let user_id = user
+        .username()
+        .ok_or_else(webserver::no_user_id)?
+        .to_string();
+
+    parser::parse(&params.query).map_err(Error::user)?;
+
+    if let Some(url) = &params.webhook_url {
+        url::Url::parse(url).map_err(|e| Error::user(format!("invalid webhook url: {e}")))?;
+    }
+
+    sqlx::query! {
+        "SELECT id FROM projects WHERE id = ? AND user_id = ?",
+        project_id,
+        user_id,
+    }
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::not_found("project not found"))?;
+
+    let last_matches = saved_search::run(&app, project_id, &params.query)
+        .await
+        .and_then(|run| run.baseline())
+        .map_err(Error::internal)?;
+
+    let id = sqlx::query! {
+        "INSERT INTO saved_searches (project_id, name, query, webhook_url) \
+         VALUES (?, ?, ?, ?) RETURNING id",
+        project_id,
+        params.name,
+        params.query,
+        params.webhook_url,
+    }
+    .fetch_one(&*app.sql)
+    .await?
+    .id;
+
+    sqlx::query! {
+        "UPDATE saved_searches SET last_matches = ?, last_run_at = datetime('now') WHERE id = ?",
+        last_matches,
+        id,
+    }
+    .execute(&*app.sql)
+    .await?;
+
+    Ok(id.to_string())
+}
+
+#[derive(serde::Serialize)]
+pub struct Get {
+    id: i64,
+    name: String,
+    query: String,
+    webhook_url: Option<String>,
+    created_at: NaiveDateTime,
+    last_run_at: Option<NaiveDateTime>,
+    /// Matches found by the last run
+    matches: Vec<Match>,
+}
+
+pub async fn get(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((project_id, saved_search_id)): Path<(i64, i64)>,
+) -> webserver::Result<Json<Get>> {
+    let user_id = user
+        .username()
+        .ok_or_else(webserver::no_user_id)?
+        .to_string();
+
+    let row = sqlx::query! {
+        "SELECT s.id, s.name, s.query, s.webhook_url, s.created_at, s.last_run_at, s.last_matches \
+         FROM saved_searches s \
+         JOIN projects p ON p.id = s.project_id AND p.user_id = ? \
+         WHERE s.project_id = ? AND s.id = ?",
+        user_id,
+        project_id,
+        saved_search_id,
+    }
+    .fetch_optional(&*app.sql)
+    .await?
+    .ok_or_else(|| Error::not_found("saved search not found"))?;
+
+    let matches = row
+        .last_matches
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+        .map_err(Error::internal)?
+        .unwrap_or_default();
+
+    Ok(Json(Get {
+        id: row.id,
+        name: row.name,
+        query: row.query,
+        webhook_url: row.webhook_url,
+        created_at: row.created_at,
+        last_run_at: row.last_run_at,
+        matches,
+    }))
+}
+
+pub async fn delete(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((project_id, saved_search_id)): Path<(i64, i64)>,
+) -> webserver::Result<()> {
+    let user_id = user
+        .username()
+        .ok_or_else(webserver::no_user_id)?
+        .to_string();
+
+    sqlx::query! {
+        "DELETE FROM saved_searches \
+         WHERE id = ? AND project_id = ? \
+         AND project_id IN (SELECT id FROM projects WHERE user_id = ?) \
+         RETURNING id",
+        saved_search_id,
+        project_id,
+        user_id,
+    }
+    .fetch_optional(&*app.sql)
+    .await?
+    .map(|_| ())
+    .ok_or_else(|| Error::not_found("saved search not found"))
+}
+
+const fn default_limit() -> i64 {
+    20
+}
+
+#[derive(serde::Deserialize)]
+pub struct ChangesParams {
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct Changes {
+    id: i64,
+    /// The repository whose sync triggered the run
+    repo_ref: String,
+    created_at: NaiveDateTime,
+    added: Vec<Match>,
+    removed: Vec<Match>,
+}
+
+/// List the changes found by the latest runs of a saved search, newest first.
+pub async fn changes(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path((project_id, saved_search_id)): Path<(i64, i64)>,
+    Query(params): Query<ChangesParams>,
+) -> webserver::Result<Json<Vec<Changes>>> {
+    let user_id = user
+        .username()
+        .ok_or_else(webserver::no_user_id)?
+        .to_string();
+
+    let rows = sqlx::query! {
+        "SELECT c.id, c.repo_ref, c.created_at, c.added, c.removed \
+         FROM saved_search_changes c \
+         JOIN saved_searches s ON s.id = c.saved_search_id \
+         JOIN projects p ON p.id = s.project_id AND p.user_id = ? \
+         WHERE s.project_id = ? AND s.id = ? \
+         ORDER BY c.id DESC \
+         LIMIT ?",
+        user_id,
+        project_id,
+        saved_search_id,
+        params.limit,
+    }
+    .fetch_all(&*app.sql)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(Changes {
+                id: row.id,
+                repo_ref: row.repo_ref,
+                created_at: row.created_at,
+                added: serde_json::from_str(&row.added).map_err(Error::internal)?,
+                removed: serde_json::from_str(&row.removed).map_err(Error::internal)?,
+            })
+        })
+        .collect::<webserver::Result<_>>()
+        .map(Json)
+}
@@ -1,7 +1,15 @@
-use axum::extract::Path;
+use axum::{
+    extract::Path,
+    response::sse::{self, Sse},
+};
+use futures::{Stream, StreamExt};
 
 use super::prelude::*;
-use crate::{db::QueryLog, query::execute::ApiQuery, Application};
+use crate::{
+    db::QueryLog,
+    query::{execute::ApiQuery, parser},
+    Application,
+};
 
 pub(super) async fn handle(
     Path(project_id): Path<i64>,
@@ -11,6 +19,10 @@ pub(super) async fn handle(
     QueryLog::new(&app.sql).insert(&api_params.q).await?;
 
     api_params.project_id = project_id;
+    api_params.ranking_profile = app
+        .config
+        .ranking_profile(api_params.ranking.as_deref())
+        .map_err(Error::user)?;
 
     Arc::new(api_params)
         .query(&app)
@@ -18,3 +30,51 @@ pub(super) async fn handle(
         .map(json)
         .map_err(Error::from)
 }
+
+/// Run a query like `handle` does, streaming the results of each repo as server-sent events.
+pub(super) async fn stream(
+    Path(project_id): Path<i64>,
+    Query(mut api_params): Query<ApiQuery>,
+    Extension(app): Extension<Application>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, axum::Error>>>> {
+    // Report syntax errors with a status code, rather than in the event stream.
+    parser::parse(&api_params.q).map_err(Error::user)?;
+
+    QueryLog::new(&app.sql).insert(&api_params.q).await?;
+
+    api_params.project_id = project_id;
+    api_params.ranking_profile = app
+        .config
+        .ranking_profile(api_params.ranking.as_deref())
+        .map_err(Error::user)?;
+
+    let stream = Arc::new(api_params)
+        .query_stream(app)
+        .map(|event| match event {
+            Ok(event) => sse::Event::default().json_data(event),
+            Err(err) => Ok(sse::Event::default()
+                .event("error")
+                .data(format!("{err:#}"))),
+        });
+
+    Ok(Sse::new(stream))
+}
+
+/// Run a query like `handle` does, and describe each stage of its execution.
+pub(super) async fn explain(
+    Path(project_id): Path<i64>,
+    Query(mut api_params): Query<ApiQuery>,
+    Extension(app): Extension<Application>,
+) -> impl IntoResponse {
+    api_params.project_id = project_id;
+    api_params.ranking_profile = app
+        .config
+        .ranking_profile(api_params.ranking.as_deref())
+        .map_err(Error::user)?;
+
+    Arc::new(api_params)
+        .explain(&app)
+        .await
+        .map(json)
+        .map_err(Error::from)
+}
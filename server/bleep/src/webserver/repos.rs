@@ -1,18 +1,28 @@
-use std::{collections::HashSet, hash::Hash, time::Duration};
+use std::{
+    collections::{BTreeMap, HashSet},
+    hash::Hash,
+    time::Duration,
+};
 
 use crate::{
     background::{QueuedRepoStatus, SyncConfig},
-    repo::{Backend, BranchFilterConfig, FileFilterConfig, RepoRef, Repository, SyncStatus},
+    fsck,
+    indexes::analytics::{self, FileSize, SkippedFileRow, SyncStats},
+    repo::{
+        Backend, BranchFilterConfig, FileFilterConfig, GitCredentials, GitRemote, RepoRef,
+        RepoRemote, Repository, SyncStatus,
+    },
     state::RepositoryPool,
     Application,
 };
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::{sse, IntoResponse, Sse},
     Extension, Json,
 };
 use chrono::{DateTime, NaiveDateTime, Utc};
+use secrecy::SecretString;
 use serde::{Deserialize, Serialize};
 
 use super::prelude::*;
@@ -173,6 +183,48 @@ impl Repo {
             branches: vec![],
         }
     }
+
+    pub(crate) fn from_gitlab(
+        local_duplicates: Vec<RepoRef>,
+        host: &str,
+        origin: &crate::remotes::gitlab::Project,
+    ) -> Option<Self> {
+        let name = origin.path_with_namespace.clone();
+        Some(Repo {
+            provider: Backend::Gitlab,
+            repo_ref: RepoRef::new(Backend::Gitlab, &format!("{host}/{name}")).ok()?,
+            sync_status: SyncStatus::Uninitialized,
+            local_duplicates,
+            name,
+            last_update: origin.last_activity_at,
+            last_index: None,
+            most_common_lang: None,
+            branch_filter: BranchFilterConfig::Select(vec![]),
+            file_filter: Default::default(),
+            branches: vec![],
+        })
+    }
+
+    pub(crate) fn from_gitea(
+        local_duplicates: Vec<RepoRef>,
+        host: &str,
+        origin: &crate::remotes::gitea::Repo,
+    ) -> Option<Self> {
+        let name = origin.full_name.clone();
+        Some(Repo {
+            provider: Backend::Gitea,
+            repo_ref: RepoRef::new(Backend::Gitea, &format!("{host}/{name}")).ok()?,
+            sync_status: SyncStatus::Uninitialized,
+            local_duplicates,
+            name,
+            last_update: origin.updated_at,
+            last_index: None,
+            most_common_lang: None,
+            branch_filter: BranchFilterConfig::Select(vec![]),
+            file_filter: Default::default(),
+            branches: vec![],
+        })
+    }
 }
 
 impl Hash for Repo {
@@ -198,6 +250,20 @@ pub(crate) enum ReposResponse {
     SyncQueue(Vec<QueuedRepoStatus>),
     SyncQueued,
     Deleted,
+    Fsck(Vec<fsck::Report>),
+    Stats(RepoStats),
+}
+
+#[derive(Serialize)]
+pub(crate) struct RepoStats {
+    /// Number of indexed files per language
+    languages: BTreeMap<String, usize>,
+    /// The largest indexed files, largest first
+    largest_files: Vec<FileSize>,
+    /// Files whose contents aren't indexed, and why, largest first
+    skipped_files: Vec<SkippedFileRow>,
+    /// Stats of the latest syncs, newest first
+    syncs: Vec<SyncStats>,
 }
 
 impl super::ApiResponse for ReposResponse {}
@@ -213,7 +279,10 @@ pub(super) fn router() -> Router {
         .route("/queue", get(queue))
         .route("/status", get(index_status))
         .route("/indexed", indexed)
+        .route("/remote", put(add_remote))
         .route("/sync", get(sync).delete(delete_sync))
+        .route("/fsck", get(check).post(repair))
+        .route("/:id/stats", get(stats))
 }
 
 /// Get a stream of status notifications about the indexing of each repository
@@ -295,6 +364,32 @@ pub(super) async fn get_by_id(
     }
 }
 
+/// Get index statistics of a repository, whose ref is URL-encoded in the path
+pub(super) async fn stats(
+    Path(repo): Path<RepoRef>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    if app.repo_pool.read_async(&repo, |_, _| ()).await.is_none() {
+        return Err(Error::new(ErrorKind::NotFound, "Can't find repository"));
+    }
+
+    let (languages, largest_files) = {
+        let (indexes, repo) = (app.indexes.clone(), repo.clone());
+        tokio::task::spawn_blocking(move || analytics::file_stats(&indexes.file, &repo))
+            .await
+            .map_err(Error::internal)??
+    };
+    let skipped_files = analytics::skipped_files(&app.sql, &repo).await?;
+    let syncs = analytics::sync_history(&app.sql, &repo).await?;
+
+    Ok(json(ReposResponse::Stats(RepoStats {
+        languages,
+        largest_files,
+        skipped_files,
+        syncs,
+    })))
+}
+
 /// Delete a repository from the disk and any indexes
 //
 pub(super) async fn delete_by_id(
@@ -335,48 +430,176 @@ pub(super) async fn delete_sync(
     Ok(json(ReposResponse::SyncQueued))
 }
 
+/// Check that the indexes, caches and embeddings of all repositories, or the one in `?repo=...`,
+/// agree with each other
+pub(super) async fn check(
+    Query(IndexedParams { repo }): Query<IndexedParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    let reports = fsck::run(&app, repo, false).await?;
+    Ok(json(ReposResponse::Fsck(reports)))
+}
+
+/// Repair the inconsistencies found by `check`, and sync the repositories which have files to
+/// reindex
+pub(super) async fn repair(
+    Query(IndexedParams { repo }): Query<IndexedParams>,
+    State(app): State<Application>,
+) -> Result<impl IntoResponse> {
+    let reports = fsck::run(&app, repo, true).await?;
+
+    let reindex = reports
+        .iter()
+        .filter(|r| r.repaired && r.needs_reindex())
+        .map(|r| r.reporef.clone())
+        .collect::<Vec<_>>();
+    app.write_index().enqueue_all(reindex).await;
+
+    Ok(json(ReposResponse::Fsck(reports)))
+}
+
 /// List all repositories that are either indexed, or available for indexing
 //
 pub(super) async fn available(State(app): State<Application>) -> impl IntoResponse {
-    let unknown_github = app
+    let mut unknown = app
         .credentials
         .github()
         .map(|gh| gh.repositories)
         .unwrap_or_default()
         .iter()
         .map(|repo| {
-            let mut local_duplicates = vec![];
-            app.repo_pool.scan(|k, v| {
-                // either `ssh_url` or `clone_url` should match what we generate.
-                //
-                // also note that this is quite possibly not the
-                // most efficient way of doing this, but the
-                // number of repos should be small, so even n^2
-                // should be fast.
-                //
-                // most of the time is spent in the network.
-                if [
-                    repo.ssh_url.as_deref().unwrap_or_default().to_lowercase(),
+            let local_duplicates = local_duplicates(
+                &app.repo_pool,
+                &[
+                    repo.ssh_url.as_deref().unwrap_or_default(),
                     repo.clone_url
                         .as_ref()
                         .map(|url| url.as_str())
-                        .unwrap_or_default()
-                        .to_lowercase(),
-                ]
-                .contains(&v.remote.to_string().to_lowercase())
-                {
-                    local_duplicates.push(k.clone())
-                }
-            });
+                        .unwrap_or_default(),
+                ],
+            );
 
             Repo::from_github(local_duplicates, repo)
         })
         .collect::<HashSet<_>>();
 
-    let repos = list_unique_repos(app.repo_pool.clone(), unknown_github).await;
+    if let Some(gl) = app.credentials.gitlab() {
+        let host = gl.auth.host().unwrap_or_default();
+        unknown.extend(gl.repositories.iter().filter_map(|repo| {
+            let local_duplicates = local_duplicates(
+                &app.repo_pool,
+                &[&repo.ssh_url_to_repo, &repo.http_url_to_repo],
+            );
+
+            Repo::from_gitlab(local_duplicates, &host, repo)
+        }));
+    }
+
+    if let Some(gt) = app.credentials.gitea() {
+        let host = gt.auth.host().unwrap_or_default();
+        unknown.extend(gt.repositories.iter().filter_map(|repo| {
+            let local_duplicates =
+                local_duplicates(&app.repo_pool, &[&repo.ssh_url, &repo.clone_url]);
+
+            Repo::from_gitea(local_duplicates, &host, repo)
+        }));
+    }
+
+    let repos = list_unique_repos(app.repo_pool.clone(), unknown).await;
     (StatusCode::OK, Json(ReposResponse::List(repos)))
 }
 
+/// Find local repositories which have any of the `urls` as their remote.
+fn local_duplicates(repo_pool: &RepositoryPool, urls: &[&str]) -> Vec<RepoRef> {
+    let urls = urls
+        .iter()
+        .map(|url| url.to_lowercase())
+        .collect::<Vec<_>>();
+
+    let mut local_duplicates = vec![];
+    repo_pool.scan(|k, v| {
+        // either the ssh or the https url should match what we generate.
+        //
+        // also note that this is quite possibly not the
+        // most efficient way of doing this, but the
+        // number of repos should be small, so even n^2
+        // should be fast.
+        //
+        // most of the time is spent in the network.
+        if urls.contains(&v.remote.to_string().to_lowercase()) {
+            local_duplicates.push(k.clone())
+        }
+    });
+
+    local_duplicates
+}
+
+#[derive(Deserialize)]
+pub(super) struct AddRemote {
+    /// Clone URL of the repository
+    url: String,
+    username: Option<String>,
+    password: Option<SecretString>,
+    #[serde(default)]
+    shallow: bool,
+}
+
+/// Add a repository on a plain git server by its clone URL, and start syncing it.
+///
+/// Credentials can be given in the URL or separately, and replace the ones stored for an
+/// existing repository.
+//
+pub(super) async fn add_remote(
+    State(app): State<Application>,
+    Json(AddRemote {
+        url,
+        username,
+        password,
+        shallow,
+    }): Json<AddRemote>,
+) -> Result<impl IntoResponse> {
+    let mut remote = GitRemote::from_url(&url).map_err(Error::user)?;
+
+    match (username, password) {
+        (Some(username), password) => {
+            remote.credentials = Some(GitCredentials { username, password })
+        }
+        (None, Some(password)) => match remote.credentials {
+            Some(ref mut creds) => creds.password = Some(password),
+            None => return Err(Error::user("a password requires a username")),
+        },
+        (None, None) => {}
+    }
+
+    let reporef = RepoRef::new(Backend::Git, &format!("{}/{}", remote.host, remote.address))
+        .map_err(Error::user)?;
+
+    let remote = RepoRemote::Git(remote);
+    let disk_path = app
+        .config
+        .source
+        .repo_path_for_name(&reporef.to_string().replace('/', "_"));
+
+    app.repo_pool
+        .entry_async(reporef.clone())
+        .await
+        .and_modify(|repo| repo.remote = remote.clone())
+        .or_insert_with(|| Repository::remote_from(disk_path, remote, shallow));
+
+    app.write_index()
+        .enqueue(SyncConfig::new(app.clone(), reporef.clone()).shallow(shallow))
+        .await;
+
+    match app
+        .repo_pool
+        .read_async(&reporef, |k, v| ReposResponse::Item(Repo::from((k, v))))
+        .await
+    {
+        Some(result) => Ok(json(result)),
+        None => Err(Error::internal("repository was removed while being added")),
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub(super) struct SetIndexed {
     indexed: Vec<RepoRef>,
@@ -472,6 +695,7 @@ mod test {
                     disk_path: "/repo".into(),
                     remote: Git(GitRemote {
                         protocol: GitProtocol::Https,
+                        credentials: None,
                         host: "github.com".into(),
                         address: "test/test".into(),
                     }),
@@ -484,6 +708,7 @@ mod test {
                     pub_sync_status: Default::default(),
                     locked: Default::default(),
                     shallow: Default::default(),
+                    indexed_tip: Default::default(),
                 },
             )
             .unwrap();
@@ -494,6 +719,7 @@ mod test {
                     disk_path: "/repo2".into(),
                     remote: Git(GitRemote {
                         protocol: GitProtocol::Https,
+                        credentials: None,
                         host: "github.com".into(),
                         address: "test/test2".into(),
                     }),
@@ -506,6 +732,7 @@ mod test {
                     pub_sync_status: Default::default(),
                     locked: Default::default(),
                     shallow: Default::default(),
+                    indexed_tip: Default::default(),
                 },
             )
             .unwrap();
@@ -518,6 +745,7 @@ mod test {
                     disk_path: "/unused".into(),
                     remote: Git(GitRemote {
                         protocol: GitProtocol::Https,
+                        credentials: None,
                         host: "github.com".into(),
                         address: "test/test".into(),
                     }),
@@ -530,6 +758,7 @@ mod test {
                     pub_sync_status: Default::default(),
                     locked: Default::default(),
                     shallow: Default::default(),
+                    indexed_tip: Default::default(),
                 },
             )
                 .into(),
@@ -541,6 +770,7 @@ mod test {
                 disk_path: "/unused".into(),
                 remote: Git(GitRemote {
                     protocol: GitProtocol::Https,
+                    credentials: None,
                     host: "github.com".into(),
                     address: "test/test2".into(),
                 }),
@@ -553,6 +783,7 @@ mod test {
                 pub_sync_status: Default::default(),
                 locked: Default::default(),
                 shallow: Default::default(),
+                indexed_tip: Default::default(),
             },
         )
             .into();
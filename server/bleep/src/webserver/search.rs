@@ -12,20 +12,45 @@ use crate::{
 use axum::extract::Path;
 use tracing::error;
 
+/// How `/search/code` retrieves code.
+#[derive(Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum CodeSearchMode {
+    /// Qdrant only
+    #[default]
+    Semantic,
+    /// The content index and Qdrant, with their results fused
+    Hybrid,
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct CodeSearchParams {
+    #[serde(default)]
+    mode: CodeSearchMode,
+}
+
 pub(super) async fn semantic_code(
     Query(args): Query<ApiQuery>,
+    Query(CodeSearchParams { mode }): Query<CodeSearchParams>,
     Extension(semantic): Extension<Semantic>,
+    Extension(indexes): Extension<Arc<Indexes>>,
 ) -> impl IntoResponse {
-    match parser::parse_nl(&args.q.clone()) {
-        Ok(q) => semantic::execute::execute(semantic, q, args)
-            .await
-            .map(json)
-            .map_err(Error::from),
+    let q = match parser::parse_nl(&args.q.clone()) {
+        Ok(q) => q,
         Err(err) => {
             error!(?err, "Couldn't parse query");
-            Err(Error::new(ErrorKind::UpstreamService, "error"))
+            return Err(Error::new(ErrorKind::UpstreamService, "error"));
+        }
+    };
+
+    match mode {
+        CodeSearchMode::Semantic => semantic::execute::execute(semantic, q, args).await,
+        CodeSearchMode::Hybrid => {
+            semantic::execute::execute_hybrid(&indexes, semantic, q, args).await
         }
     }
+    .map(json)
+    .map_err(Error::from)
 }
 
 #[axum::debug_handler]
@@ -86,5 +111,6 @@ pub(super) async fn fuzzy_path(
         data,
         metadata: PagingMetadata::new(args.page, args.page_size, None),
         stats: ResultStats::default(),
+        collectors: vec![],
     }))
 }
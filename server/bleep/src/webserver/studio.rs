@@ -30,6 +30,7 @@ use crate::{
 };
 
 mod diff;
+pub mod replace;
 
 const LLM_GATEWAY_MODEL: &str = "gpt-4-turbo";
 
@@ -881,6 +882,7 @@ async fn generate_llm_context(
 mod structured_diff {
     use std::fmt;
 
+    use super::DiffChunk;
     use crate::repo::RepoRef;
 
     #[derive(serde::Serialize, serde::Deserialize)]
@@ -923,6 +925,31 @@ mod structured_diff {
         pub raw_patch: String,
     }
 
+    impl Chunk {
+        pub fn new(repo: RepoRef, file: String, lang: Option<String>, chunk: DiffChunk) -> Self {
+            Self {
+                raw_patch: chunk.to_string(),
+
+                lang,
+                repo,
+                branch: None,
+                file,
+                hunks: chunk
+                    .hunks
+                    .into_iter()
+                    .map(|hunk| Hunk {
+                        line_start: hunk.src_line,
+                        patch: hunk
+                            .lines
+                            .into_iter()
+                            .map(|line| line.to_string())
+                            .collect::<String>(),
+                    })
+                    .collect(),
+            }
+        }
+    }
+
     #[derive(serde::Serialize, serde::Deserialize)]
     pub struct Hunk {
         pub line_start: usize,
@@ -1088,26 +1115,12 @@ pub async fn diff(
             detected_lang
         };
 
-        out.chunks.push(structured_diff::Chunk {
-            raw_patch: chunk.to_string(),
-
-            lang: lang.clone(),
-            repo: repo.clone(),
-            branch: None,
-            file: path.to_owned(),
-            hunks: chunk
-                .hunks
-                .into_iter()
-                .map(|hunk| structured_diff::Hunk {
-                    line_start: hunk.src_line,
-                    patch: hunk
-                        .lines
-                        .into_iter()
-                        .map(|line| line.to_string())
-                        .collect::<String>(),
-                })
-                .collect(),
-        });
+        out.chunks.push(structured_diff::Chunk::new(
+            repo,
+            path.to_owned(),
+            lang,
+            chunk,
+        ));
     }
 
     Ok(Json(out))
@@ -59,6 +59,38 @@ pub struct DiffChunk {
 }
 
 impl DiffChunk {
+    /// Create a chunk which changes the contents of `path` from `old` to `new`.
+    pub fn from_change(path: &str, old: &str, new: &str) -> Self {
+        let patch = diffy::create_patch(old, new);
+        let line = |l: &str| l.strip_suffix('\n').unwrap_or(l).to_owned();
+
+        let hunks = patch
+            .hunks()
+            .iter()
+            .map(|hunk| DiffHunk {
+                src_line: hunk.old_range().start(),
+                src_count: hunk.old_range().len(),
+                dst_line: hunk.new_range().start(),
+                dst_count: hunk.new_range().len(),
+                lines: hunk
+                    .lines()
+                    .iter()
+                    .map(|l| match l {
+                        diffy::Line::Context(l) => Line::Context(line(l)),
+                        diffy::Line::Delete(l) => Line::Del(line(l)),
+                        diffy::Line::Insert(l) => Line::Add(line(l)),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            src: Some(path.to_owned()),
+            dst: Some(path.to_owned()),
+            hunks,
+        }
+    }
+
     pub fn fixup_hunks(&mut self) {
         self.hunks.retain_mut(|h| {
             if !h.fixup() {
@@ -320,6 +352,36 @@ foo bar
         assert_eq!(expected, output);
     }
 
+    #[test]
+    fn test_from_change() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nJ\n";
+
+        let expected = "\
+--- repo:file.txt
++++ repo:file.txt
+@@ -1,5 +1,5 @@
+ a
+-b
++B
+ c
+ d
+ e
+@@ -7,4 +7,4 @@
+ g
+ h
+ i
+-j
++J
+";
+
+        let chunk = DiffChunk::from_change("repo:file.txt", old, new);
+        assert_eq!(expected, chunk.to_string());
+
+        let parsed = relaxed_parse(expected).collect::<Vec<_>>();
+        assert_eq!(parsed, vec![chunk]);
+    }
+
     #[test]
     fn test_split_hunks() {
         let hunks = "@@ -1,1 +1,1 @@
@@ -0,0 +1,401 @@
+//! Search and replace across the repos of a project.
+//!
+//! The matches of a query's content target are replaced in every matching file, and the changes
+//! are returned as diffs, without touching any file on disk. A preview can be imported into a
+//! studio, where it can be reviewed and applied with `diff_apply`.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+use regex::{Regex, RegexBuilder};
+
+use super::{
+    canonicalize_context, diff::DiffChunk, latest_snapshot_id, structured_diff, studio_not_found,
+    ContextFile, Message,
+};
+use crate::{
+    query::{
+        execute::{ApiQuery, QueryResult},
+        parser::{self, Target},
+    },
+    repo::RepoRef,
+    webserver::{self, middleware::User, Error},
+    Application,
+};
+
+#[derive(serde::Deserialize)]
+pub struct Replace {
+    /// The replacement for each match, which can refer to capture groups as `$1` or `${name}`
+    pub replace: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Import {
+    /// An optional studio ID to import into.
+    pub studio_id: Option<i64>,
+}
+
+/// A replacement in a single file.
+struct FileChange {
+    repo: RepoRef,
+    path: String,
+    lang: Option<String>,
+    chunk: DiffChunk,
+}
+
+impl FileChange {
+    /// The context covering the lines this change replaces.
+    fn context(&self) -> ContextFile {
+        ContextFile {
+            path: self.path.clone(),
+            hidden: false,
+            repo: self.repo.clone(),
+            branch: None,
+            ranges: self
+                .chunk
+                .hunks
+                .iter()
+                .map(|h| h.src_line.saturating_sub(1)..h.src_line.saturating_sub(1) + h.src_count)
+                .collect(),
+        }
+    }
+}
+
+/// Preview replacing the matches of a query, in the files on the requested page of results.
+pub async fn preview(
+    app: Extension<Application>,
+    Path(project_id): Path<i64>,
+    Query(api_params): Query<ApiQuery>,
+    Query(params): Query<Replace>,
+) -> webserver::Result<Json<structured_diff::Diff>> {
+    let changes = replace(&app, project_id, api_params, &params.replace).await?;
+
+    Ok(Json(structured_diff::Diff {
+        chunks: changes
+            .into_iter()
+            .map(|c| structured_diff::Chunk::new(c.repo, c.path, c.lang, c.chunk))
+            .collect(),
+    }))
+}
+
+/// Import the preview of a replacement into a studio, returning its ID.
+///
+/// The changed lines of each file are added to the studio's context, and the diff is added to
+/// its messages. A new studio is created unless the `?studio_id=...` query param is present.
+pub async fn import(
+    app: Extension<Application>,
+    user: Extension<User>,
+    Path(project_id): Path<i64>,
+    Query(api_params): Query<ApiQuery>,
+    Query(params): Query<Replace>,
+    Query(Import { studio_id }): Query<Import>,
+) -> webserver::Result<String> {
+    let user_id = user
+        .username()
+        .ok_or_else(webserver::no_user_id)?
+        .to_string();
+
+    // The user must own the project, and an existing studio must belong to it, as the
+    // replacements are computed from the project's repos.
+    match studio_id {
+        None => {
+            sqlx::query! {
+                "SELECT id FROM projects WHERE id = ? AND user_id = ?",
+                project_id,
+                user_id,
+            }
+            .fetch_optional(&*app.sql)
+            .await?
+            .ok_or_else(|| Error::not_found("project not found"))?;
+        }
+        Some(studio_id) => {
+            sqlx::query! {
+                "SELECT s.id FROM studios s \
+                 JOIN projects p ON p.id = s.project_id \
+                 WHERE s.id = ? AND s.project_id = ? AND p.user_id = ?",
+                studio_id,
+                project_id,
+                user_id,
+            }
+            .fetch_optional(&*app.sql)
+            .await?
+            .ok_or_else(studio_not_found)?;
+        }
+    }
+
+    let query = api_params.q.clone();
+    let changes = replace(&app, project_id, api_params, &params.replace).await?;
+
+    if changes.is_empty() {
+        return Err(Error::user("query did not match anything to replace"));
+    }
+
+    let mut transaction = app.sql.begin().await?;
+
+    let (old_context, doc_context_json, mut messages) = match studio_id {
+        None => (Vec::new(), "[]".to_owned(), Vec::new()),
+        Some(studio_id) => {
+            let snapshot_id = latest_snapshot_id(studio_id, &mut transaction, &user_id).await?;
+
+            let row = sqlx::query! {
+                "SELECT context, doc_context, messages FROM studio_snapshots WHERE id = ?",
+                snapshot_id,
+            }
+            .fetch_optional(&mut transaction)
+            .await?
+            .ok_or_else(studio_not_found)?;
+
+            (
+                serde_json::from_str::<Vec<ContextFile>>(&row.context).map_err(Error::internal)?,
+                row.doc_context,
+                serde_json::from_str::<Vec<Message>>(&row.messages).map_err(Error::internal)?,
+            )
+        }
+    };
+
+    let imported_context = changes.iter().map(FileChange::context);
+    let context =
+        canonicalize_context(old_context.into_iter().chain(imported_context)).collect::<Vec<_>>();
+
+    let diff = changes
+        .iter()
+        .map(|c| c.chunk.to_string())
+        .collect::<String>();
+
+    messages.push(Message::User(format!(
+        "Replace `{query}` with `{}`",
+        params.replace
+    )));
+    messages.push(Message::Assistant(format!("```diff\n{diff}```")));
+
+    let context_json = serde_json::to_string(&context).unwrap();
+    let messages_json = serde_json::to_string(&messages).unwrap();
+
+    let studio_id = match studio_id {
+        Some(id) => id,
+        None => {
+            let name = format!("Replace {query}");
+            sqlx::query!(
+                "INSERT INTO studios(name, project_id) VALUES (?, ?) RETURNING id",
+                name,
+                project_id,
+            )
+            .fetch_one(&mut transaction)
+            .await?
+            .id
+        }
+    };
+
+    sqlx::query! {
+        "INSERT INTO studio_snapshots(studio_id, context, doc_context, messages)
+        VALUES (?, ?, ?, ?)",
+        studio_id,
+        context_json,
+        doc_context_json,
+        messages_json,
+    }
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(studio_id.to_string())
+}
+
+/// Run a query, and replace the matches of its content target in the files it finds.
+///
+/// Files are replaced in full, not just in the matching snippets. Only files which change are
+/// returned.
+async fn replace(
+    app: &Application,
+    project_id: i64,
+    mut api_params: ApiQuery,
+    replacement: &str,
+) -> webserver::Result<Vec<FileChange>> {
+    let regex = content_regex(&api_params.q)?;
+
+    api_params.project_id = project_id;
+    api_params.ranking_profile = app
+        .config
+        .ranking_profile(api_params.ranking.as_deref())
+        .map_err(Error::user)?;
+
+    let results = Arc::new(api_params).query(app).await?;
+
+    let mut changes = Vec::new();
+    for result in results.data {
+        let QueryResult::Snippets(file) = result else {
+            continue;
+        };
+
+        let repo = file
+            .repo_ref
+            .parse::<RepoRef>()
+            .context("search returned an invalid repo ref")?;
+
+        let doc = app
+            .indexes
+            .file
+            .by_path(&repo, &file.relative_path, None)
+            .await?
+            .context("path did not exist in the index")?;
+
+        changes.extend(replace_file(
+            &regex,
+            replacement,
+            repo,
+            file.relative_path,
+            doc.lang,
+            &doc.content,
+        ));
+    }
+
+    Ok(changes)
+}
+
+/// Build the regex matching the content target of a query.
+///
+/// Literals match their exact text, like they do in search results.
+fn content_regex(query: &str) -> webserver::Result<Regex> {
+    let queries = parser::parse(query).map_err(Error::user)?;
+    let (literal, case_sensitive) = queries
+        .iter()
+        .find_map(|q| match q.target.as_ref()? {
+            Target::Content(literal) => Some((literal, q.is_case_sensitive())),
+            Target::Symbol(..) | Target::Ast(..) => None,
+        })
+        .ok_or_else(|| Error::user("query must search for content to replace"))?;
+
+    RegexBuilder::new(&literal.regex_str())
+        .multi_line(true)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .map_err(Error::user)
+}
+
+/// Replace every match of `regex` in a file, returning `None` if nothing changes.
+fn replace_file(
+    regex: &Regex,
+    replacement: &str,
+    repo: RepoRef,
+    path: String,
+    lang: Option<String>,
+    content: &str,
+) -> Option<FileChange> {
+    let replaced = regex.replace_all(content, replacement);
+    if replaced == content {
+        return None;
+    }
+
+    let chunk = DiffChunk::from_change(&format!("{repo}:{path}"), content, &replaced);
+
+    Some(FileChange {
+        repo,
+        path,
+        lang,
+        chunk,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo() -> RepoRef {
+        "github.com/BloopAI/bloop".parse().unwrap()
+    }
+
+    fn replace_str(query: &str, replacement: &str, content: &str) -> Option<FileChange> {
+        let regex = content_regex(query).unwrap();
+        replace_file(
+            &regex,
+            replacement,
+            repo(),
+            "src/lib.rs".to_owned(),
+            None,
+            content,
+        )
+    }
+
+    #[test]
+    fn literals_are_replaced_exactly() {
+        let content = "foo.bar()\nfooxbar()\nfoo_bar()\nFOO.BAR()\n";
+
+        let change = replace_str("foo.bar", "baz", content).unwrap();
+        assert_eq!(
+            change.chunk.to_string(),
+            "\
+--- github.com/BloopAI/bloop:src/lib.rs
++++ github.com/BloopAI/bloop:src/lib.rs
+@@ -1,4 +1,4 @@
+-foo.bar()
++baz()
+ fooxbar()
+ foo_bar()
+-FOO.BAR()
++baz()
+"
+        );
+
+        let change = replace_str("foo.bar case:sensitive", "baz", content).unwrap();
+        assert_eq!(change.chunk.hunks[0].lines.len(), 5);
+        assert!(replace_str("foo_bar", "baz", "fooBar()\nfoo bar()\n").is_none());
+    }
+
+    #[test]
+    fn regexes_can_use_capture_groups() {
+        let content = "let a = 1;\nlet b = 2;\nconst C: u8 = 3;\n";
+
+        let change = replace_str("/let (\\w+)/", "let mut $1", content).unwrap();
+        assert_eq!(
+            change.chunk.to_string(),
+            "\
+--- github.com/BloopAI/bloop:src/lib.rs
++++ github.com/BloopAI/bloop:src/lib.rs
+@@ -1,3 +1,3 @@
+-let a = 1;
+-let b = 2;
++let mut a = 1;
++let mut b = 2;
+ const C: u8 = 3;
+"
+        );
+    }
+
+    #[test]
+    fn unchanged_files_are_skipped() {
+        assert!(replace_str("missing", "baz", "foo\n").is_none());
+        assert!(replace_str("foo", "foo", "foo\n").is_none());
+    }
+
+    #[test]
+    fn queries_need_a_content_target() {
+        assert!(content_regex("repo:bloop").is_err());
+        assert!(content_regex("symbol:foo").is_err());
+    }
+
+    #[test]
+    fn context_covers_replaced_lines() {
+        let content = (0..20)
+            .map(|i| {
+                if i == 10 {
+                    "target\n".to_owned()
+                } else {
+                    format!("line {i}\n")
+                }
+            })
+            .collect::<String>();
+
+        let change = replace_str("target", "replaced", &content).unwrap();
+        let context = change.context();
+
+        assert_eq!(context.repo, repo());
+        assert_eq!(context.path, "src/lib.rs");
+        // The changed line, with three lines of context on either side.
+        assert_eq!(context.ranges, vec![7..14]);
+    }
+}